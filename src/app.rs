@@ -2,6 +2,7 @@ use std::{
     fmt::Debug,
     fs,
     os::espidf,
+    path::Path,
     sync::mpsc::{self, Receiver, TryRecvError},
 };
 
@@ -14,12 +15,12 @@ use embedded_graphics::{
     image::Image,
     mono_font::{
         ascii::{FONT_10X20, FONT_6X10},
-        MonoFont, MonoTextStyle,
+        MonoFont, MonoTextStyle, MonoTextStyleBuilder,
     },
     pixelcolor::{BinaryColor, Rgb565},
     prelude::*,
     primitives::{
-        Line, Polyline, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, StyledDrawable,
+        Circle, Line, Polyline, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, StyledDrawable,
     },
     text::{Alignment, Text, TextStyleBuilder},
 };
@@ -27,7 +28,7 @@ use embedded_hal::digital::OutputPin;
 use embedded_iconoir::prelude::IconoirNewIcon;
 
 use embedded_plots::curve::{Curve, PlotPoint};
-use esp_idf_hal::{delay::Delay, prelude::Peripherals, task::block_on};
+use esp_idf_hal::{delay::Delay, prelude::Peripherals};
 use log::*;
 use mipidsi::{
     interface::{CommandInterface, PixelFormat, PixelInterface},
@@ -43,8 +44,31 @@ use u8g2_fonts::{
 use xpt2046::{TouchEvent, TouchKind};
 
 use crate::{
+    clock::{format_utc_offset, next_utc_offset, WallClock},
+    config_service::{validate_username, ConfigService, ConfigWrite},
+    cts::{ClockKeeper, ClockState, TimeSync},
     errors::{AppError, Result},
-    heart_rate::ble::{BleIdents, BleStuff, MonitorHandle, MonitorReply, MonitorStatus},
+    heart_rate::{
+        beats::BeatScheduler,
+        ble::{
+            forget_bond, BatteryLevel, BleHrCommand, BleIdents, BleMacLe, BleStuff, MonitorHandle,
+            MonitorInfo, MonitorOptions, MonitorReply, MonitorState, MonitorStatus,
+        },
+        discovery::MAX_BARS,
+        ecg::{EcgSweep, ERASE_AHEAD},
+        fit::FitExporter,
+        group::{MonitorRegistry, TrackedMonitor, MAX_TRACKED},
+        hrv::HrvWindow,
+        logger::SessionRecorder,
+        peripheral::{Advertised, HrPeripheral},
+        presence::PresencePayload,
+        session::{SessionRow, SESSION_DIR},
+        simulate::SimConfig,
+        source::{HeartRateSource, HrSource, ReplaySource, SimulatedSource},
+        stats::{SessionStats, GRAPH_POINTS},
+        zones::HrZone,
+    },
+    littlefs::paths::STATS_PATH,
     settings::Settings,
 };
 
@@ -60,6 +84,10 @@ pub enum AppView {
     Doodle,
     HrSelect,
     NameInput,
+    Settings,
+    Group,
+    Friends,
+    SessionSummary,
     // Gif,
     // ResetSettings,
 }
@@ -70,9 +98,41 @@ pub enum AppView {
 //     Both,
 // }
 
+/// What the name input screen is editing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NameTarget {
+    Username,
+    /// Label of a monitor on the group display.
+    GroupLabel(BleMacLe),
+}
+
 const HR_HISTORY_AMOUNT: usize = 100;
 
-const INPUT_CHARS: &[char] = &[
+/// BPM-only changes to our presence advertisement wait at least this long.
+const PRESENCE_UPDATE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// How many nearby badges the friends screen lists.
+const FRIENDS_ROWS: usize = 6;
+
+/// Session stats get saved at most this often while they're changing.
+const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Half a blink of the low strap battery glyph.
+const BATTERY_FLASH_MS: u64 = 500;
+
+/// Where `hr_canvas` goes on the badge.
+const NUMERIC_BOUND: Rectangle = Rectangle::new(Point::new(0, 260), Size::new(240, 60));
+/// The heart within `hr_canvas`, stopping short of the battery glyph.
+const HEART_BOUND: Rectangle = Rectangle::new(Point::new(6, 6), Size::new(48, 46));
+/// Steps of heart brightness, fewer means less redrawing.
+const HEART_LEVELS: u8 = 8;
+/// How bright the heart is between beats, out of 1.0.
+const HEART_REST: f32 = 0.4;
+
+/// How many monitors the picker shows at once.
+const PICKER_ROWS: usize = 4;
+
+pub const INPUT_CHARS: &[char] = &[
     ' ', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R',
     'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k',
     'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '~', '!', '#', '$',
@@ -107,8 +167,37 @@ where
     debounce_instant: Instant,
     debounce_duration: Duration,
     doodle_lines: Lines,
-
-    monitor: Option<MonitorHandle>,
+    /// Which page of the settings menu is showing.
+    settings_page: usize,
+
+    monitor: Option<Box<dyn HeartRateSource>>,
+    /// What `monitor` was started with, it needs rebuilding if these change.
+    monitor_options: MonitorOptions,
+    /// Last state reported by the monitor supervisor.
+    monitor_state: Option<MonitorState>,
+    /// Last connection attempt got as far as pairing and failed there.
+    monitor_pairing_failed: bool,
+    /// What the last BLE monitor we connected to said about itself, by MAC.
+    monitor_info: Option<([u8; 6], MonitorInfo)>,
+    /// Showing the saved monitor's info instead of the picker.
+    saved_info_open: bool,
+    /// Only while on the badge, a new file each time.
+    session_log: Option<SessionRecorder>,
+    /// Last write to the SD card didn't go through.
+    session_log_failing: bool,
+    /// Turns finished recordings into `.fit` files.
+    fit_exporter: FitExporter,
+    peripheral: HrPeripheral,
+    config: ConfigService,
+    time_sync: TimeSync,
+    clock: ClockKeeper,
+    /// What we last told NimBLE to advertise, and when.
+    advertised: Advertised,
+    advertised_at: Instant,
+    /// Last go at advertising didn't take, `advertised` is still the one before.
+    advertise_failed: bool,
+    /// Only populated while on the group display.
+    group: MonitorRegistry,
 
     ble: BleStuff<'a>,
 
@@ -118,8 +207,26 @@ where
     hr_history: Vec<PlotPoint>,
     plot_bpm_high: u8,
     plot_bpm_low: u8,
+    hrv: HrvWindow,
+    hr_zone: HrZone,
+    hr_contact_lost: bool,
+    /// The connected strap's, kept between HR packets since not all of them say.
+    monitor_battery: BatteryLevel,
+    /// Which half of the low battery blink the glyph was last drawn in.
+    battery_flash_on: bool,
+    /// Predicts beats so the heart can pulse between notifications.
+    beats: BeatScheduler,
+    /// Heart brightness last drawn, out of `HEART_LEVELS`.
+    heart_level: u8,
+    /// The trace for `BadgeLayout::Monitor`.
+    ecg: EcgSweep,
+    /// The whole session, survives view changes and reboots.
+    stats: SessionStats,
+    stats_saved_at: Instant,
+    stats_unsaved: bool,
 
     username_scratch: String,
+    name_target: NameTarget,
     settings: Settings,
 
     delay: Delay,
@@ -166,17 +273,46 @@ where
             debounce_instant: Instant::now(),
             debounce_duration: Duration::from_millis(500),
             doodle_lines: Lines::default(),
+            settings_page: 0,
             username_scratch: String::new(),
+            name_target: NameTarget::Username,
             settings: Settings::littlefs_load()?,
             // ble_handle: BleHrHandle::build()?,
             ble: BleStuff::build(),
             monitor: None,
+            monitor_options: MonitorOptions::default(),
+            monitor_state: None,
+            monitor_pairing_failed: false,
+            monitor_info: None,
+            saved_info_open: false,
+            session_log: None,
+            session_log_failing: false,
+            fit_exporter: FitExporter::spawn(Path::new(SESSION_DIR))?,
+            peripheral: HrPeripheral::build()?,
+            config: ConfigService::build()?,
+            time_sync: TimeSync::build()?,
+            clock: ClockKeeper::restore()?,
+            advertised: Advertised::default(),
+            advertised_at: Instant::now(),
+            advertise_failed: false,
+            group: MonitorRegistry::new(),
             delay,
             hr_canvas: Canvas::new(Size::new(240, 60)),
             name_canvas: Canvas::new(Size::new(240, 40)),
             hr_history: Vec::with_capacity(HR_HISTORY_AMOUNT),
             plot_bpm_high: 0,
             plot_bpm_low: 0,
+            hrv: HrvWindow::default(),
+            hr_zone: HrZone::default(),
+            hr_contact_lost: false,
+            monitor_battery: BatteryLevel::Unknown,
+            battery_flash_on: false,
+            beats: BeatScheduler::default(),
+            heart_level: HEART_LEVELS,
+            ecg: EcgSweep::new(NUMERIC_BOUND.size.width as usize),
+            stats: SessionStats::load(Path::new(STATS_PATH)),
+            stats_saved_at: Instant::now(),
+            stats_unsaved: false,
             image_index: None,
             image_count: 0,
         })
@@ -222,16 +358,7 @@ where
         // let font = FontRenderer::new::<fonts::u8g2_font_haxrcorp4089_t_cyrillic>();
 
         const NAME_BOUND: Rectangle = Rectangle::new(Point::new(0, 0), Size::new(240, 40));
-        const NUMERIC_BOUND: Rectangle = Rectangle::new(Point::new(0, 260), Size::new(240, 60));
 
-        let bpm_style = SevenSegmentStyleBuilder::new()
-            .digit_size(Size::new(10 * 3, 20 * 3)) // digits are 10x20 pixels
-            .digit_spacing(5) // 5px spacing between digits
-            .segment_width(5) // 5px wide segments
-            // .segment_color(Rgb565::RED)
-            .segment_color(BinaryColor::On)
-            .build();
-        let left_style = TextStyleBuilder::new().alignment(Alignment::Left).build();
         let center_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
 
         self.name_canvas
@@ -260,6 +387,23 @@ where
         )
         .unwrap();
 
+        if let Some(now) = WallClock::now(self.settings.utc_offset_min) {
+            // Could be hours out after a reboot, until a phone connects
+            let estimated = if self.clock.state == ClockState::Estimated {
+                "~"
+            } else {
+                ""
+            };
+            let right_style = TextStyleBuilder::new().alignment(Alignment::Right).build();
+            _ = Text::with_text_style(
+                &format!("{estimated}{}", now.hours_minutes()),
+                Point::new(238, 8),
+                MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
+                right_style,
+            )
+            .draw(&mut self.name_canvas);
+        }
+
         self.display.set_pixels(
             NAME_BOUND.top_left.x as u16,
             NAME_BOUND.top_left.y as u16,
@@ -277,25 +421,8 @@ where
             // I don't like this positioning of this var but it works for now
 
             if self.monitor.is_some() {
-                let heart_icon =
-                    embedded_iconoir::icons::size48px::health::Heart::new(BinaryColor::On);
-                // Text::with_text_style("Badge!", Point::new(240 / 2, 20), title_style, center_style)
-                // .draw(&mut self.display)?;
-                // _ = heart_icon.draw(&mut self.display.color_converted());
-                let image = Image::new(&heart_icon, Point::new(6, 6));
-                _ = image.draw(&mut self.hr_canvas);
-
-                self.display.set_pixels(
-                    NUMERIC_BOUND.top_left.x as u16,
-                    NUMERIC_BOUND.top_left.y as u16,
-                    NUMERIC_BOUND.bottom_right().unwrap().x as u16,
-                    NUMERIC_BOUND.bottom_right().unwrap().y as u16,
-                    self.hr_canvas.pixels.iter().map(|p| match p {
-                        Some(BinaryColor::On) => Rgb565::RED,
-                        Some(BinaryColor::Off) => Rgb565::BLACK,
-                        None => Rgb565::BLACK,
-                    }),
-                )?;
+                let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+                self.paint_hr_readout(last_bpm)?;
             }
 
             let mut index = {
@@ -349,12 +476,123 @@ where
             //     .into_styled(PrimitiveStyle::with_fill(Rgb565::RED))
             //     .draw(&mut self.display)?;
         }
+        // Lands on each predicted beat, notifications only come once a second
+        let layout = self.settings.badge_layout;
+        if self.monitor.is_some() && layout == BadgeLayout::Classic {
+            let now = std::time::Duration::from_millis(Instant::now().as_millis());
+            let brightness = self
+                .beats
+                .pulse(now)
+                .map_or(1.0, |pulse| HEART_REST + (1.0 - HEART_REST) * pulse);
+            let level = (brightness * HEART_LEVELS as f32).round() as u8;
+            if level != self.heart_level {
+                self.heart_level = level;
+                self.paint_heart()?;
+            }
+        }
+        if self.monitor.is_some() && layout == BadgeLayout::Monitor {
+            let now = std::time::Duration::from_millis(Instant::now().as_millis());
+            let from = self.ecg.cursor();
+            let moved = self.ecg.advance(now, &self.beats);
+            if moved > 0 {
+                let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+                self.draw_hr_readout(last_bpm);
+                // Only the new columns and the gap being erased ahead of them,
+                // everything if it's wrapped around
+                let start = from.saturating_sub(1);
+                let end = from + moved + ERASE_AHEAD;
+                let area = if end <= NUMERIC_BOUND.size.width as usize {
+                    Rectangle::new(
+                        Point::new(start as i32, 0),
+                        Size::new((end - start) as u32, NUMERIC_BOUND.size.height),
+                    )
+                } else {
+                    Rectangle::new(Point::zero(), NUMERIC_BOUND.size)
+                };
+                self.push_hr_canvas(area, self.readout_color())?;
+            }
+        }
+
+        // Nothing else would repaint the readout often enough to blink it
+        if self.monitor.is_some() && self.monitor_battery.is_low(self.settings.hr.low_battery) {
+            let flash_on = (Instant::now().as_millis() / BATTERY_FLASH_MS) % 2 == 0;
+            if flash_on != self.battery_flash_on {
+                self.battery_flash_on = flash_on;
+                let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+                self.paint_hr_readout(last_bpm)?;
+            }
+        }
+
+        let slideshow_enabled = self.settings.slideshow_length_sec != SlideshowLength::Off;
+        let image_count = self.image_count;
+        match self.touch() {
+            Some(TouchEvent {
+                point: new_point,
+                kind: TouchKind::Start | TouchKind::End,
+            }) => {
+                self.debounce_instant = Instant::now();
+            }
+            Some(TouchEvent {
+                point: new_point,
+                kind: TouchKind::Move,
+            }) => {
+                self.repaint_full()?;
+                self.debounce_instant = Instant::now();
+            }
+            None if slideshow_enabled && image_count > 1 => {
+                if self.debounce_instant.elapsed() > self.settings.slideshow_length_sec.into() {
+                    info!("does it ever happen?");
+                    self.repaint_full()?;
+                    self.debounce_instant = Instant::now();
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+    fn update_battery(&mut self, level: BatteryLevel) {
+        let threshold = self.settings.hr.low_battery;
+        if level.is_low(threshold) && !self.monitor_battery.is_low(threshold) {
+            warn!("Strap battery low: {level:?}");
+        }
+        self.monitor_battery = level;
+    }
+    /// Takes the next reply from the heart rate source whatever's on screen,
+    /// otherwise it fills up and blocks the supervisor.
+    fn poll_monitor(&mut self) -> Result<()> {
+        let on_badge = matches!(self.view, AppView::BadgeDisplay);
         let mut rebuild_monitor = false;
-        if let Some(monitor) = &self.monitor {
-            let msg = monitor.reply_rx.try_recv();
+        if let Some(monitor) = &mut self.monitor {
+            let msg = monitor.try_recv();
             // let text = format!("{msg:#?}");
+            if let Ok(MonitorReply::MonitorStatus(status)) = &msg {
+                if self.settings.hr.rebroadcast {
+                    self.peripheral.publish(status);
+                }
+                if on_badge {
+                    self.record_status(status)?;
+                }
+                // Made up sources never say, don't forget what the strap told us
+                if status.battery_level != BatteryLevel::Unknown {
+                    self.update_battery(status.battery_level);
+                }
+            }
             match msg {
+                // Readings only count while they're on screen
+                Ok(MonitorReply::MonitorStatus(_)) if !on_badge => (),
+                Ok(MonitorReply::MonitorStatus(status)) if status.contact_lost() => {
+                    // Only worth repainting on the transition, nothing's changing after that
+                    self.beats.stop();
+                    if !self.hr_contact_lost {
+                        info!("Sensor contact lost!");
+                        self.hr_contact_lost = true;
+                        let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+                        self.paint_hr_readout(last_bpm)?;
+                    }
+                }
                 Ok(MonitorReply::MonitorStatus(status)) if status.heart_rate_bpm > 0 => {
+                    self.hr_contact_lost = false;
                     if self.plot_bpm_low == 0 && self.plot_bpm_high == 0 {
                         self.plot_bpm_low = (status.heart_rate_bpm as u8).saturating_sub(5);
                     } else {
@@ -382,120 +620,284 @@ where
                         .iter_mut()
                         .enumerate()
                         .for_each(|(index, point)| point.x = index as i32);
-                    // self.display.fill_solid(&self.hr_bound, Rgb565::BLACK)?;
-                    let bpm_string = format!(
-                        // "{}",
-                        "{:3}",
-                        status.heart_rate_bpm
+
+                    debug!(
+                        "RR rejected: {}, interpolated: {}",
+                        status.rr_rejected, status.rr_interpolated
                     );
-                    let text = Text::with_text_style(
-                        &bpm_string,
-                        // Point::new(240 / 2, 150),
-                        // Point::new(0, 60),
-                        Point::new(60, 60),
-                        bpm_style,
-                        left_style,
+                    self.hrv.extend(&status.rr_intervals, !status.has_real_rr());
+                    self.hr_zone = self.settings.zones.zone_for(status.heart_rate_bpm);
+                    let now = std::time::Duration::from_millis(Instant::now().as_millis());
+                    self.stats.record(
+                        now,
+                        status.heart_rate_bpm,
+                        status.energy_expended,
+                        self.hr_zone,
                     );
-
-                    // self.hr_bound = text.bounding_box();
-
-                    // info!("{:?}", self.hr_bound);
-
-                    let width = self.hr_canvas.size().width;
-                    self.hr_canvas
-                        .pixels
-                        .iter_mut()
-                        .enumerate()
-                        .for_each(|(index, color)| {
-                            let coords = (index % width as usize, index / width as usize);
-                            if color.is_some() && coords.0 > 50 {
-                                *color = Some(BinaryColor::Off);
-                            }
-                        });
-
-                    _ = text.draw(&mut self.hr_canvas);
-
-                    let mut curve = Curve::from_data(self.hr_history.as_slice());
-                    // let curve_list = [(curve, BinaryColor::On)];
-                    curve.x_range = 0..self.hr_history.capacity() as i32;
-                    curve.y_range = self.plot_bpm_low as i32..self.plot_bpm_high as i32;
-                    _ = curve
-                        .into_drawable_curve(&Point { x: 165, y: 0 }, &Point { x: 240, y: 60 })
-                        .set_color(BinaryColor::On)
-                        .set_thickness(3)
-                        .draw(&mut self.hr_canvas);
-
-                    self.display.set_pixels(
-                        NUMERIC_BOUND.top_left.x as u16,
-                        NUMERIC_BOUND.top_left.y as u16,
-                        NUMERIC_BOUND.bottom_right().unwrap().x as u16,
-                        NUMERIC_BOUND.bottom_right().unwrap().y as u16,
-                        self.hr_canvas.pixels.iter().map(|p| {
-                            match p {
-                                Some(BinaryColor::On) => Rgb565::RED,
-                                Some(BinaryColor::Off) => Rgb565::BLACK,
-                                None => Rgb565::BLACK,
-                                // Some(BinaryColor::Off) => Rgb565::new(50, 0, 0),
-                            }
-                            // if let Some(BinaryColor::On) = p {
-                            //     Rgb565::RED
-                            // } else {
-                            //     Rgb565::BLACK
-                            // }
-                        }),
-                    )?;
-
-                    // let plot = SinglePlot::new(
-                    // &curve_list,
-                    // Scale::RangeFraction(3),
-                    // Scale::RangeFraction(2),
-                    // )
-                    // .into_drawable(Point { x: 18, y: 2 }, Point { x: 120, y: 30 })
-                    // .set_color(BinaryColor::On);
-
-                    // plot.draw(&mut self.display.color_converted())?;
+                    self.stats_unsaved = true;
+                    self.beats.update(now, &status.rr_intervals);
+                    self.paint_hr_readout(Some(status.heart_rate_bpm))?;
+                }
+                Ok(MonitorReply::State(state)) => {
+                    info!("Monitor state: {state:?}");
+                    self.monitor_state = Some(state);
+                    if state == MonitorState::Scanning {
+                        // Whatever gets connected next reports its own
+                        self.monitor_battery = BatteryLevel::Unknown;
+                    }
+                    if state == MonitorState::Subscribed {
+                        self.hr_contact_lost = false;
+                        self.monitor_pairing_failed = false;
+                    } else {
+                        self.beats.stop();
+                    }
+                    let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+                    self.paint_hr_readout(last_bpm)?;
+                }
+                Ok(MonitorReply::Error(e)) => {
+                    warn!("Monitor reported an error: {e}");
+                }
+                Ok(MonitorReply::PairingFailed(e)) => {
+                    warn!("Couldn't pair with monitor: {e}");
+                    self.monitor_pairing_failed = true;
+                    let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+                    self.paint_hr_readout(last_bpm)?;
+                }
+                Ok(MonitorReply::Battery(level)) => {
+                    info!("Monitor battery: {level:?}");
+                    self.update_battery(level);
+                    let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+                    self.paint_hr_readout(last_bpm)?;
+                }
+                Ok(MonitorReply::Info(monitor_info)) => {
+                    info!("Monitor info: {monitor_info:?}");
+                    // Made up ones aren't worth showing as the saved strap's
+                    if monitor.kind() == HrSource::Ble {
+                        self.monitor_info = Some((monitor.ident().mac, monitor_info));
+                    }
                 }
                 Ok(msg) => (),
                 Err(TryRecvError::Empty) => (),
                 Err(TryRecvError::Disconnected) => {
-                    // Monitor disconnected or errored!
-                    error!("Monitor disconnected or errored! Rebuilding...");
+                    // Supervisor thread's gone, shouldn't happen unless it panicked
+                    error!("Heart rate source died! Restarting...");
                     rebuild_monitor = true;
                 }
             }
         }
 
         if rebuild_monitor {
-            self.delay.delay_ms(10000);
-            self.change_view(AppView::BadgeDisplay)?;
+            self.monitor = None;
+            self.start_monitor()?;
         }
+        Ok(())
+    }
 
-        let slideshow_enabled = self.settings.slideshow_length_sec != SlideshowLength::Off;
-        let image_count = self.image_count;
-        match self.touch() {
-            Some(TouchEvent {
-                point: new_point,
-                kind: TouchKind::Start | TouchKind::End,
-            }) => {
-                self.debounce_instant = Instant::now();
+    /// Redraws the readout for the picked layout into `hr_canvas`, then pushes
+    /// it to the display.
+    ///
+    /// Greyed out while the monitor isn't connected, with a cracked heart
+    /// if the strap's lost sensor contact.
+    fn paint_hr_readout(&mut self, bpm: Option<u16>) -> Result<()> {
+        if !matches!(self.view, AppView::BadgeDisplay) {
+            return Ok(());
+        }
+        self.draw_hr_readout(bpm);
+        self.push_hr_canvas(
+            Rectangle::new(Point::zero(), NUMERIC_BOUND.size),
+            self.readout_color(),
+        )?;
+        // Mid-pulse, put the heart back how it was
+        if self.settings.badge_layout == BadgeLayout::Classic && self.heart_level < HEART_LEVELS {
+            self.paint_heart()?;
+        }
+        Ok(())
+    }
+    /// Just the drawing half of `paint_hr_readout`.
+    fn draw_hr_readout(&mut self, bpm: Option<u16>) {
+        let small_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        let linked = self.monitor_state == Some(MonitorState::Subscribed);
+
+        self.hr_canvas
+            .pixels
+            .iter_mut()
+            .for_each(|pixel| *pixel = None);
+
+        match self.settings.badge_layout {
+            BadgeLayout::Classic => self.draw_classic_readout(bpm),
+            BadgeLayout::Monitor => self.draw_monitor_trace(bpm),
+        }
+        if self.session_log.is_some() {
+            // Recording dot in the corner, hollow if the card's not taking writes
+            let style = if self.session_log_failing {
+                PrimitiveStyle::with_stroke(BinaryColor::On, 1)
+            } else {
+                PrimitiveStyle::with_fill(BinaryColor::On)
+            };
+            _ = Circle::new(Point::zero(), 7).draw_styled(&style, &mut self.hr_canvas);
+        }
+        // Strap battery under the heart, blinking once it's low
+        let blinked_off =
+            self.monitor_battery.is_low(self.settings.hr.low_battery) && !self.battery_flash_on;
+        if self.monitor_battery != BatteryLevel::Unknown && !blinked_off {
+            const BATTERY_BOUND: Rectangle = Rectangle::new(Point::new(2, 52), Size::new(14, 8));
+            let outline = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
+            let fill = PrimitiveStyle::with_fill(BinaryColor::On);
+            _ = BATTERY_BOUND.draw_styled(&outline, &mut self.hr_canvas);
+            _ = Rectangle::new(Point::new(16, 54), Size::new(2, 4))
+                .draw_styled(&fill, &mut self.hr_canvas);
+            match self.monitor_battery {
+                BatteryLevel::Level(level) => {
+                    let width = (level as u32 * 10).div_ceil(100);
+                    _ = Rectangle::new(Point::new(4, 54), Size::new(width, 4))
+                        .draw_styled(&fill, &mut self.hr_canvas);
+                }
+                // Struck through, this strap just doesn't say
+                _ => {
+                    _ = Line::new(Point::new(3, 58), Point::new(15, 53))
+                        .draw_styled(&outline, &mut self.hr_canvas);
+                }
             }
-            Some(TouchEvent {
-                point: new_point,
-                kind: TouchKind::Move,
-            }) => {
-                self.repaint_full()?;
-                self.debounce_instant = Instant::now();
+        }
+        let label = match self.monitor_state {
+            // Sticks around through the backoff, so it's obvious why it keeps retrying
+            Some(_) if !linked && self.monitor_pairing_failed => String::from("Pair failed"),
+            Some(state) if !linked => state.to_string(),
+            None => String::from("Starting"),
+            _ if self.hr_contact_lost => String::from("No contact"),
+            _ => self.hr_zone.label().to_string(),
+        };
+        _ = Text::new(&label, Point::new(168, 58), small_style).draw(&mut self.hr_canvas);
+    }
+    /// Heart, big BPM digits, and the history curve squeezed in the corner.
+    fn draw_classic_readout(&mut self, bpm: Option<u16>) {
+        let bpm_style = SevenSegmentStyleBuilder::new()
+            .digit_size(Size::new(10 * 3, 20 * 3)) // digits are 10x20 pixels
+            .digit_spacing(5) // 5px spacing between digits
+            .segment_width(5) // 5px wide segments
+            // .segment_color(Rgb565::RED)
+            .segment_color(BinaryColor::On)
+            .build();
+        let left_style = TextStyleBuilder::new().alignment(Alignment::Left).build();
+        let small_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        let linked = self.monitor_state == Some(MonitorState::Subscribed);
+
+        let heart_icon = embedded_iconoir::icons::size48px::health::Heart::new(BinaryColor::On);
+        _ = Image::new(&heart_icon, Point::new(6, 6)).draw(&mut self.hr_canvas);
+        if self.hr_contact_lost {
+            _ = Polyline::new(&[
+                Point::new(30, 10),
+                Point::new(24, 24),
+                Point::new(35, 32),
+                Point::new(28, 52),
+            ])
+            .draw_styled(
+                &PrimitiveStyle::with_stroke(BinaryColor::Off, 3),
+                &mut self.hr_canvas,
+            );
+        }
+
+        if let Some(bpm) = bpm {
+            let bpm_string = format!(
+                // "{}",
+                "{:3}",
+                bpm
+            );
+            let text = Text::with_text_style(
+                &bpm_string,
+                // Point::new(240 / 2, 150),
+                // Point::new(0, 60),
+                Point::new(60, 60),
+                bpm_style,
+                left_style,
+            );
+            _ = text.draw(&mut self.hr_canvas);
+
+            let mut curve = Curve::from_data(self.hr_history.as_slice());
+            // let curve_list = [(curve, BinaryColor::On)];
+            curve.x_range = 0..self.hr_history.capacity() as i32;
+            curve.y_range = self.plot_bpm_low as i32..self.plot_bpm_high as i32;
+            _ = curve
+                .into_drawable_curve(&Point { x: 165, y: 0 }, &Point { x: 240, y: 60 })
+                .set_color(BinaryColor::On)
+                .set_thickness(3)
+                .draw(&mut self.hr_canvas);
+
+            let calm_score = self.hrv.stats().and_then(|stats| stats.calm_score());
+            if let Some(score) = calm_score.filter(|_| linked && !self.hr_contact_lost) {
+                _ = Text::new(&format!("Calm {score}"), Point::new(168, 8), small_style)
+                    .draw(&mut self.hr_canvas);
             }
-            None if slideshow_enabled && image_count > 1 => {
-                if self.debounce_instant.elapsed() > self.settings.slideshow_length_sec.into() {
-                    info!("does it ever happen?");
-                    self.repaint_full()?;
-                    self.debounce_instant = Instant::now();
-                }
+        }
+    }
+    /// Full width sweeping trace, with the BPM tucked in the corner.
+    fn draw_monitor_trace(&mut self, bpm: Option<u16>) {
+        // Leaves the bottom rows for the battery and label
+        const BASELINE: f32 = 40.0;
+        const R_HEIGHT: f32 = 34.0;
+        let y = |level: f32| (BASELINE - level * R_HEIGHT).round() as i32;
+        let trace_style = PrimitiveStyle::with_stroke(BinaryColor::On, 2);
+        for (x, columns) in self.ecg.trace().windows(2).enumerate() {
+            if let [Some(from), Some(to)] = columns {
+                _ = Line::new(
+                    Point::new(x as i32, y(*from)),
+                    Point::new(x as i32 + 1, y(*to)),
+                )
+                .draw_styled(&trace_style, &mut self.hr_canvas);
             }
-            _ => (),
         }
 
+        if let Some(bpm) = bpm {
+            // Blanks the trace behind it
+            let bpm_style = MonoTextStyleBuilder::new()
+                .font(&FONT_10X20)
+                .text_color(BinaryColor::On)
+                .background_color(BinaryColor::Off)
+                .build();
+            let right_style = TextStyleBuilder::new().alignment(Alignment::Right).build();
+            _ = Text::with_text_style(
+                &bpm.to_string(),
+                Point::new(238, 16),
+                bpm_style,
+                right_style,
+            )
+            .draw(&mut self.hr_canvas);
+        }
+    }
+    fn readout_color(&self) -> Rgb565 {
+        let linked = self.monitor_state == Some(MonitorState::Subscribed);
+        if self.hr_contact_lost || !linked {
+            Rgb565::CSS_GRAY
+        } else {
+            self.hr_zone.color()
+        }
+    }
+    /// Pushes just the heart from `hr_canvas`, at `heart_level` brightness.
+    fn paint_heart(&mut self) -> Result<()> {
+        let color = dim(
+            self.readout_color(),
+            self.heart_level as f32 / HEART_LEVELS as f32,
+        );
+        self.push_hr_canvas(HEART_BOUND, color)
+    }
+    /// Pushes `area` of `hr_canvas` to the display, lit pixels in `color`.
+    fn push_hr_canvas(&mut self, area: Rectangle, color: Rgb565) -> Result<()> {
+        let width = self.hr_canvas.size().width as i32;
+        let top_left = NUMERIC_BOUND.top_left + area.top_left;
+        let bottom_right = NUMERIC_BOUND.top_left + area.bottom_right().unwrap();
+        let pixels = area.points().map(|point| {
+            match self.hr_canvas.pixels[(point.y * width + point.x) as usize] {
+                Some(BinaryColor::On) => color,
+                _ => Rgb565::BLACK,
+            }
+        });
+        self.display.set_pixels(
+            top_left.x as u16,
+            top_left.y as u16,
+            bottom_right.x as u16,
+            bottom_right.y as u16,
+            pixels,
+        )?;
         Ok(())
     }
     pub fn doodle(&mut self) -> Result<()> {
@@ -562,7 +964,7 @@ where
         Ok(())
     }
     fn main_menu(&mut self) -> Result<()> {
-        let options_offset = Point::new(20, 50);
+        let options_offset = Point::new(20, 45);
         if self.paint_check() {
             info!(
                 "My code is running! Core: {:?}, Heap free: {}",
@@ -570,7 +972,7 @@ where
                 unsafe { esp_idf_hal::sys::esp_get_free_heap_size() }
             );
             let smol_char_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
-            let character_style = MonoTextStyle::new(&FONT_10X20, Rgb565::RED);
+            let character_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
             let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
             let line_style = PrimitiveStyleBuilder::new()
                 .stroke_width(2)
@@ -621,8 +1023,8 @@ where
             }
 
             Text::with_text_style(
-                "To clear settings,\nhold inner button _after_ powering on.",
-                Point::new(160, 220),
+                "Clear settings: hold inner button _after_ power on",
+                Point::new(160, 234),
                 smol_char_style,
                 text_style,
             )
@@ -673,9 +1075,16 @@ where
                     info!("{choice} at {point}");
                     match choice {
                         MainMenu::Start => self.change_view(AppView::BadgeDisplay)?,
-                        MainMenu::NameInput => self.change_view(AppView::NameInput)?,
+                        MainMenu::NameInput => {
+                            self.name_target = NameTarget::Username;
+                            self.change_view(AppView::NameInput)?;
+                        }
                         MainMenu::HrSelect => self.change_view(AppView::HrSelect)?,
                         MainMenu::Doodle => self.change_view(AppView::Doodle)?,
+                        MainMenu::Settings => self.change_view(AppView::Settings)?,
+                        MainMenu::Group => self.change_view(AppView::Group)?,
+                        MainMenu::Friends => self.change_view(AppView::Friends)?,
+                        MainMenu::SessionSummary => self.change_view(AppView::SessionSummary)?,
                         MainMenu::Slideshow => {
                             self.cycle_slideshow_length()?;
                             self.settings.littlefs_save()?;
@@ -717,16 +1126,20 @@ where
         let mut width: i32 = self.display.bounding_box().size.width as i32
             / self.username_scratch.len().max(1) as i32;
         if self.paint_check() {
-            let title_style = MonoTextStyle::new(&FONT_10X20, Rgb565::RED);
+            let title_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
             let name_style = MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE);
             let save_style = MonoTextStyle::new(&FONT_10X20, Rgb565::GREEN);
             let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
             let line_style = PrimitiveStyleBuilder::new()
                 .stroke_width(1)
-                .stroke_color(Rgb565::RED)
+                .stroke_color(self.settings.theme.accent())
                 .build();
 
-            Text::with_text_style("Name Input", Point::new(160, 15), title_style, text_style)
+            let title = match self.name_target {
+                NameTarget::Username => "Name Input",
+                NameTarget::GroupLabel(_) => "Group Label",
+            };
+            Text::with_text_style(title, Point::new(160, 15), title_style, text_style)
                 .draw(&mut self.display)?;
 
             if self.username_scratch.is_empty() {
@@ -809,7 +1222,7 @@ where
             .unwrap();
 
             let box_style = PrimitiveStyleBuilder::new()
-                .stroke_color(Rgb565::RED)
+                .stroke_color(self.settings.theme.accent())
                 .stroke_width(1)
                 .fill_color(Rgb565::BLACK)
                 .build();
@@ -957,128 +1370,647 @@ where
                     }
                 };
                 info!("SaveCan! {is_save}");
-                if is_save {
-                    self.settings.username.clone_from(&self.username_scratch);
-                    self.settings.littlefs_save()?;
-                    if fs::exists("/sdcard/NAME.TXT")? {
-                        info!("Writing name to SD!");
-                        fs::write("/sdcard/NAME.TXT", &self.settings.username)?;
+                match (is_save, self.name_target) {
+                    (true, NameTarget::Username) => {
+                        let username = self.username_scratch.clone();
+                        if let Err(e) = self.set_username(&username) {
+                            warn!("Not saving name: {e}");
+                            return Ok(());
+                        }
+                    }
+                    (true, NameTarget::GroupLabel(mac)) => {
+                        if let Some(tracked) = self
+                            .settings
+                            .hr
+                            .tracked
+                            .iter_mut()
+                            .find(|tracked| tracked.ident.mac == mac)
+                        {
+                            tracked.label = self.username_scratch.trim().to_string();
+                            self.settings.littlefs_save()?;
+                        }
                     }
+                    (false, _) => (),
+                }
+                match self.name_target {
+                    NameTarget::Username => self.change_view(AppView::MainMenu)?,
+                    NameTarget::GroupLabel(_) => self.change_view(AppView::Group)?,
                 }
-                self.change_view(AppView::MainMenu)?;
             }
             _ => (),
         }
         Ok(())
     }
-    fn hr_select(&mut self) -> Result<()> {
-        let has_hr_saved = self.settings.hr.saved.is_some();
-        let monitors_discovered = !self.ble.discovered.is_empty();
-
+    fn settings_menu(&mut self) -> Result<()> {
+        let options_offset = Point::new(20, 50);
         const BACK_BUTTON_BOUND: Rectangle =
             Rectangle::new(Point::new(290, 0), Size::new_equal(24));
-
-        const TRASH_BUTTON_BOUND: Rectangle =
-            Rectangle::new(Point::new(290, 210), Size::new_equal(24));
-
-        const RESCAN_BUTTON_BOUND: Rectangle =
-            Rectangle::new(Point::new(10, 210), Size::new_equal(24));
-
-        const SAVE_BUTTON_BOUND: Rectangle =
-            Rectangle::with_center(Point::new(160, 165), Size::new(50, 35));
-
-        const LEFT_BUTTON_BOUND: Rectangle =
-            Rectangle::with_center(Point::new(100, 165), Size::new_equal(24));
-
-        const RIGHT_BUTTON_BOUND: Rectangle =
-            Rectangle::with_center(Point::new(220, 165), Size::new_equal(24));
-
+        const PAGE_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(290, 212), Size::new_equal(24));
+        let page = self.settings_page;
         if self.paint_check() {
-            let back_icon = embedded_iconoir::icons::size24px::actions::Undo::new(Rgb565::WHITE);
-            let image = Image::new(&back_icon, BACK_BUTTON_BOUND.top_left);
-            image.draw(&mut self.display)?;
-
-            let rescan_icon =
-                embedded_iconoir::icons::size24px::actions::Refresh::new(Rgb565::WHITE);
-            let image = Image::new(&rescan_icon, RESCAN_BUTTON_BOUND.top_left);
-            image.draw(&mut self.display)?;
-
-            let title_style = MonoTextStyle::new(&FONT_10X20, Rgb565::RED);
-            let name_style = MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE);
-            let small_name_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
-            let save_style = MonoTextStyle::new(&FONT_10X20, Rgb565::GREEN);
+            let character_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
             let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
             let line_style = PrimitiveStyleBuilder::new()
-                .stroke_width(1)
-                .stroke_color(Rgb565::RED)
-                .build();
-            let green_line_style = PrimitiveStyleBuilder::new()
-                .stroke_width(1)
-                .stroke_color(Rgb565::RED)
+                .stroke_width(2)
+                .stroke_color(Rgb565::BLUE)
                 .build();
 
-            if monitors_discovered {
-                let left_icon =
-                    embedded_iconoir::icons::size24px::navigation::ArrowLeft::new(Rgb565::WHITE);
-                let image = Image::new(&left_icon, LEFT_BUTTON_BOUND.top_left);
-                image.draw(&mut self.display)?;
-
-                let right_icon =
-                    embedded_iconoir::icons::size24px::navigation::ArrowRight::new(Rgb565::WHITE);
-                let image = Image::new(&right_icon, RIGHT_BUTTON_BOUND.top_left);
-                image.draw(&mut self.display)?;
-
-                let text = format!(
-                    "Select HR Monitor\n{index}/{total}",
-                    index = self.ble.chosen_discovered + 1,
-                    total = self.ble.discovered.len()
-                );
-
-                Text::with_text_style(&text, Point::new(160, 15), title_style, text_style)
-                    .draw(&mut self.display)?;
-
-                let device = {
-                    let (mac, name) = self
-                        .ble
-                        .discovered
-                        .iter()
-                        .nth(self.ble.chosen_discovered)
-                        .unwrap();
-                    BleIdents {
-                        mac: *mac,
-                        name: name.clone(),
-                    }
-                };
-
-                Text::with_text_style(
-                    &device.to_string(),
-                    Point::new(160, 65),
-                    name_style,
-                    text_style,
-                )
+            let title = format!("Settings ({}/{})", page + 1, SettingsMenu::pages());
+            Text::with_text_style(&title, Point::new(160, 15), character_style, text_style)
                 .draw(&mut self.display)?;
+            let back_icon = embedded_iconoir::icons::size24px::actions::Undo::new(Rgb565::WHITE);
+            let image = Image::new(&back_icon, BACK_BUTTON_BOUND.top_left);
+            image.draw(&mut self.display)?;
+            let page_icon =
+                embedded_iconoir::icons::size24px::navigation::ArrowRight::new(Rgb565::WHITE);
+            let image = Image::new(&page_icon, PAGE_BUTTON_BOUND.top_left);
+            image.draw(&mut self.display)?;
 
-                SAVE_BUTTON_BOUND.draw_styled(&line_style, &mut self.display)?;
-                Text::with_text_style("Save", Point::new(160, 170), save_style, text_style)
-                    .draw(&mut self.display)?;
-            } else {
-                Text::with_text_style(
-                    "No monitors found!\n\nRescan with bottom-left button?",
-                    Point::new(160, 40),
-                    title_style,
-                    text_style,
-                )
-                .draw(&mut self.display)?;
+            for (item, point) in SettingsMenu::page_regions(Some(options_offset), page) {
+                let button_text = self.settings_item_text(item);
+                Text::new(&button_text, point, character_style).draw(&mut self.display)?;
+                Line::new(point + Point::new(-5, 0), point + Point::new(-5, -10))
+                    .draw_styled(&line_style, &mut self.display)?;
             }
+        }
 
-            if let Some(saved) = &self.settings.hr.saved {
-                let trash_icon =
-                    embedded_iconoir::icons::size24px::actions::Trash::new(Rgb565::WHITE);
-                let image = Image::new(&trash_icon, TRASH_BUTTON_BOUND.top_left);
-                image.draw(&mut self.display)?;
+        match self.touch() {
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if BACK_BUTTON_BOUND.contains(*point) => {
+                self.settings_page = 0;
+                self.change_view(AppView::MainMenu)?;
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if PAGE_BUTTON_BOUND.contains(*point) => {
+                self.settings_page = (page + 1) % SettingsMenu::pages();
+                self.repaint_full()?;
+                self.debounce_instant = Instant::now();
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) => {
+                let point = *point;
+                if let Some(choice) = SettingsMenu::from_touch_on_page(
+                    Some(options_offset),
+                    page,
+                    &point,
+                    &FONT_10X20,
+                ) {
+                    info!("{choice} at {point}");
+                    match choice {
+                        SettingsMenu::Source => {
+                            self.settings.hr.source = cycle_variant(self.settings.hr.source);
+                        }
+                        SettingsMenu::Record => {
+                            self.settings.hr.record = !self.settings.hr.record;
+                        }
+                        SettingsMenu::FilterRr => {
+                            self.settings.hr.filter_rr = !self.settings.hr.filter_rr;
+                        }
+                        SettingsMenu::Rebroadcast => {
+                            self.settings.hr.rebroadcast = !self.settings.hr.rebroadcast;
+                        }
+                        SettingsMenu::NameFallback => {
+                            self.settings.hr.name_fallback = !self.settings.hr.name_fallback;
+                        }
+                        SettingsMenu::Bond => {
+                            self.settings.hr.bond = !self.settings.hr.bond;
+                        }
+                        SettingsMenu::LowBattery => {
+                            let hr = &mut self.settings.hr;
+                            hr.low_battery = cycle_value(hr.low_battery, 0, 30, 5);
+                        }
+                        SettingsMenu::Visible => {
+                            self.settings.visible = !self.settings.visible;
+                        }
+                        SettingsMenu::PhoneSetup => {
+                            self.settings.phone_setup = !self.settings.phone_setup;
+                        }
+                        SettingsMenu::UtcOffset => {
+                            self.settings.utc_offset_min =
+                                next_utc_offset(self.settings.utc_offset_min);
+                        }
+                        SettingsMenu::Theme => {
+                            self.settings.theme = cycle_variant(self.settings.theme);
+                        }
+                        SettingsMenu::BadgeLayout => {
+                            self.settings.badge_layout = cycle_variant(self.settings.badge_layout);
+                        }
+                        SettingsMenu::ZoneModel => {
+                            let zones = &mut self.settings.zones;
+                            zones.model = cycle_variant(zones.model);
+                        }
+                        SettingsMenu::RestingHr => {
+                            let zones = &mut self.settings.zones;
+                            zones.resting_hr = cycle_value(zones.resting_hr, 40, 100, 5);
+                        }
+                        SettingsMenu::MaxHr => {
+                            let zones = &mut self.settings.zones;
+                            zones.max_hr = cycle_value(zones.max_hr, 150, 220, 5);
+                        }
+                    }
+                    self.settings.littlefs_save()?;
+                    let restart = matches!(
+                        choice,
+                        SettingsMenu::FilterRr | SettingsMenu::NameFallback | SettingsMenu::Bond
+                    );
+                    // Still running from the badge, don't wait until it's next started
+                    if restart && self.monitor.is_some() {
+                        self.start_monitor()?;
+                    }
+                    self.repaint_full()?;
+                }
+                self.debounce_instant = Instant::now();
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+    /// Starts the heart rate source picked in settings, unless it's already running.
+    fn start_monitor(&mut self) -> Result<()> {
+        let source = self.settings.hr.source;
+        let options = self.settings.hr.monitor_options();
+        // Sources only take their options when they're built
+        let running = self
+            .monitor
+            .as_ref()
+            .map(|monitor| monitor.kind())
+            .filter(|_| self.monitor_options == options);
+        self.monitor_options = options;
+        match source {
+            HrSource::Ble => {
+                let Some(saved) = self.settings.hr.saved.as_ref() else {
+                    self.monitor = None;
+                    return Ok(());
+                };
+                match &mut self.monitor {
+                    Some(monitor) if running == Some(source) && monitor.ident() == saved => {
+                        return Ok(())
+                    }
+                    Some(monitor) if running == Some(source) => {
+                        info!("Switching monitor supervisor to {saved}");
+                        monitor.command(BleHrCommand::SwitchTo(saved.clone()));
+                    }
+                    _ => {
+                        info!("Starting monitor supervisor for {saved}");
+                        let free_stack = unsafe {
+                            esp_idf_hal::sys::uxTaskGetStackHighWaterMark(std::ptr::null_mut())
+                        };
+                        info!("Stack Free: {free_stack}");
+                        // Drop the old source first, the supervisor wants the radio
+                        self.monitor = None;
+                        self.monitor =
+                            Some(Box::new(MonitorHandle::build(saved.clone(), options)?));
+                    }
+                }
+            }
+            _ if running == Some(source) => return Ok(()),
+            HrSource::Simulator => {
+                info!("Starting heart rate simulator");
+                self.monitor = Some(Box::new(SimulatedSource::new(
+                    SimConfig::default(),
+                    options.filter_rr,
+                )));
+            }
+            HrSource::Replay => {
+                self.monitor = None;
+                match ReplaySource::newest()? {
+                    Some(path) => match ReplaySource::open(&path, options.filter_rr) {
+                        Ok(replay) => self.monitor = Some(Box::new(replay)),
+                        Err(e) => warn!("Couldn't replay {path:?}: {e}"),
+                    },
+                    None => warn!("No recorded sessions to replay"),
+                }
+            }
+        }
+        self.monitor_state = None;
+        self.monitor_pairing_failed = false;
+        self.hrv.clear();
+        self.hr_contact_lost = false;
+        Ok(())
+    }
+    /// Starts a new recording if it's turned on. Replays aren't worth recording again.
+    fn start_session_log(&mut self) {
+        self.finish_session_log();
+        self.session_log_failing = false;
+        if !self.settings.hr.record || self.settings.hr.source == HrSource::Replay {
+            return;
+        }
+        match SessionRecorder::spawn() {
+            Ok(recorder) => self.session_log = Some(recorder),
+            Err(e) => warn!("Couldn't start recording: {e}"),
+        }
+    }
+    /// Flushes whatever's left and queues it up for exporting.
+    fn finish_session_log(&mut self) {
+        if let Some(recorder) = self.session_log.take() {
+            let path = recorder.path().to_path_buf();
+            drop(recorder);
+            self.fit_exporter.export(path);
+        }
+    }
+    fn save_stats(&mut self) -> Result<()> {
+        if self.stats_unsaved {
+            self.stats.save(Path::new(STATS_PATH))?;
+            self.stats_unsaved = false;
+        }
+        self.stats_saved_at = Instant::now();
+        Ok(())
+    }
+    fn record_status(&mut self, status: &MonitorStatus) -> Result<()> {
+        let Some(recorder) = self.session_log.as_ref() else {
+            return Ok(());
+        };
+        let row = SessionRow {
+            elapsed: recorder.elapsed(),
+            wall_clock: WallClock::now(self.settings.utc_offset_min),
+            measurement: status.to_measurement(),
+            battery: status.battery_level.percent(),
+        };
+        recorder.record(row);
+        // Only finds out about a failed write on the next row, close enough
+        let failing = recorder.failing();
+        if failing != self.session_log_failing {
+            self.session_log_failing = failing;
+            let last_bpm = self.hr_history.first().map(|point| point.y as u16);
+            self.paint_hr_readout(last_bpm)?;
+        }
+        Ok(())
+    }
+    /// Keeps our advertisement in line with settings and the current heart rate.
+    ///
+    /// Presence updates are rate limited, restarting advertising every beat would be a bit much.
+    fn update_advertising(&mut self) -> Result<()> {
+        let connectable = self.settings.hr.rebroadcast || self.settings.phone_setup;
+        let wanted = Advertised {
+            name: connectable.then(|| self.settings.username.clone()),
+            hr_service: self.settings.hr.rebroadcast,
+            presence: self.settings.visible.then(|| self.presence().encode()),
+        };
+        if wanted == self.advertised {
+            return Ok(());
+        }
+        let presence_only = wanted.name == self.advertised.name
+            && wanted.hr_service == self.advertised.hr_service
+            && wanted.presence.is_some() == self.advertised.presence.is_some();
+        // Don't hammer NimBLE retrying something it just refused either
+        if (presence_only || self.advertise_failed)
+            && self.advertised_at.elapsed() < PRESENCE_UPDATE_INTERVAL
+        {
+            return Ok(());
+        }
+        // Not worth taking the whole UI down over, it'll get another go in a bit
+        match self.peripheral.advertise(&wanted) {
+            Ok(()) => {
+                self.advertised = wanted;
+                self.advertise_failed = false;
+            }
+            Err(e) => {
+                warn!("Couldn't update advertising: {e}");
+                self.advertise_failed = true;
+            }
+        }
+        self.advertised_at = Instant::now();
+        Ok(())
+    }
+    /// What other badges get to see about us.
+    fn presence(&self) -> PresencePayload {
+        let live = self.monitor_state == Some(MonitorState::Subscribed) && !self.hr_contact_lost;
+        let bpm = self
+            .hr_history
+            .first()
+            .filter(|_| live)
+            .map(|point| point.y as u16);
+        PresencePayload {
+            username: self.settings.username.clone(),
+            bpm,
+            zone: bpm.map(|_| self.hr_zone),
+        }
+    }
+    /// Applies any changes a phone's made through the config service.
+    fn poll_config(&mut self) -> Result<()> {
+        self.config.set_enabled(self.settings.phone_setup);
+        let mut changed = false;
+        while let Ok(write) = self.config.write_rx.try_recv() {
+            // Could've been queued just before it got turned off
+            if !self.settings.phone_setup {
+                warn!("Phone setup's off, ignoring {write:?}");
+                continue;
+            }
+            info!("Config write from phone: {write:?}");
+            // A bad write shouldn't take the UI down, the phone's already been told it worked
+            if let Err(e) = self.apply_config(write) {
+                warn!("Couldn't apply config write: {e}");
+            }
+            changed = true;
+        }
+        self.config.publish(&self.settings);
+        if changed {
+            if let (AppView::NameInput, NameTarget::Username) = (&self.view, self.name_target) {
+                self.username_scratch.clone_from(&self.settings.username);
+            }
+            if matches!(self.view, AppView::BadgeDisplay) {
+                self.clear_vertical()?;
+                self.repaint();
+            } else {
+                self.repaint_full()?;
+            }
+        }
+        Ok(())
+    }
+    /// Sets the clock from whatever phone last connected, and the offset if it told us.
+    fn poll_time_sync(&mut self) -> Result<()> {
+        while let Ok(result) = self.time_sync.result_rx.try_recv() {
+            let phone = match result {
+                Ok(phone) => phone,
+                Err(e) => {
+                    info!("Couldn't get the time from the phone: {e}");
+                    continue;
+                }
+            };
+            let utc_offset_min = phone.utc_offset_min.unwrap_or(self.settings.utc_offset_min);
+            if utc_offset_min != self.settings.utc_offset_min {
+                self.settings.utc_offset_min = utc_offset_min;
+                self.settings.littlefs_save()?;
+            }
+            self.clock.synced(phone.time.to_unix(utc_offset_min))?;
+        }
+        Ok(())
+    }
+    fn apply_config(&mut self, write: ConfigWrite) -> Result<()> {
+        match write {
+            ConfigWrite::Username(username) => self.set_username(&username),
+            ConfigWrite::Slideshow(length) => {
+                self.settings.slideshow_length_sec = length;
+                self.settings.littlefs_save()
+            }
+            ConfigWrite::SavedMonitor(saved) => self.set_saved_monitor(saved),
+            ConfigWrite::Theme(theme) => {
+                self.settings.theme = theme;
+                self.settings.littlefs_save()
+            }
+        }
+    }
+    /// Saves a new username, to the SD card too if it's got a NAME.TXT.
+    fn set_username(&mut self, username: &str) -> Result<()> {
+        self.settings.username = validate_username(username)?;
+        self.settings.littlefs_save()?;
+        if fs::exists("/sdcard/NAME.TXT")? {
+            info!("Writing name to SD!");
+            fs::write("/sdcard/NAME.TXT", &self.settings.username)?;
+        }
+        Ok(())
+    }
+    /// Saves (or forgets) the monitor the badge connects to.
+    fn set_saved_monitor(&mut self, saved: Option<BleIdents>) -> Result<()> {
+        if saved.is_none() {
+            if let Some(mut monitor) = self.monitor.take() {
+                monitor.command(BleHrCommand::Disconnect);
+            }
+            self.monitor_state = None;
+        }
+        self.settings.hr.saved = saved;
+        self.settings.littlefs_save()?;
+        if matches!(self.view, AppView::BadgeDisplay) {
+            self.start_monitor()?;
+        }
+        Ok(())
+    }
+    fn settings_item_text(&self, item: SettingsMenu) -> String {
+        let on_off = |enabled: bool| if enabled { "On" } else { "Off" };
+        match item {
+            SettingsMenu::Source => format!("{item}: {}", self.settings.hr.source),
+            SettingsMenu::Record => format!("{item}: {}", on_off(self.settings.hr.record)),
+            SettingsMenu::FilterRr => format!("{item}: {}", on_off(self.settings.hr.filter_rr)),
+            SettingsMenu::Rebroadcast => {
+                format!("{item}: {}", on_off(self.settings.hr.rebroadcast))
+            }
+            SettingsMenu::NameFallback => {
+                format!("{item}: {}", on_off(self.settings.hr.name_fallback))
+            }
+            SettingsMenu::Bond => format!("{item}: {}", on_off(self.settings.hr.bond)),
+            SettingsMenu::LowBattery => match self.settings.hr.low_battery {
+                0 => format!("{item}: Off"),
+                threshold => format!("{item}: {threshold}%"),
+            },
+            SettingsMenu::Visible => format!("{item}: {}", on_off(self.settings.visible)),
+            SettingsMenu::PhoneSetup => format!("{item}: {}", on_off(self.settings.phone_setup)),
+            SettingsMenu::UtcOffset => {
+                format!(
+                    "{item}: {}",
+                    format_utc_offset(self.settings.utc_offset_min)
+                )
+            }
+            SettingsMenu::Theme => format!("{item}: {}", self.settings.theme),
+            SettingsMenu::BadgeLayout => format!("{item}: {}", self.settings.badge_layout),
+            SettingsMenu::ZoneModel => format!("{item}: {}", self.settings.zones.model),
+            SettingsMenu::RestingHr => format!("{item}: {}", self.settings.zones.resting_hr),
+            SettingsMenu::MaxHr => format!("{item}: {}", self.settings.zones.max_hr),
+        }
+    }
+    fn hr_select(&mut self) -> Result<()> {
+        match self.ble.poll_scan() {
+            Ok(true) => self.repaint_full()?,
+            Ok(false) => (),
+            Err(e) => {
+                error!("Monitor scan failed: {e}");
+                self.repaint_full()?;
+            }
+        }
+        let has_hr_saved = self.settings.hr.saved.is_some();
+        let show_saved_info = self.saved_info_open && has_hr_saved;
+        // The picker's hidden under the info panel, its buttons too
+        let monitors_discovered = !self.ble.discovered.is_empty() && !show_saved_info;
+        let scanning = self.ble.scanning();
+
+        const BACK_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(290, 0), Size::new_equal(24));
 
+        const TRASH_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(290, 210), Size::new_equal(24));
+
+        const UNPAIR_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(234, 212), Size::new(48, 20));
+
+        const RESCAN_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(10, 210), Size::new_equal(24));
+
+        const SAVE_BUTTON_BOUND: Rectangle =
+            Rectangle::with_center(Point::new(160, 165), Size::new(50, 35));
+
+        const LEFT_BUTTON_BOUND: Rectangle =
+            Rectangle::with_center(Point::new(100, 165), Size::new_equal(24));
+
+        const RIGHT_BUTTON_BOUND: Rectangle =
+            Rectangle::with_center(Point::new(220, 165), Size::new_equal(24));
+
+        const GROUP_BUTTON_BOUND: Rectangle =
+            Rectangle::with_center(Point::new(40, 165), Size::new(50, 35));
+
+        const SCAN_PROGRESS_BOUND: Rectangle =
+            Rectangle::new(Point::new(60, 130), Size::new(200, 6));
+
+        const SAVED_INFO_BOUND: Rectangle = Rectangle::new(Point::new(60, 195), Size::new(170, 40));
+
+        if self.paint_check() {
+            let back_icon = embedded_iconoir::icons::size24px::actions::Undo::new(Rgb565::WHITE);
+            let image = Image::new(&back_icon, BACK_BUTTON_BOUND.top_left);
+            image.draw(&mut self.display)?;
+
+            if scanning {
+                // Cancel "icon", the rescan button stops the scan while it's running
+                let cancel_style = PrimitiveStyle::with_stroke(Rgb565::WHITE, 3);
+                let bound = RESCAN_BUTTON_BOUND.offset(-4);
+                let bottom_right = bound.bottom_right().unwrap();
+                Line::new(bound.top_left, bottom_right)
+                    .draw_styled(&cancel_style, &mut self.display)?;
+                Line::new(
+                    Point::new(bound.top_left.x, bottom_right.y),
+                    Point::new(bottom_right.x, bound.top_left.y),
+                )
+                .draw_styled(&cancel_style, &mut self.display)?;
+
+                SCAN_PROGRESS_BOUND.draw_styled(
+                    &PrimitiveStyle::with_stroke(self.settings.theme.accent(), 1),
+                    &mut self.display,
+                )?;
+            } else {
+                let rescan_icon =
+                    embedded_iconoir::icons::size24px::actions::Refresh::new(Rgb565::WHITE);
+                let image = Image::new(&rescan_icon, RESCAN_BUTTON_BOUND.top_left);
+                image.draw(&mut self.display)?;
+            }
+
+            let title_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
+            let small_name_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+            let save_style = MonoTextStyle::new(&FONT_10X20, Rgb565::GREEN);
+            let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+            let line_style = PrimitiveStyleBuilder::new()
+                .stroke_width(1)
+                .stroke_color(self.settings.theme.accent())
+                .build();
+            let green_line_style = PrimitiveStyleBuilder::new()
+                .stroke_width(1)
+                .stroke_color(self.settings.theme.accent())
+                .build();
+
+            if show_saved_info {
+                self.paint_saved_info()?;
+            } else if monitors_discovered {
+                let left_icon =
+                    embedded_iconoir::icons::size24px::navigation::ArrowLeft::new(Rgb565::WHITE);
+                let image = Image::new(&left_icon, LEFT_BUTTON_BOUND.top_left);
+                image.draw(&mut self.display)?;
+
+                let right_icon =
+                    embedded_iconoir::icons::size24px::navigation::ArrowRight::new(Rgb565::WHITE);
+                let image = Image::new(&right_icon, RIGHT_BUTTON_BOUND.top_left);
+                image.draw(&mut self.display)?;
+
+                let text = format!(
+                    "Select HR Monitor ({index}/{total})",
+                    index = self.ble.chosen_discovered + 1,
+                    total = self.ble.discovered.len()
+                );
+
+                Text::with_text_style(&text, Point::new(160, 15), title_style, text_style)
+                    .draw(&mut self.display)?;
+
+                let page_start =
+                    self.ble.chosen_discovered - self.ble.chosen_discovered % PICKER_ROWS;
+                let rows: Vec<(usize, String, u8)> = self
+                    .ble
+                    .discovered
+                    .iter()
+                    .enumerate()
+                    .skip(page_start)
+                    .take(PICKER_ROWS)
+                    .map(|(index, (_, monitor))| (index, monitor.name.clone(), monitor.bars()))
+                    .collect();
+
+                for (row, (index, name, bars)) in rows.into_iter().enumerate() {
+                    let top = picker_row_bound(row).top_left;
+                    let chosen = index == self.ble.chosen_discovered;
+                    let color = if chosen {
+                        Rgb565::WHITE
+                    } else {
+                        Rgb565::CSS_GRAY
+                    };
+                    self.paint_signal_bars(top + Point::new(20, 18), bars, color)?;
+
+                    let name: String = name.chars().take(22).collect();
+                    let row_style = MonoTextStyle::new(&FONT_10X20, color);
+                    Text::new(&name, top + Point::new(50, 15), row_style)
+                        .draw(&mut self.display)?;
+                    if chosen {
+                        Text::new(">", top + Point::new(4, 15), row_style)
+                            .draw(&mut self.display)?;
+                    }
+                }
+
+                SAVE_BUTTON_BOUND.draw_styled(&line_style, &mut self.display)?;
+                Text::with_text_style("Save", Point::new(160, 170), save_style, text_style)
+                    .draw(&mut self.display)?;
+
+                let group_text = if self.chosen_is_tracked() {
+                    "-Group"
+                } else {
+                    "+Group"
+                };
+                GROUP_BUTTON_BOUND.draw_styled(&line_style, &mut self.display)?;
                 Text::with_text_style(
-                    &format!("Saved:\n{saved}"),
+                    group_text,
+                    GROUP_BUTTON_BOUND.center() + Point::new(0, 3),
+                    small_name_style,
+                    text_style,
+                )
+                .draw(&mut self.display)?;
+            } else if scanning {
+                Text::with_text_style(
+                    "Scanning for HR monitors...\n\nThey'll show up here\nas they're found",
+                    Point::new(160, 40),
+                    title_style,
+                    text_style,
+                )
+                .draw(&mut self.display)?;
+            } else {
+                Text::with_text_style(
+                    "No monitors found!\n\nRescan with bottom-left button?",
+                    Point::new(160, 40),
+                    title_style,
+                    text_style,
+                )
+                .draw(&mut self.display)?;
+            }
+
+            if let Some(saved) = &self.settings.hr.saved {
+                let trash_icon =
+                    embedded_iconoir::icons::size24px::actions::Trash::new(Rgb565::WHITE);
+                let image = Image::new(&trash_icon, TRASH_BUTTON_BOUND.top_left);
+                image.draw(&mut self.display)?;
+
+                UNPAIR_BUTTON_BOUND.draw_styled(&line_style, &mut self.display)?;
+                Text::with_text_style(
+                    "Unpair",
+                    UNPAIR_BUTTON_BOUND.center() + Point::new(0, 3),
+                    small_name_style,
+                    text_style,
+                )
+                .draw(&mut self.display)?;
+
+                let hint = if show_saved_info {
+                    "hide"
+                } else {
+                    "tap for info"
+                };
+                Text::with_text_style(
+                    &format!("Saved ({hint}):\n{saved}"),
                     Point::new(160, 210),
                     small_name_style,
                     text_style,
@@ -1086,13 +2018,316 @@ where
                 .draw(&mut self.display)?;
             } else {
                 Text::with_text_style(
-                    "No monitor saved",
-                    Point::new(160, 210),
-                    small_name_style,
+                    "No monitor saved",
+                    Point::new(160, 210),
+                    small_name_style,
+                    text_style,
+                )
+                .draw(&mut self.display)?;
+            }
+        }
+
+        // Cheap enough to just draw over itself every tick
+        if let Some(progress) = self.ble.scan_progress() {
+            let width = (SCAN_PROGRESS_BOUND.size.width as f32 * progress) as u32;
+            Rectangle::new(
+                SCAN_PROGRESS_BOUND.top_left,
+                Size::new(width, SCAN_PROGRESS_BOUND.size.height),
+            )
+            .draw_styled(
+                &PrimitiveStyle::with_fill(self.settings.theme.accent()),
+                &mut self.display,
+            )?;
+        }
+
+        match self.touch() {
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if BACK_BUTTON_BOUND.contains(*point) => {
+                self.change_view(AppView::MainMenu)?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if TRASH_BUTTON_BOUND.contains(*point) && has_hr_saved => {
+                info!("Trashing saved device!");
+                self.set_saved_monitor(None)?;
+                self.repaint_full()?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if UNPAIR_BUTTON_BOUND.contains(*point) && has_hr_saved => {
+                if let Some(saved) = &self.settings.hr.saved {
+                    // Next connection pairs from scratch, for when the strap's forgotten us
+                    match forget_bond(saved) {
+                        Ok(true) => info!("Forgot bond with {saved}"),
+                        Ok(false) => info!("No bond with {saved} to forget"),
+                        Err(e) => warn!("Couldn't forget bond with {saved}: {e}"),
+                    }
+                }
+                self.repaint_full()?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if SAVED_INFO_BOUND.contains(*point) && has_hr_saved => {
+                self.saved_info_open = !self.saved_info_open;
+                self.repaint_full()?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if SAVE_BUTTON_BOUND.contains(*point) && monitors_discovered => {
+                let device = {
+                    let (mac, monitor) =
+                        self.ble.discovered.get(self.ble.chosen_discovered).unwrap();
+                    monitor.ident(mac)
+                };
+                info!("Saving {device}!");
+                self.ble.stop_scan();
+                self.set_saved_monitor(Some(device))?;
+                // Straight to the badge so it connects now, no need to wait out the scan
+                self.change_view(AppView::BadgeDisplay)?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if GROUP_BUTTON_BOUND.contains(*point) && monitors_discovered => {
+                let (mac, monitor) = self.ble.discovered.get(self.ble.chosen_discovered).unwrap();
+                let tracked = &mut self.settings.hr.tracked;
+                if let Some(index) = tracked.iter().position(|t| t.ident.mac == mac) {
+                    info!("Removing {} from the group", monitor.name);
+                    tracked.remove(index);
+                } else if tracked.len() < MAX_TRACKED {
+                    info!("Adding {} to the group", monitor.name);
+                    tracked.push(TrackedMonitor::new(monitor.ident(mac)));
+                } else {
+                    warn!("Group's full!");
+                    return Ok(());
+                }
+                self.settings.littlefs_save()?;
+                self.repaint_full()?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if RESCAN_BUTTON_BOUND.contains(*point) && scanning => {
+                info!("Cancelling scan");
+                self.ble.stop_scan();
+                self.repaint_full()?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if RESCAN_BUTTON_BOUND.contains(*point) => {
+                self.change_view(AppView::HrSelect)?;
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if LEFT_BUTTON_BOUND.contains(*point) && monitors_discovered => {
+                self.display.clear(Rgb565::BLACK)?;
+
+                self.ble.chosen_discovered = self
+                    .ble
+                    .chosen_discovered
+                    .checked_sub(1)
+                    .unwrap_or(self.ble.discovered.len() - 1);
+
+                self.repaint();
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if RIGHT_BUTTON_BOUND.contains(*point) && monitors_discovered => {
+                self.display.clear(Rgb565::BLACK)?;
+
+                self.ble.chosen_discovered += 1;
+                if self.ble.chosen_discovered >= self.ble.discovered.len() {
+                    self.ble.chosen_discovered = 0;
+                }
+
+                self.repaint();
+                return Ok(());
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if monitors_discovered => {
+                let page_start =
+                    self.ble.chosen_discovered - self.ble.chosen_discovered % PICKER_ROWS;
+                let tapped = (0..PICKER_ROWS)
+                    .find(|row| picker_row_bound(*row).contains(*point))
+                    .map(|row| page_start + row)
+                    .filter(|index| *index < self.ble.discovered.len());
+                if let Some(index) = tapped {
+                    self.ble.chosen_discovered = index;
+                    self.repaint_full()?;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+    /// If the monitor selected in the picker is on the group display.
+    fn chosen_is_tracked(&self) -> bool {
+        self.ble
+            .discovered
+            .get(self.ble.chosen_discovered)
+            .is_some_and(|(mac, _)| {
+                self.settings
+                    .hr
+                    .tracked
+                    .iter()
+                    .any(|tracked| tracked.ident.mac == mac)
+            })
+    }
+    /// Grid of every tracked monitor's label and BPM, tap one to relabel it.
+    fn group_view(&mut self) -> Result<()> {
+        const BACK_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(290, 0), Size::new_equal(24));
+
+        let changed = self.group.poll()?;
+        let tracked_count = self.settings.hr.tracked.len().min(MAX_TRACKED);
+
+        if self.paint_check() {
+            let back_icon = embedded_iconoir::icons::size24px::actions::Undo::new(Rgb565::WHITE);
+            let image = Image::new(&back_icon, BACK_BUTTON_BOUND.top_left);
+            image.draw(&mut self.display)?;
+
+            let title_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
+            let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+            Text::with_text_style("Group", Point::new(160, 15), title_style, text_style)
+                .draw(&mut self.display)?;
+
+            if tracked_count == 0 {
+                Text::with_text_style(
+                    "Nobody in the group yet!\n\nAdd monitors from\nBLE HR Monitor Selection",
+                    Point::new(160, 80),
+                    title_style,
+                    text_style,
+                )
+                .draw(&mut self.display)?;
+            }
+            for index in 0..tracked_count {
+                self.paint_group_cell(index)?;
+            }
+        } else {
+            for mac in changed {
+                if let Some(index) = self
+                    .settings
+                    .hr
+                    .tracked
+                    .iter()
+                    .position(|tracked| tracked.ident.mac == mac)
+                {
+                    self.paint_group_cell(index)?;
+                }
+            }
+        }
+
+        match self.touch() {
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) if BACK_BUTTON_BOUND.contains(*point) => {
+                self.change_view(AppView::MainMenu)?;
+            }
+            Some(TouchEvent {
+                point,
+                kind: TouchKind::Start,
+            }) => {
+                let point = *point;
+                if let Some(index) =
+                    (0..tracked_count).find(|index| group_cell_bound(*index).contains(point))
+                {
+                    let mac = self.settings.hr.tracked[index].ident.mac;
+                    self.name_target = NameTarget::GroupLabel(mac);
+                    self.change_view(AppView::NameInput)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+    /// Whatever the saved monitor told us last time it connected.
+    fn paint_saved_info(&mut self) -> Result<()> {
+        let title_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
+        let text_style = MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE);
+        let center_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+        Text::with_text_style(
+            "Saved Monitor",
+            Point::new(160, 15),
+            title_style,
+            center_style,
+        )
+        .draw(&mut self.display)?;
+
+        let saved_mac = self.settings.hr.saved.as_ref().map(|saved| saved.mac);
+        let info = match &self.monitor_info {
+            Some((mac, info)) if Some(*mac) == saved_mac => info,
+            _ => {
+                Text::with_text_style(
+                    "Start the badge with it\nconnected to read\nits details",
+                    Point::new(160, 60),
                     text_style,
+                    center_style,
                 )
                 .draw(&mut self.display)?;
+                return Ok(());
             }
+        };
+        let unknown = || String::from("?");
+        let lines = [
+            (
+                "Worn on",
+                info.location.map(|location| location.to_string()),
+            ),
+            ("Maker", info.manufacturer.clone()),
+            ("Model", info.model.clone()),
+            ("Firmware", info.firmware.clone()),
+            ("Serial", info.serial.clone()),
+        ];
+        for (index, (label, value)) in lines.into_iter().enumerate() {
+            let value: String = value.unwrap_or_else(unknown).chars().take(20).collect();
+            let baseline = 45 + 20 * index as i32;
+            Text::new(
+                &format!("{label:<9}{value}"),
+                Point::new(10, baseline),
+                text_style,
+            )
+            .draw(&mut self.display)?;
+        }
+        Ok(())
+    }
+    fn session_summary(&mut self) -> Result<()> {
+        const BACK_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(290, 0), Size::new_equal(24));
+        const CLEAR_BOUND: Rectangle = Rectangle::new(Point::new(110, 210), Size::new(100, 30));
+
+        if self.paint_check() {
+            let back_icon = embedded_iconoir::icons::size24px::actions::Undo::new(Rgb565::WHITE);
+            Image::new(&back_icon, BACK_BUTTON_BOUND.top_left).draw(&mut self.display)?;
+            self.paint_session_summary()?;
+            Text::with_text_style(
+                "Clear",
+                CLEAR_BOUND.center() + Point::new(0, 6),
+                MonoTextStyle::new(&FONT_10X20, Rgb565::RED),
+                TextStyleBuilder::new().alignment(Alignment::Center).build(),
+            )
+            .draw(&mut self.display)?;
         }
 
         match self.touch() {
@@ -1101,80 +2336,298 @@ where
                 kind: TouchKind::Start,
             }) if BACK_BUTTON_BOUND.contains(*point) => {
                 self.change_view(AppView::MainMenu)?;
-                return Ok(());
             }
             Some(TouchEvent {
                 point,
                 kind: TouchKind::Start,
-            }) if TRASH_BUTTON_BOUND.contains(*point) && has_hr_saved => {
-                info!("Trashing saved device!");
-                self.settings.hr.saved = None;
-                self.settings.littlefs_save()?;
+            }) if CLEAR_BOUND.contains(*point) => {
+                info!("Clearing session stats");
+                self.stats = SessionStats::default();
+                self.stats_unsaved = true;
+                self.save_stats()?;
+                self.debounce_instant = Instant::now();
                 self.repaint_full()?;
-                return Ok(());
             }
-            Some(TouchEvent {
-                point,
-                kind: TouchKind::Start,
-            }) if SAVE_BUTTON_BOUND.contains(*point) && monitors_discovered => {
-                let device = {
-                    let (mac, name) = self
-                        .ble
-                        .discovered
-                        .iter()
-                        .nth(self.ble.chosen_discovered)
-                        .unwrap();
-                    BleIdents {
-                        mac: *mac,
-                        name: name.clone(),
-                    }
-                };
-                info!("Saving {device}!");
-                self.settings.hr.saved = Some(device);
-                self.settings.littlefs_save()?;
-                self.change_view(AppView::MainMenu)?;
-                return Ok(());
+            _ => (),
+        }
+        Ok(())
+    }
+    fn paint_session_summary(&mut self) -> Result<()> {
+        const GRAPH_BOUND: Rectangle = Rectangle::new(Point::new(10, 130), Size::new(300, 75));
+
+        let title_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
+        let text_style = MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE);
+        let small_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let center_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+        let right_style = TextStyleBuilder::new().alignment(Alignment::Right).build();
+        Text::with_text_style(
+            "Session Summary",
+            Point::new(160, 15),
+            title_style,
+            center_style,
+        )
+        .draw(&mut self.display)?;
+
+        let (Some(avg), Some(min), Some(max)) = (
+            self.stats.average_bpm(),
+            self.stats.min_bpm,
+            self.stats.max_bpm,
+        ) else {
+            Text::with_text_style(
+                "Nothing recorded yet...",
+                Point::new(160, 100),
+                title_style,
+                center_style,
+            )
+            .draw(&mut self.display)?;
+            return Ok(());
+        };
+
+        let lines = [
+            format!("Time {}", format_duration(self.stats.active())),
+            format!("Avg  {avg} BPM"),
+            format!("Low  {min} BPM"),
+            format!("High {max} BPM"),
+            format!("Energy {} kcal", self.stats.energy.kcal()),
+        ];
+        for (index, line) in lines.iter().enumerate() {
+            let baseline = 45 + 20 * index as i32;
+            Text::new(line, Point::new(10, baseline), text_style).draw(&mut self.display)?;
+        }
+
+        // Time in each zone, bars scaled to whichever's longest
+        let longest = HrZone::VARIANTS
+            .iter()
+            .map(|&zone| self.stats.zone_time(zone))
+            .max()
+            .unwrap_or_default()
+            .max(std::time::Duration::from_secs(1));
+        for (index, &zone) in HrZone::VARIANTS.iter().enumerate() {
+            let baseline = 40 + 14 * index as i32;
+            let time = self.stats.zone_time(zone);
+            Text::new(zone.label(), Point::new(165, baseline), small_style)
+                .draw(&mut self.display)?;
+            let width = (40.0 * time.as_secs_f32() / longest.as_secs_f32()).round() as u32;
+            Rectangle::new(Point::new(228, baseline - 8), Size::new(width.max(1), 8))
+                .into_styled(PrimitiveStyle::with_fill(zone.color()))
+                .draw(&mut self.display)?;
+            Text::with_text_style(
+                &format_duration(time),
+                Point::new(316, baseline),
+                small_style,
+                right_style,
+            )
+            .draw(&mut self.display)?;
+        }
+
+        let points = self.stats.graph_points();
+        if points.len() > 1 {
+            let data: Vec<PlotPoint> = points
+                .iter()
+                .enumerate()
+                .map(|(x, &bpm)| PlotPoint {
+                    x: x as i32,
+                    y: bpm as i32,
+                })
+                .collect();
+            let mut curve = Curve::from_data(data.as_slice());
+            // Short sessions don't get stretched across the whole width
+            curve.x_range = 0..(points.len() - 1).max(GRAPH_POINTS / 4) as i32;
+            curve.y_range = min.saturating_sub(5) as i32..max.saturating_add(5) as i32;
+            curve
+                .into_drawable_curve(&GRAPH_BOUND.top_left, &GRAPH_BOUND.bottom_right().unwrap())
+                .set_color(self.settings.theme.accent())
+                .set_thickness(2)
+                .draw(&mut self.display)?;
+        }
+        Ok(())
+    }
+    /// Other badges in range, closest first.
+    fn friends_view(&mut self) -> Result<()> {
+        const BACK_BUTTON_BOUND: Rectangle =
+            Rectangle::new(Point::new(290, 0), Size::new_equal(24));
+
+        const LIST_BOUND: Rectangle = Rectangle::new(Point::new(0, 30), Size::new(320, 180));
+
+        if self.ble.poll_presence() {
+            // Only the list needs redrawing, saves flashing the whole screen
+            self.display.fill_solid(&LIST_BOUND, Rgb565::BLACK)?;
+            self.repaint();
+        }
+
+        if self.paint_check() {
+            let back_icon = embedded_iconoir::icons::size24px::actions::Undo::new(Rgb565::WHITE);
+            let image = Image::new(&back_icon, BACK_BUTTON_BOUND.top_left);
+            image.draw(&mut self.display)?;
+
+            let title_style = MonoTextStyle::new(&FONT_10X20, self.settings.theme.accent());
+            let small_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+            let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+            let right_style = TextStyleBuilder::new().alignment(Alignment::Right).build();
+            Text::with_text_style(
+                "Friends Nearby",
+                Point::new(160, 15),
+                title_style,
+                text_style,
+            )
+            .draw(&mut self.display)?;
+
+            let rows: Vec<(String, Option<u16>, Rgb565, u8)> = self
+                .ble
+                .nearby
+                .sorted()
+                .into_iter()
+                .take(FRIENDS_ROWS)
+                .map(|(_, badge)| {
+                    let presence = &badge.presence;
+                    let color = presence.zone.map_or(Rgb565::CSS_GRAY, |zone| zone.color());
+                    (presence.username.clone(), presence.bpm, color, badge.bars())
+                })
+                .collect();
+
+            if rows.is_empty() {
+                Text::with_text_style(
+                    "Nobody nearby yet...",
+                    Point::new(160, 100),
+                    title_style,
+                    text_style,
+                )
+                .draw(&mut self.display)?;
             }
-            Some(TouchEvent {
-                point,
-                kind: TouchKind::Start,
-            }) if RESCAN_BUTTON_BOUND.contains(*point) => {
-                self.change_view(AppView::HrSelect)?;
-                return Ok(());
+
+            for (row, (username, bpm, color, bars)) in rows.into_iter().enumerate() {
+                let baseline = 55 + 28 * row as i32;
+                self.paint_signal_bars(Point::new(10, baseline + 3), bars, Rgb565::WHITE)?;
+                Text::new(
+                    &username,
+                    Point::new(45, baseline),
+                    MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE),
+                )
+                .draw(&mut self.display)?;
+                let bpm_text = bpm.map_or(String::from("--"), |bpm| format!("{bpm} BPM"));
+                Text::with_text_style(
+                    &bpm_text,
+                    Point::new(310, baseline),
+                    MonoTextStyle::new(&FONT_10X20, color),
+                    right_style,
+                )
+                .draw(&mut self.display)?;
             }
-            Some(TouchEvent {
-                point,
-                kind: TouchKind::Start,
-            }) if LEFT_BUTTON_BOUND.contains(*point) && monitors_discovered => {
-                self.display.clear(Rgb565::BLACK)?;
 
-                if let None = self.ble.chosen_discovered.checked_sub(1) {
-                    self.ble.chosen_discovered = self.ble.discovered.len() - 1;
-                }
+            let footer = if self.settings.visible {
+                "They can see you too!"
+            } else {
+                "You're invisible, turn it back on in Settings"
+            };
+            Text::with_text_style(footer, Point::new(160, 225), small_style, text_style)
+                .draw(&mut self.display)?;
+        }
 
-                self.repaint();
-                return Ok(());
-            }
+        match self.touch() {
             Some(TouchEvent {
                 point,
                 kind: TouchKind::Start,
-            }) if RIGHT_BUTTON_BOUND.contains(*point) && monitors_discovered => {
-                self.display.clear(Rgb565::BLACK)?;
-
-                self.ble.chosen_discovered += 1;
-                if self.ble.chosen_discovered >= self.ble.discovered.len() {
-                    self.ble.chosen_discovered = 0;
-                }
-
-                self.repaint();
-                return Ok(());
+            }) if BACK_BUTTON_BOUND.contains(*point) => {
+                self.change_view(AppView::MainMenu)?;
             }
             _ => (),
         }
+        Ok(())
+    }
+    fn paint_group_cell(&mut self, index: usize) -> Result<()> {
+        let Some(tracked) = self.settings.hr.tracked.get(index) else {
+            return Ok(());
+        };
+        let entry = self
+            .group
+            .get(&tracked.ident.mac)
+            .cloned()
+            .unwrap_or_default();
+        let bound = group_cell_bound(index);
+        let center_x = bound.center().x;
+
+        bound.offset(-2).draw_styled(
+            &PrimitiveStyleBuilder::new()
+                .fill_color(Rgb565::BLACK)
+                .stroke_color(Rgb565::CSS_DIM_GRAY)
+                .stroke_width(1)
+                .build(),
+            &mut self.display,
+        )?;
+
+        let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+        let label = if tracked.label.is_empty() {
+            &tracked.ident.name
+        } else {
+            &tracked.label
+        };
+        Text::with_text_style(
+            label,
+            Point::new(center_x, bound.top_left.y + 22),
+            MonoTextStyle::new(&FONT_10X20, Rgb565::WHITE),
+            text_style,
+        )
+        .draw(&mut self.display)?;
 
+        let live = entry.linked() && !entry.contact_lost;
+        let color = match entry.bpm {
+            Some(bpm) if live => self.settings.zones.zone_for(bpm).color(),
+            _ => Rgb565::CSS_GRAY,
+        };
+        let bpm_style = SevenSegmentStyleBuilder::new()
+            .digit_size(Size::new(20, 40))
+            .digit_spacing(4)
+            .segment_width(4)
+            .segment_color(color)
+            .build();
+        let bpm_text = entry.bpm.map_or(String::from("--"), |bpm| bpm.to_string());
+        Text::with_text_style(
+            &bpm_text,
+            Point::new(center_x, bound.top_left.y + 75),
+            bpm_style,
+            text_style,
+        )
+        .draw(&mut self.display)?;
+
+        let status = match entry.state {
+            Some(state) if !entry.linked() => state.to_string(),
+            None => String::from("Starting"),
+            _ if entry.contact_lost => String::from("No contact"),
+            _ => entry
+                .bpm
+                .map(|bpm| self.settings.zones.zone_for(bpm).label().to_string())
+                .unwrap_or_default(),
+        };
+        Text::with_text_style(
+            &status,
+            Point::new(center_x, bound.top_left.y + 95),
+            MonoTextStyle::new(&FONT_6X10, color),
+            text_style,
+        )
+        .draw(&mut self.display)?;
+        Ok(())
+    }
+    /// Little cell-signal style bars, `bottom_left` being the bottom of the first bar.
+    fn paint_signal_bars(&mut self, bottom_left: Point, bars: u8, color: Rgb565) -> Result<()> {
+        let filled = PrimitiveStyle::with_fill(color);
+        let empty = PrimitiveStyle::with_stroke(Rgb565::CSS_DIM_GRAY, 1);
+        for bar in 0..MAX_BARS {
+            let height = 4 * (bar as u32 + 1);
+            let top_left = bottom_left + Point::new(6 * bar as i32, 1 - height as i32);
+            let style = if bar < bars { &filled } else { &empty };
+            Rectangle::new(top_left, Size::new(4, height)).draw_styled(style, &mut self.display)?;
+        }
         Ok(())
     }
     pub fn main_loop(&mut self) -> Result<()> {
+        self.update_advertising()?;
+        self.poll_config()?;
+        self.poll_time_sync()?;
+        self.poll_monitor()?;
+        self.clock.tick()?;
+        if self.stats_saved_at.elapsed() >= STATS_SAVE_INTERVAL {
+            self.save_stats()?;
+        }
         match self.view {
             AppView::Doodle => {
                 self.doodle()?;
@@ -1191,72 +2644,50 @@ where
             AppView::BadgeDisplay => {
                 self.badge_view()?;
             }
+            AppView::Settings => {
+                self.settings_menu()?;
+            }
+            AppView::Group => {
+                self.group_view()?;
+            }
+            AppView::SessionSummary => {
+                self.session_summary()?;
+            }
+            AppView::Friends => {
+                self.friends_view()?;
+            }
         }
         Ok(())
     }
     pub fn change_view(&mut self, new_view: AppView) -> Result<()> {
+        if !matches!(new_view, AppView::HrSelect) {
+            self.ble.stop_scan();
+        }
+        if !matches!(new_view, AppView::Group) {
+            self.group.clear();
+        }
+        if !matches!(new_view, AppView::Friends) {
+            self.ble.stop_presence_scan();
+        }
+        if !matches!(new_view, AppView::BadgeDisplay) {
+            self.finish_session_log();
+            self.stats.pause();
+            self.save_stats()?;
+        }
         self.repaint_full()?;
         self.view = new_view;
         self.debounce_instant = Instant::now();
 
-        let character_style = MonoTextStyle::new(&FONT_10X20, Rgb565::RED);
-        let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
         // Extra actions based on new view
         match self.view {
             AppView::BadgeDisplay => {
                 self.set_display_to_vertical()?;
-                if let Some(addr) = self.settings.hr.saved.as_ref() {
-                    Text::with_text_style(
-                        "Trying to find\nsaved HR monitor!\nGiving up in 30s...\n\n\nTrash saved device\nto skip this.",
-                        Point::new(240 / 2, 100),
-                        character_style,
-                        text_style,
-                    )
-                    .draw(&mut self.display)?;
-                    let free_stack = unsafe {
-                        esp_idf_hal::sys::uxTaskGetStackHighWaterMark(std::ptr::null_mut())
-                    };
-                    info!("Stack Free: {free_stack}");
-                    // let (hr_tx, hr_rx) = mpsc::sync_channel(5);
-                    // self.hr_rx = Some(hr_rx);
-                    // let res =
-                    //     block_on(async { self.ble.connect_to_monitor(addr.mac, hr_tx).await });
-
-                    let addr = block_on(async { self.ble.scan_for_connect(addr).await })?;
-
-                    if let Some(addr) = addr {
-                        let monitor = MonitorHandle::build(addr, self.delay)?;
-                        if let Ok(MonitorReply::Error(err)) = monitor
-                            .reply_rx
-                            .recv_timeout(std::time::Duration::from_secs(30))
-                        {
-                            self.clear_vertical()?;
-                            Text::with_text_style(
-                                &format!("{err}"),
-                                Point::new(240 / 2, 120),
-                                character_style,
-                                text_style,
-                            )
-                            .draw(&mut self.display)?;
-                            self.delay.delay_ms(10000);
-                            panic!();
-                        }
-
-                        self.monitor = Some(monitor);
-                        // info!("{:?}", self.monitor)
-                    } else {
-                        self.clear_vertical()?;
-                        Text::with_text_style(
-                            &format!("HR Monitor not found!"),
-                            Point::new(240 / 2, 120),
-                            character_style,
-                            text_style,
-                        )
-                        .draw(&mut self.display)?;
-                        self.delay.delay_ms(5000);
-                    }
+                self.start_monitor()?;
+                // New session, the strap's energy count starts over with it
+                if let Some(monitor) = &mut self.monitor {
+                    monitor.command(BleHrCommand::ResetEnergy);
                 }
-                info!("Done.");
+                self.start_session_log();
                 self.clear_vertical()?;
                 self.debounce_duration = Duration::from_millis(1000);
             }
@@ -1266,30 +2697,41 @@ where
             }
             AppView::NameInput => {
                 self.debounce_duration = Duration::from_millis(100);
-                self.username_scratch.clone_from(&self.settings.username);
+                let current = match self.name_target {
+                    NameTarget::Username => Some(&self.settings.username),
+                    NameTarget::GroupLabel(mac) => self
+                        .settings
+                        .hr
+                        .tracked
+                        .iter()
+                        .find(|tracked| tracked.ident.mac == mac)
+                        .map(|tracked| &tracked.label),
+                };
+                self.username_scratch = current.cloned().unwrap_or_default();
+            }
+            AppView::Friends => {
+                // Nothing worth advertising a heart rate for while we're in here
+                self.monitor = None;
+                self.monitor_state = None;
+                self.ble.start_presence_scan()?;
+                self.debounce_duration = Duration::from_millis(500);
+            }
+            AppView::Group => {
+                // The group's monitors get the radio instead
+                self.monitor = None;
+                self.monitor_state = None;
+                self.group.sync(
+                    &self.settings.hr.tracked,
+                    self.settings.hr.monitor_options(),
+                )?;
+                self.debounce_duration = Duration::from_millis(500);
             }
             AppView::HrSelect => {
-                Text::with_text_style(
-                    "Scanning for BLE HR Monitors...\nPlease wait 10s...",
-                    Point::new(320 / 2, 240 / 2),
-                    character_style,
-                    text_style,
-                )
-                .draw(&mut self.display)?;
-
-                self.ble.discovered = block_on(async { self.ble.scan_for_select().await })?;
-
-                info!("{:?}", self.ble.discovered);
-
-                // Filtering out all the nameless monitors
-                // (easy enough to just have the user rescan)
-                self.ble.discovered.retain(|_, name| !name.is_empty());
-
-                self.ble.chosen_discovered = 0;
-
-                // Repaint again since we drew here
-                self.repaint_full()?;
-                // self.change_view(AppView::MainMenu)?;
+                self.saved_info_open = false;
+                // Scanning needs the radio to itself
+                self.monitor = None;
+                self.monitor_state = None;
+                self.ble.start_scan()?;
             }
             _ => (),
         }
@@ -1365,7 +2807,21 @@ where
 const SPACING: usize = 30;
 
 trait MenuTest: strum::VariantArray + Clone + Copy {
+    const ITEM_SPACING: usize = SPACING;
+    /// Items shown at once, anything past this goes on another page.
+    const PAGE_SIZE: usize = usize::MAX;
+    fn pages() -> usize {
+        Self::VARIANTS.len().div_ceil(Self::PAGE_SIZE)
+    }
     fn from_touch(offset: Option<Point>, touch: &Point, font: &MonoFont) -> Option<Self> {
+        Self::from_touch_on_page(offset, 0, touch, font)
+    }
+    fn from_touch_on_page(
+        offset: Option<Point>,
+        page: usize,
+        touch: &Point,
+        font: &MonoFont,
+    ) -> Option<Self> {
         // let adjusted_point = Point::new((touch.x - offset.x).max(0), (touch.y - offset.y).max(0));
         // Simple bound check
         let font_correction = -(font.character_size.height as i32);
@@ -1382,7 +2838,7 @@ trait MenuTest: strum::VariantArray + Clone + Copy {
                 x: _,
                 y: bottom_bound,
             },
-        ) in Self::vert_regions(offset)
+        ) in Self::page_regions(offset, page)
         {
             if touch.y >= top_bound && touch.y <= bottom_bound {
                 return Some(variant);
@@ -1397,20 +2853,35 @@ trait MenuTest: strum::VariantArray + Clone + Copy {
     //     Self::vert_regions(Some(offset))
     // }
     fn vert_regions(offset: Option<Point>) -> impl Iterator<Item = (Self, Point)> {
+        Self::page_regions(offset, 0)
+    }
+    fn page_regions(offset: Option<Point>, page: usize) -> impl Iterator<Item = (Self, Point)> {
         Self::VARIANTS
             .iter()
+            .skip(page.saturating_mul(Self::PAGE_SIZE))
+            .take(Self::PAGE_SIZE)
             .enumerate()
             .map(move |(index, &variant)| {
                 if let Some(Point { x, y }) = offset {
-                    (variant, Point::new(x, (index * SPACING) as i32 + y))
+                    (
+                        variant,
+                        Point::new(x, (index * Self::ITEM_SPACING) as i32 + y),
+                    )
                 } else {
-                    (variant, Point::new(0, (index * SPACING) as i32))
+                    (variant, Point::new(0, (index * Self::ITEM_SPACING) as i32))
                 }
             })
     }
 }
 
-impl MenuTest for MainMenu {}
+impl MenuTest for MainMenu {
+    // Squeezed a bit to fit above the footer
+    const ITEM_SPACING: usize = 21;
+}
+impl MenuTest for SettingsMenu {
+    const ITEM_SPACING: usize = 24;
+    const PAGE_SIZE: usize = 8;
+}
 
 #[derive(strum_macros::Display, strum_macros::VariantArray, Clone, Copy)]
 enum MainMenu {
@@ -1423,6 +2894,97 @@ enum MainMenu {
     #[strum(to_string = "BLE HR Monitor Selection")]
     HrSelect,
     Doodle,
+    Settings,
+    #[strum(to_string = "Group Display")]
+    Group,
+    #[strum(to_string = "Friends Nearby")]
+    Friends,
+    #[strum(to_string = "Session Summary")]
+    SessionSummary,
+}
+
+#[derive(strum_macros::Display, strum_macros::VariantArray, Clone, Copy)]
+enum SettingsMenu {
+    #[strum(to_string = "HR Source")]
+    Source,
+    #[strum(to_string = "Record to SD")]
+    Record,
+    #[strum(to_string = "RR Filter")]
+    FilterRr,
+    #[strum(to_string = "Rebroadcast HR")]
+    Rebroadcast,
+    #[strum(to_string = "Match by Name")]
+    NameFallback,
+    #[strum(to_string = "Pair Monitors")]
+    Bond,
+    #[strum(to_string = "Low Battery")]
+    LowBattery,
+    #[strum(to_string = "Visible to Friends")]
+    Visible,
+    #[strum(to_string = "Phone Setup")]
+    PhoneSetup,
+    #[strum(to_string = "UTC Offset")]
+    UtcOffset,
+    Theme,
+    #[strum(to_string = "Badge Layout")]
+    BadgeLayout,
+    #[strum(to_string = "Zones")]
+    ZoneModel,
+    #[strum(to_string = "Resting HR")]
+    RestingHr,
+    #[strum(to_string = "Max HR")]
+    MaxHr,
+}
+
+/// Scales a colour towards black, `brightness` from 0.0 to 1.0.
+fn dim(color: Rgb565, brightness: f32) -> Rgb565 {
+    let scale = |channel: u8| (channel as f32 * brightness).round() as u8;
+    Rgb565::new(scale(color.r()), scale(color.g()), scale(color.b()))
+}
+
+/// Steps to the next variant, wrapping around to the first.
+fn cycle_variant<T: VariantArray + PartialEq + Copy>(current: T) -> T {
+    let index = T::VARIANTS
+        .iter()
+        .position(|v| *v == current)
+        .map_or(0, |i| (i + 1) % T::VARIANTS.len());
+    T::VARIANTS[index]
+}
+
+/// 2x2 grid under the title, one cell per tracked monitor.
+fn group_cell_bound(index: usize) -> Rectangle {
+    let column = (index % 2) as i32;
+    let row = (index / 2) as i32;
+    Rectangle::new(
+        Point::new(column * 160, 30 + row * 105),
+        Size::new(160, 105),
+    )
+}
+
+/// Touch/paint area of a row in the monitor picker list.
+fn picker_row_bound(row: usize) -> Rectangle {
+    Rectangle::new(Point::new(0, 30 + 22 * row as i32), Size::new(280, 22))
+}
+
+/// `1:02:03`, or `2:03` under an hour.
+fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{secs:02}")
+    } else {
+        format!("{minutes}:{secs:02}")
+    }
+}
+
+/// Steps up by `step`, wrapping back to `min` once past `max`.
+fn cycle_value(current: u8, min: u8, max: u8, step: u8) -> u8 {
+    let next = current.saturating_add(step);
+    if next > max || next < min {
+        min
+    } else {
+        next
+    }
 }
 
 #[derive(
@@ -1471,6 +3033,63 @@ impl From<&SlideshowLength> for Duration {
     }
 }
 
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    strum_macros::VariantArray,
+    strum_macros::Display,
+    Serialize,
+    Deserialize,
+)]
+pub enum Theme {
+    #[default]
+    Red,
+    Blue,
+    Green,
+    Pink,
+    Orange,
+    Purple,
+}
+
+impl Theme {
+    /// Titles, outlines and the like.
+    pub fn accent(&self) -> Rgb565 {
+        match self {
+            Theme::Red => Rgb565::RED,
+            Theme::Blue => Rgb565::CSS_DODGER_BLUE,
+            Theme::Green => Rgb565::CSS_LIME_GREEN,
+            Theme::Pink => Rgb565::CSS_HOT_PINK,
+            Theme::Orange => Rgb565::CSS_ORANGE,
+            Theme::Purple => Rgb565::CSS_MEDIUM_PURPLE,
+        }
+    }
+}
+
+/// What goes along the bottom of the badge.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    strum_macros::VariantArray,
+    strum_macros::Display,
+    Serialize,
+    Deserialize,
+)]
+pub enum BadgeLayout {
+    /// Heart, BPM digits and a small history plot.
+    #[default]
+    Classic,
+    /// Sweeping ECG style trace, like a hospital monitor.
+    Monitor,
+}
+
 // #[derive(Debug, Clone, Copy)]
 // enum Touch {
 //     Pressed(Point),
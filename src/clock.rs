@@ -0,0 +1,334 @@
+// Wall-clock time. There's no RTC on the board, so the clock starts at 1970 on
+// every boot until a phone tells us otherwise over the Current Time Service.
+//
+// Calendar math's done by hand since there's nothing else here that needs a
+// whole date crate.
+
+use std::time::{Duration, SystemTime};
+
+/// The clock starts at 1970 on boot, anything before 2024 means it hasn't been set.
+const CLOCK_SET_AFTER: Duration = Duration::from_secs(1_704_067_200);
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// UTC offsets only go this far out (Kiribati, Baker Island).
+pub const MIN_UTC_OFFSET_MIN: i16 = -12 * 60;
+pub const MAX_UTC_OFFSET_MIN: i16 = 14 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ClockError {
+    #[error("Current Time is {0} bytes, expected at least 7")]
+    TooShort(usize),
+    #[error("Phone says it's {0:?}, which isn't a date")]
+    BadDate(CurrentTime),
+    #[error("Phone doesn't know its own time zone")]
+    UnknownZone,
+}
+
+/// A moment in time, and the offset to show it in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallClock {
+    /// Since the Unix epoch.
+    pub unix: Duration,
+    pub utc_offset_min: i16,
+}
+
+impl WallClock {
+    /// What the system clock says, if it's been set.
+    pub fn now(utc_offset_min: i16) -> Option<Self> {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .filter(|since| *since > CLOCK_SET_AFTER)
+            .map(|unix| Self {
+                unix,
+                utc_offset_min,
+            })
+    }
+    fn local(&self) -> DateTime {
+        let local = self.unix.as_secs() as i64 + self.utc_offset_min as i64 * 60;
+        DateTime::from_unix(local)
+    }
+    /// `14:03`
+    pub fn hours_minutes(&self) -> String {
+        let local = self.local();
+        format!("{:02}:{:02}", local.hour, local.minute)
+    }
+    /// `2024-12-22T14:03:09+02:00`
+    pub fn iso8601(&self) -> String {
+        let local = self.local();
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+            local.year,
+            local.month,
+            local.day,
+            local.hour,
+            local.minute,
+            local.second,
+            format_utc_offset(self.utc_offset_min),
+        )
+    }
+}
+
+/// `+02:00`, `-09:30`
+pub fn format_utc_offset(offset_min: i16) -> String {
+    let sign = if offset_min < 0 { '-' } else { '+' };
+    let offset = offset_min.unsigned_abs();
+    format!("{sign}{:02}:{:02}", offset / 60, offset % 60)
+}
+
+/// Reverse of `format_utc_offset`, also takes the trailing offset of an ISO 8601 time.
+pub fn parse_utc_offset(text: &str) -> Option<i16> {
+    let offset = text.get(text.len().checked_sub(6)?..)?;
+    let (sign, rest) = offset.split_at(1);
+    let (hours, minutes) = rest.split_once(':')?;
+    let minutes: i16 = hours.parse::<i16>().ok()? * 60 + minutes.parse::<i16>().ok()?;
+    let minutes = match sign {
+        "+" => minutes,
+        "-" => -minutes,
+        _ => return None,
+    };
+    (MIN_UTC_OFFSET_MIN..=MAX_UTC_OFFSET_MIN)
+        .contains(&minutes)
+        .then_some(minutes)
+}
+
+/// Half an hour later, wrapping around to the furthest behind.
+pub fn next_utc_offset(offset_min: i16) -> i16 {
+    let next = offset_min.saturating_add(30);
+    if (MIN_UTC_OFFSET_MIN..=MAX_UTC_OFFSET_MIN).contains(&next) {
+        next
+    } else {
+        MIN_UTC_OFFSET_MIN
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DateTime {
+    year: i64,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+impl DateTime {
+    fn from_unix(secs: i64) -> Self {
+        let days = secs.div_euclid(SECS_PER_DAY);
+        let of_day = secs.rem_euclid(SECS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: (of_day / 3600) as u8,
+            minute: (of_day / 60 % 60) as u8,
+            second: (of_day % 60) as u8,
+        }
+    }
+}
+
+// Both from http://howardhinnant.github.io/date_algorithms.html
+
+/// Days since 1970-01-01.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The Current Time characteristic (0x2A2B), which is the phone's local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// 1/256ths of a second, 0 if the phone leaves it out.
+    pub fractions256: u8,
+}
+
+impl CurrentTime {
+    pub fn parse(data: &[u8]) -> Result<Self, ClockError> {
+        // Exact Time 256 is 9 bytes, plus a byte of adjust reason, but only the date's needed
+        let [year_low, year_high, month, day, hour, minute, second, rest @ ..] = data else {
+            return Err(ClockError::TooShort(data.len()));
+        };
+        let time = Self {
+            year: u16::from_le_bytes([*year_low, *year_high]),
+            month: *month,
+            day: *day,
+            hour: *hour,
+            minute: *minute,
+            second: *second,
+            // Skipping over day of week
+            fractions256: rest.get(1).copied().unwrap_or(0),
+        };
+        let valid_date = (1582..=9999).contains(&time.year)
+            && (1..=12).contains(&time.month)
+            && time.day >= 1
+            && time.day <= days_in_month(time.year as i64, time.month)
+            && time.hour < 24
+            && time.minute < 60
+            && time.second < 60;
+        if !valid_date {
+            return Err(ClockError::BadDate(time));
+        }
+        Ok(time)
+    }
+    /// Since the Unix epoch, given how far the phone's local time is from UTC.
+    pub fn to_unix(&self, utc_offset_min: i16) -> Duration {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        let local_secs = days * SECS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64;
+        let secs = (local_secs - utc_offset_min as i64 * 60).max(0) as u64;
+        Duration::from_secs(secs) + Duration::from_millis(self.fractions256 as u64 * 1000 / 256)
+    }
+}
+
+/// Local Time Information (0x2A0F), time zone and DST together as one offset.
+pub fn parse_local_time_info(data: &[u8]) -> Result<i16, ClockError> {
+    const UNKNOWN_ZONE: i8 = -128;
+    const UNKNOWN_DST: u8 = 255;
+    let [zone, dst, ..] = data else {
+        return Err(ClockError::UnknownZone);
+    };
+    let zone = *zone as i8;
+    if zone == UNKNOWN_ZONE {
+        return Err(ClockError::UnknownZone);
+    }
+    // Both in 15 minute steps
+    let dst = if *dst == UNKNOWN_DST { 0 } else { *dst as i16 };
+    let offset = (zone as i16 + dst) * 15;
+    Ok(offset.clamp(MIN_UTC_OFFSET_MIN, MAX_UTC_OFFSET_MIN))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        civil_from_days, days_from_civil, format_utc_offset, next_utc_offset,
+        parse_local_time_info, parse_utc_offset, ClockError, CurrentTime, WallClock,
+        MAX_UTC_OFFSET_MIN, MIN_UTC_OFFSET_MIN,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn clock_calendar_round_trips() {
+        assert_eq!(0, days_from_civil(1970, 1, 1));
+        assert_eq!(20_079, days_from_civil(2024, 12, 22));
+        assert_eq!(11_016, days_from_civil(2000, 2, 29));
+        for days in [-1, 0, 59, 365, 11_016, 20_079, 100_000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days, days_from_civil(year, month, day));
+        }
+        assert_eq!((1969, 12, 31), civil_from_days(-1));
+    }
+
+    #[test]
+    fn clock_parses_current_time() {
+        // 2024-12-22 14:03:09, Sunday, half a second, manual adjust
+        let data = [0xE8, 0x07, 12, 22, 14, 3, 9, 7, 128, 1];
+        let time = CurrentTime::parse(&data).unwrap();
+        assert_eq!(128, time.fractions256);
+        // Phone's at UTC+2
+        assert_eq!(
+            Duration::from_millis(1_734_868_989_500),
+            time.to_unix(2 * 60)
+        );
+        assert_eq!(Duration::from_millis(1_734_876_189_500), time.to_unix(0));
+        // Fractions are optional
+        assert_eq!(0, CurrentTime::parse(&data[..7]).unwrap().fractions256);
+    }
+
+    #[test]
+    fn clock_rejects_nonsense_time() {
+        assert_eq!(
+            Err(ClockError::TooShort(3)),
+            CurrentTime::parse(&[0xE8, 7, 12])
+        );
+        // Year 0 means the phone doesn't know
+        assert!(CurrentTime::parse(&[0, 0, 12, 22, 14, 3, 9]).is_err());
+        // Not a leap year
+        assert!(CurrentTime::parse(&[0xE9, 0x07, 2, 29, 0, 0, 0]).is_err());
+        assert!(CurrentTime::parse(&[0xE8, 0x07, 2, 29, 0, 0, 0]).is_ok());
+        assert!(CurrentTime::parse(&[0xE8, 0x07, 1, 1, 24, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn clock_local_time_info() {
+        // UTC+1 with an hour of DST
+        assert_eq!(Ok(120), parse_local_time_info(&[4, 4]));
+        // UTC-9:30, DST unknown
+        assert_eq!(Ok(-570), parse_local_time_info(&[(-38i8) as u8, 255]));
+        assert_eq!(
+            Err(ClockError::UnknownZone),
+            parse_local_time_info(&[(-128i8) as u8, 0])
+        );
+        assert_eq!(Err(ClockError::UnknownZone), parse_local_time_info(&[4]));
+    }
+
+    #[test]
+    fn clock_formats_local() {
+        let clock = WallClock {
+            unix: Duration::from_millis(1_734_868_989_500),
+            utc_offset_min: 2 * 60,
+        };
+        assert_eq!("14:03", clock.hours_minutes());
+        assert_eq!("2024-12-22T14:03:09+02:00", clock.iso8601());
+        let behind = WallClock {
+            utc_offset_min: -570,
+            ..clock
+        };
+        assert_eq!("2024-12-22T02:33:09-09:30", behind.iso8601());
+
+        assert_eq!("+00:00", format_utc_offset(0));
+        for offset in [-720, -570, 0, 330, 840] {
+            assert_eq!(Some(offset), parse_utc_offset(&format_utc_offset(offset)));
+        }
+        assert_eq!(Some(120), parse_utc_offset("2024-12-22T14:03:09+02:00"));
+        assert_eq!(None, parse_utc_offset("+15:00"));
+        assert_eq!(None, parse_utc_offset("02:00"));
+
+        assert_eq!(-90, next_utc_offset(-120));
+        assert_eq!(MIN_UTC_OFFSET_MIN, next_utc_offset(MAX_UTC_OFFSET_MIN));
+        // Odd offsets from a phone still step and wrap sanely
+        assert_eq!(345 + 30, next_utc_offset(345));
+        assert_eq!(MIN_UTC_OFFSET_MIN, next_utc_offset(i16::MAX));
+    }
+}
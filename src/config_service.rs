@@ -0,0 +1,358 @@
+// Custom GATT service so the badge can be set up from a phone, with a
+// generic BLE app like nRF Connect instead of rolling characters one by one.
+//
+// Every value is plain UTF-8 text, so it's readable/writable as-is:
+//   Username        "Goobinski"
+//   Slideshow       "Off", "5s", "10s", "30s", "1m", "3m"
+//   Saved monitor   "AA:BB:CC:DD:EE:FF Polar H10", name optional, empty to forget it
+//   Theme           "Red", "Blue", ...
+//
+// Writes are only checked here, the app applies them from its own loop so they
+// take the same path as changes made on the badge. They're refused outright
+// unless phone setup is on and the link's encrypted, since anything can
+// connect while we're connectable for rebroadcast.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, Receiver, SyncSender, TrySendError},
+    Arc,
+};
+
+use esp32_nimble::{
+    utilities::{mutex::Mutex, BleUuid},
+    uuid128, BLECharacteristic, BLEDevice, DescriptorProperties, NimbleProperties,
+};
+use log::{info, warn};
+use strum::VariantArray;
+
+use crate::{
+    app::{SlideshowLength, Theme, INPUT_CHARS},
+    errors::Result,
+    heart_rate::ble::BleIdents,
+    settings::Settings,
+};
+
+const CONFIG_SERVICE_UUID: BleUuid = uuid128!("4d464642-0000-4000-8000-6e756c6c7374");
+const USERNAME_UUID: BleUuid = uuid128!("4d464642-0001-4000-8000-6e756c6c7374");
+const SLIDESHOW_UUID: BleUuid = uuid128!("4d464642-0002-4000-8000-6e756c6c7374");
+const SAVED_MONITOR_UUID: BleUuid = uuid128!("4d464642-0003-4000-8000-6e756c6c7374");
+const THEME_UUID: BleUuid = uuid128!("4d464642-0004-4000-8000-6e756c6c7374");
+
+/// Characteristic User Description, so apps show a label instead of a bare UUID.
+const USER_DESCRIPTION_UUID: BleUuid = BleUuid::from_uuid16(0x2901);
+
+/// Anything longer doesn't fit on the name input screen.
+pub const MAX_USERNAME_CHARS: usize = 32;
+
+/// Writes the app hasn't gotten around to yet.
+const PENDING_WRITES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::VariantArray)]
+pub enum ConfigField {
+    Username,
+    Slideshow,
+    SavedMonitor,
+    Theme,
+}
+
+impl ConfigField {
+    fn uuid(&self) -> BleUuid {
+        match self {
+            ConfigField::Username => USERNAME_UUID,
+            ConfigField::Slideshow => SLIDESHOW_UUID,
+            ConfigField::SavedMonitor => SAVED_MONITOR_UUID,
+            ConfigField::Theme => THEME_UUID,
+        }
+    }
+    fn description(&self) -> &'static str {
+        match self {
+            ConfigField::Username => "Username",
+            ConfigField::Slideshow => "Slideshow length",
+            ConfigField::SavedMonitor => "Saved HR monitor",
+            ConfigField::Theme => "Theme",
+        }
+    }
+    /// What a read of this field returns.
+    pub fn value(&self, settings: &Settings) -> String {
+        match self {
+            ConfigField::Username => settings.username.clone(),
+            ConfigField::Slideshow => settings.slideshow_length_sec.to_string(),
+            ConfigField::SavedMonitor => settings
+                .hr
+                .saved
+                .as_ref()
+                .map(format_monitor)
+                .unwrap_or_default(),
+            ConfigField::Theme => settings.theme.to_string(),
+        }
+    }
+    /// Checks a write, turning it into something the app can apply.
+    pub fn parse(&self, data: &[u8]) -> std::result::Result<ConfigWrite, ConfigError> {
+        let text = std::str::from_utf8(data).map_err(|_| ConfigError::NotUtf8)?;
+        match self {
+            ConfigField::Username => validate_username(text).map(ConfigWrite::Username),
+            ConfigField::Slideshow => parse_variant(text)
+                .map(ConfigWrite::Slideshow)
+                .ok_or_else(|| ConfigError::UnknownSlideshow(text.to_string())),
+            ConfigField::SavedMonitor => parse_monitor(text).map(ConfigWrite::SavedMonitor),
+            ConfigField::Theme => parse_variant(text)
+                .map(ConfigWrite::Theme)
+                .ok_or_else(|| ConfigError::UnknownTheme(text.to_string())),
+        }
+    }
+}
+
+/// A checked change from the phone, waiting for the app to apply it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigWrite {
+    Username(String),
+    Slideshow(SlideshowLength),
+    SavedMonitor(Option<BleIdents>),
+    Theme(Theme),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Value isn't valid UTF-8")]
+    NotUtf8,
+    #[error("Username can't be blank")]
+    EmptyUsername,
+    #[error("Username is {0} characters, max is {MAX_USERNAME_CHARS}")]
+    UsernameTooLong(usize),
+    #[error("Username can't contain {0:?}")]
+    UsernameChar(char),
+    #[error("Unknown slideshow length {0:?}")]
+    UnknownSlideshow(String),
+    #[error("Couldn't parse monitor {0:?}, expected \"AA:BB:CC:DD:EE:FF Name\"")]
+    BadMonitor(String),
+    #[error("Unknown theme {0:?}")]
+    UnknownTheme(String),
+}
+
+/// Same rules as the name input screen, only characters it could've rolled to.
+pub fn validate_username(username: &str) -> std::result::Result<String, ConfigError> {
+    if username.trim().is_empty() {
+        return Err(ConfigError::EmptyUsername);
+    }
+    let length = username.chars().count();
+    if length > MAX_USERNAME_CHARS {
+        return Err(ConfigError::UsernameTooLong(length));
+    }
+    if let Some(bad) = username.chars().find(|c| !INPUT_CHARS.contains(c)) {
+        return Err(ConfigError::UsernameChar(bad));
+    }
+    Ok(username.to_string())
+}
+
+/// Matches the variant's display name, ignoring case.
+fn parse_variant<T: VariantArray + ToString + Copy>(text: &str) -> Option<T> {
+    let text = text.trim();
+    T::VARIANTS
+        .iter()
+        .find(|variant| variant.to_string().eq_ignore_ascii_case(text))
+        .copied()
+}
+
+fn format_monitor(ident: &BleIdents) -> String {
+    let mac: Vec<String> = ident.mac.iter().map(|byte| format!("{byte:02X}")).collect();
+    let mac = mac.join(":");
+    if ident.name.is_empty() {
+        mac
+    } else {
+        format!("{mac} {}", ident.name)
+    }
+}
+
+/// Blank forgets the saved monitor.
+fn parse_monitor(text: &str) -> std::result::Result<Option<BleIdents>, ConfigError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let bad = || ConfigError::BadMonitor(text.to_string());
+    let (mac_text, name) = text.split_once(' ').unwrap_or((text, ""));
+    let mut mac = [0; 6];
+    let mut octets = mac_text.split(':');
+    for byte in mac.iter_mut() {
+        let octet = octets
+            .next()
+            .filter(|octet| octet.len() == 2)
+            .ok_or_else(bad)?;
+        *byte = u8::from_str_radix(octet, 16).map_err(|_| bad())?;
+    }
+    if octets.next().is_some() {
+        return Err(bad());
+    }
+    // No way to know the address type from text, so it'll match either
+    Ok(Some(BleIdents {
+        mac,
+        name: name.trim().to_string(),
+        addr_type: None,
+    }))
+}
+
+pub struct ConfigService {
+    characteristics: Vec<(ConfigField, Arc<Mutex<BLECharacteristic>>)>,
+    /// Checked writes from the phone, for the app to apply.
+    pub write_rx: Receiver<ConfigWrite>,
+    /// Values as of the last `publish`, to skip setting unchanged ones.
+    published: Vec<String>,
+    /// Shared with the write callbacks, mirrors the phone setup setting.
+    enabled: Arc<AtomicBool>,
+}
+
+impl ConfigService {
+    /// Registers the GATT service.
+    ///
+    /// NimBLE can't unregister services, so only build this once.
+    pub fn build() -> Result<Self> {
+        let (write_tx, write_rx) = mpsc::sync_channel(PENDING_WRITES);
+        let enabled = Arc::new(AtomicBool::new(false));
+        let server = BLEDevice::take().get_server();
+        let service = server.create_service(CONFIG_SERVICE_UUID);
+
+        let mut characteristics = Vec::with_capacity(ConfigField::VARIANTS.len());
+        for field in ConfigField::VARIANTS {
+            let characteristic = service.lock().create_characteristic(
+                field.uuid(),
+                // Pairs first if the phone hasn't already
+                NimbleProperties::READ | NimbleProperties::WRITE | NimbleProperties::WRITE_ENC,
+            );
+            characteristic
+                .lock()
+                .create_descriptor(USER_DESCRIPTION_UUID, DescriptorProperties::READ)
+                .lock()
+                .set_value(field.description().as_bytes());
+
+            let write_tx: SyncSender<ConfigWrite> = write_tx.clone();
+            let enabled = enabled.clone();
+            characteristic.lock().on_write(move |args| {
+                if !enabled.load(Ordering::Relaxed) {
+                    warn!("Phone setup's off, rejecting {field:?} write");
+                    args.reject();
+                    return;
+                }
+                if !args.desc().encrypted() {
+                    warn!("Rejecting {field:?} write over an unencrypted link");
+                    args.reject();
+                    return;
+                }
+                let write = match field.parse(args.recv_data()) {
+                    Ok(write) => write,
+                    Err(e) => {
+                        warn!("Rejected {field:?} write: {e}");
+                        args.reject();
+                        return;
+                    }
+                };
+                match write_tx.try_send(write) {
+                    Ok(()) => (),
+                    Err(TrySendError::Full(_)) => {
+                        warn!("Too many config writes at once, rejecting {field:?}");
+                        args.reject();
+                    }
+                    // App's gone, nothing to do
+                    Err(TrySendError::Disconnected(_)) => args.reject(),
+                }
+            });
+            characteristics.push((*field, characteristic));
+        }
+        info!("Config service registered");
+
+        Ok(Self {
+            characteristics,
+            write_rx,
+            published: Vec::new(),
+            enabled,
+        })
+    }
+    /// Whether writes get accepted at all.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+    /// Updates what reads return, only touching values that changed.
+    pub fn publish(&mut self, settings: &Settings) {
+        let values: Vec<String> = self
+            .characteristics
+            .iter()
+            .map(|(field, _)| field.value(settings))
+            .collect();
+        if values == self.published {
+            return;
+        }
+        for ((_, characteristic), value) in self.characteristics.iter().zip(&values) {
+            characteristic.lock().set_value(value.as_bytes());
+        }
+        self.published = values;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_username, ConfigError, ConfigField, ConfigWrite, MAX_USERNAME_CHARS};
+    use crate::{
+        app::{SlideshowLength, Theme},
+        heart_rate::ble::BleIdents,
+        settings::Settings,
+    };
+
+    #[test]
+    fn config_username_rules() {
+        assert_eq!(Ok(String::from("Bingus")), validate_username("Bingus"));
+        assert_eq!(Err(ConfigError::EmptyUsername), validate_username("   "));
+        assert_eq!(
+            Err(ConfigError::UsernameChar('é')),
+            validate_username("Goobinské")
+        );
+        let long = "a".repeat(MAX_USERNAME_CHARS + 1);
+        assert_eq!(
+            Err(ConfigError::UsernameTooLong(MAX_USERNAME_CHARS + 1)),
+            validate_username(&long)
+        );
+        assert_eq!(
+            Err(ConfigError::NotUtf8),
+            ConfigField::Username.parse(&[0xFF])
+        );
+    }
+
+    #[test]
+    fn config_variants_by_name() {
+        assert_eq!(
+            Ok(ConfigWrite::Slideshow(SlideshowLength::ThirtySec)),
+            ConfigField::Slideshow.parse(b"30S\n")
+        );
+        assert_eq!(
+            Ok(ConfigWrite::Theme(Theme::Blue)),
+            ConfigField::Theme.parse(b"blue")
+        );
+        assert!(ConfigField::Theme.parse(b"Plaid").is_err());
+    }
+
+    #[test]
+    fn config_monitor_round_trip() {
+        let mut settings = Settings::default();
+        settings.hr.saved = Some(BleIdents {
+            mac: [0xAA, 0xBB, 0xCC, 0x01, 0x02, 0x03],
+            name: String::from("Polar H10 1234"),
+            addr_type: None,
+        });
+        let value = ConfigField::SavedMonitor.value(&settings);
+        assert_eq!("AA:BB:CC:01:02:03 Polar H10 1234", value);
+        assert_eq!(
+            Ok(ConfigWrite::SavedMonitor(settings.hr.saved.clone())),
+            ConfigField::SavedMonitor.parse(value.as_bytes())
+        );
+        assert_eq!(
+            Ok(ConfigWrite::SavedMonitor(None)),
+            ConfigField::SavedMonitor.parse(b"")
+        );
+        for bad in [
+            "AA:BB:CC",
+            "AA:BB:CC:01:02:03:04",
+            "AA:BB:CC:01:02:G3",
+            "AABBCC010203",
+        ] {
+            assert!(ConfigField::SavedMonitor.parse(bad.as_bytes()).is_err());
+        }
+    }
+}
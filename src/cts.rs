@@ -0,0 +1,311 @@
+// Current Time Service client. Phones have CTS (0x1805), so whenever one
+// connects (for phone setup, usually) we read the time off it and set our clock.
+//
+// The phone's the central here, so there's no `BLEClient` to go through.
+// Reads go straight to NimBLE over the connection the phone already made.
+//
+// The last known time goes into littlefs every so often, so after a reboot the
+// clock's at least in the right ballpark until the next sync.
+
+use std::{
+    ffi::c_void,
+    fs,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use embassy_time::Instant;
+use esp32_nimble::BLEDevice;
+use esp_idf_sys as sys;
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    clock::{parse_local_time_info, ClockError, CurrentTime, WallClock},
+    errors::Result,
+    littlefs::paths::CLOCK_PATH,
+};
+
+const CURRENT_TIME_UUID: u16 = 0x2A2B;
+const LOCAL_TIME_INFO_UUID: u16 = 0x2A0F;
+
+/// Let the phone finish looking around our services before asking it things.
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+/// How long to wait on the phone for each read.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Long enough for the phone to show a pairing prompt and someone to tap it.
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often to save the time we're at, for estimating after a reboot.
+const SAVE_INTERVAL: embassy_time::Duration = embassy_time::Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TimeSyncError {
+    #[error("Central doesn't have the Current Time Service")]
+    NoService,
+    #[error("Central wants to pair first")]
+    NeedsPairing,
+    #[error("Central didn't answer in time")]
+    Timeout,
+    #[error("NimBLE returned {0}")]
+    Nimble(i32),
+    #[error(transparent)]
+    Clock(#[from] ClockError),
+}
+
+/// What a phone said the time was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhoneTime {
+    pub time: CurrentTime,
+    /// `None` if it doesn't have Local Time Information, or doesn't know its zone.
+    pub utc_offset_min: Option<i16>,
+}
+
+pub struct TimeSync {
+    pub result_rx: Receiver<std::result::Result<PhoneTime, TimeSyncError>>,
+}
+
+impl TimeSync {
+    /// Spawns a worker that tries to sync with every central that connects.
+    pub fn build() -> Result<Self> {
+        let (conn_tx, conn_rx) = mpsc::sync_channel::<u16>(2);
+        let (result_tx, result_rx) = mpsc::sync_channel(2);
+
+        let server = BLEDevice::take().get_server();
+        server.on_connect(move |_server, desc| {
+            info!("Central connected: {:?}", desc.address());
+            // This is NimBLE's task, the worker does the waiting
+            _ = conn_tx.try_send(desc.conn_handle());
+        });
+
+        std::thread::Builder::new()
+            .stack_size(4000)
+            .spawn(move || {
+                for conn_handle in conn_rx {
+                    std::thread::sleep(SETTLE_DELAY);
+                    if result_tx.send(sync_from(conn_handle)).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self { result_rx })
+    }
+}
+
+fn sync_from(conn_handle: u16) -> std::result::Result<PhoneTime, TimeSyncError> {
+    let data = match read_by_uuid(conn_handle, CURRENT_TIME_UUID) {
+        Err(TimeSyncError::NeedsPairing) => {
+            info!("Central wants to pair before telling us the time");
+            let rc = unsafe { sys::ble_gap_security_initiate(conn_handle) };
+            if rc != 0 {
+                return Err(TimeSyncError::Nimble(rc));
+            }
+            let started = Instant::now();
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+                match read_by_uuid(conn_handle, CURRENT_TIME_UUID) {
+                    Err(TimeSyncError::NeedsPairing)
+                        if started.elapsed().as_secs() < PAIRING_TIMEOUT.as_secs() => {}
+                    result => break result?,
+                }
+            }
+        }
+        result => result?,
+    };
+    let time = CurrentTime::parse(&data)?;
+    let utc_offset_min = read_by_uuid(conn_handle, LOCAL_TIME_INFO_UUID)
+        .ok()
+        .and_then(|data| parse_local_time_info(&data).ok());
+    Ok(PhoneTime {
+        time,
+        utc_offset_min,
+    })
+}
+
+type ReadResult = std::result::Result<Vec<u8>, i32>;
+
+/// Where the read in flight reports back to, there's only ever one.
+static READ_TX: Mutex<Option<SyncSender<ReadResult>>> = Mutex::new(None);
+
+/// Reads a characteristic without discovering services first, a single ATT request.
+fn read_by_uuid(conn_handle: u16, uuid16: u16) -> std::result::Result<Vec<u8>, TimeSyncError> {
+    let (read_tx, read_rx) = mpsc::sync_channel(4);
+    *READ_TX.lock().unwrap() = Some(read_tx);
+
+    let uuid = sys::ble_uuid16_t {
+        u: sys::ble_uuid_t {
+            type_: sys::BLE_UUID_TYPE_16 as u8,
+        },
+        value: uuid16,
+    };
+    let rc = unsafe {
+        sys::ble_gattc_read_by_uuid(
+            conn_handle,
+            1,
+            0xFFFF,
+            &uuid.u,
+            Some(on_read),
+            std::ptr::null_mut(),
+        )
+    };
+    let result = if rc != 0 {
+        Err(TimeSyncError::Nimble(rc))
+    } else {
+        match read_rx.recv_timeout(READ_TIMEOUT) {
+            Ok(Ok(data)) => Ok(data),
+            Ok(Err(status)) => Err(read_error(status)),
+            Err(_) => Err(TimeSyncError::Timeout),
+        }
+    };
+    *READ_TX.lock().unwrap() = None;
+    result
+}
+
+fn read_error(status: i32) -> TimeSyncError {
+    let att = |error: u32| (sys::BLE_HS_ERR_ATT_BASE + error) as i32;
+    if status == sys::BLE_HS_EDONE as i32 || status == att(sys::BLE_ATT_ERR_ATTR_NOT_FOUND) {
+        // Finished without finding anything
+        TimeSyncError::NoService
+    } else if status == att(sys::BLE_ATT_ERR_INSUFFICIENT_AUTHEN)
+        || status == att(sys::BLE_ATT_ERR_INSUFFICIENT_ENC)
+    {
+        TimeSyncError::NeedsPairing
+    } else {
+        TimeSyncError::Nimble(status)
+    }
+}
+
+/// Called from NimBLE's task once per matching attribute, then once more with `BLE_HS_EDONE`.
+unsafe extern "C" fn on_read(
+    _conn_handle: u16,
+    error: *const sys::ble_gatt_error,
+    attr: *mut sys::ble_gatt_attr,
+    _arg: *mut c_void,
+) -> i32 {
+    let status = (*error).status as i32;
+    let result = if status == 0 && !attr.is_null() {
+        let mut data = [0_u8; 32];
+        let mut len = 0_u16;
+        let rc = sys::ble_hs_mbuf_to_flat(
+            (*attr).om,
+            data.as_mut_ptr() as *mut c_void,
+            data.len() as u16,
+            &mut len,
+        );
+        if rc == 0 {
+            Ok(data[..len as usize].to_vec())
+        } else {
+            Err(rc)
+        }
+    } else {
+        Err(status)
+    };
+    if let Ok(read_tx) = READ_TX.lock() {
+        if let Some(read_tx) = read_tx.as_ref() {
+            // Only the first one matters, the reader's gone by the `BLE_HS_EDONE`
+            _ = read_tx.try_send(result);
+        }
+    }
+    0
+}
+
+/// Sets the system clock, `SystemTime` and anything else in libc follows it.
+pub fn set_system_clock(unix: Duration) -> Result<()> {
+    let time = sys::timeval {
+        tv_sec: unix.as_secs() as _,
+        tv_usec: unix.subsec_micros() as _,
+    };
+    if unsafe { sys::settimeofday(&time, std::ptr::null()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockState {
+    /// Still 1970.
+    Unset,
+    /// Going off the last saved time or a reboot, could be way off.
+    Estimated,
+    /// A phone set it this boot.
+    Synced,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct SavedClock {
+    /// Seconds since the Unix epoch when a phone last set the clock.
+    synced: Option<u64>,
+    /// Latest time we know we've been on for.
+    seen: u64,
+}
+
+/// Keeps the system clock set, and saved across reboots.
+pub struct ClockKeeper {
+    pub state: ClockState,
+    saved: SavedClock,
+    saved_at: Instant,
+}
+
+impl ClockKeeper {
+    /// Picks up from the saved time if the clock's not already set.
+    pub fn restore() -> Result<Self> {
+        let saved: SavedClock = match fs::read(CLOCK_PATH) {
+            Ok(bytes) => postcard::from_bytes(&bytes).unwrap_or_else(|e| {
+                warn!("Couldn't load saved clock: {e}");
+                SavedClock::default()
+            }),
+            Err(_) => SavedClock::default(),
+        };
+        let state = if WallClock::now(0).is_some() {
+            // Survived a soft reset, but we don't know where it came from
+            ClockState::Estimated
+        } else if saved.seen > 0 {
+            set_system_clock(Duration::from_secs(saved.seen))?;
+            if let Some(synced) = saved.synced {
+                let hours = saved.seen.saturating_sub(synced) / 3600;
+                info!("Estimating the time, last synced {hours}h before we went off");
+            }
+            ClockState::Estimated
+        } else {
+            ClockState::Unset
+        };
+        Ok(Self {
+            state,
+            saved,
+            saved_at: Instant::now(),
+        })
+    }
+    /// A phone told us the time.
+    pub fn synced(&mut self, unix: Duration) -> Result<()> {
+        set_system_clock(unix)?;
+        info!(
+            "Clock set to {}",
+            WallClock {
+                unix,
+                utc_offset_min: 0
+            }
+            .iso8601()
+        );
+        self.state = ClockState::Synced;
+        self.saved.synced = Some(unix.as_secs());
+        self.save()
+    }
+    /// Saves the time every so often.
+    pub fn tick(&mut self) -> Result<()> {
+        if self.state != ClockState::Unset && self.saved_at.elapsed() >= SAVE_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+    fn save(&mut self) -> Result<()> {
+        if let Some(now) = WallClock::now(0) {
+            self.saved.seen = now.unix.as_secs();
+            fs::write(CLOCK_PATH, postcard::to_allocvec(&self.saved)?)?;
+        }
+        self.saved_at = Instant::now();
+        Ok(())
+    }
+}
@@ -18,6 +18,12 @@ pub enum AppError {
     Postcard(#[from] postcard::Error),
     #[error(transparent)]
     Ble(#[from] esp32_nimble::BLEError),
+    #[error(transparent)]
+    HrmParse(#[from] crate::heart_rate::measurement::HrmParseError),
+    #[error(transparent)]
+    Replay(#[from] crate::heart_rate::source::ReplayError),
+    #[error(transparent)]
+    Config(#[from] crate::config_service::ConfigError),
     #[error("Boundless rectangle")]
     BoundlessRectangle,
 }
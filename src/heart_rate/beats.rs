@@ -0,0 +1,148 @@
+// Guesses when the next beats land from the RR intervals, so the heart on the
+// badge can pulse on every beat instead of once per notification. Straps only
+// notify about once a second, everything in between is prediction.
+
+use std::time::Duration;
+
+/// Anything outside this (24 to 240 BPM) is a glitch, not a heartbeat.
+const MIN_PERIOD: Duration = Duration::from_millis(250);
+const MAX_PERIOD: Duration = Duration::from_millis(2500);
+/// How long the pulse takes to fade after each beat.
+pub const PULSE_LENGTH: Duration = Duration::from_millis(200);
+/// Stops beating if the notifications do.
+const STALE_AFTER: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Default)]
+pub struct BeatScheduler {
+    period: Duration,
+    last_beat: Option<Duration>,
+    updated_at: Duration,
+}
+
+impl BeatScheduler {
+    /// `now` is any clock that doesn't go backwards, only the differences matter.
+    ///
+    /// The newest RR interval sets the pace, keeping in step with the beats
+    /// already shown rather than jumping to the notification.
+    pub fn update(&mut self, now: Duration, rr_intervals: &[Duration]) {
+        let Some(rr) = rr_intervals.last() else {
+            return;
+        };
+        // Catch up at the old pace first
+        self.last_beat(now);
+        self.period = (*rr).clamp(MIN_PERIOD, MAX_PERIOD);
+        self.updated_at = now;
+        if self.last_beat.is_none() {
+            // Newest beat in the packet only just happened
+            self.last_beat = Some(now);
+        }
+    }
+    /// No contact or no link, nothing to beat for.
+    pub fn stop(&mut self) {
+        self.last_beat = None;
+    }
+    /// The most recent beat, predicted or not.
+    pub fn last_beat(&mut self, now: Duration) -> Option<Duration> {
+        self.last_beat = self.beat_before(now);
+        self.last_beat
+    }
+    /// The beat at or before `at`, without moving anything along, so it's fine
+    /// to ask about the recent past too.
+    pub fn beat_before(&self, at: Duration) -> Option<Duration> {
+        if at.saturating_sub(self.updated_at) > STALE_AFTER {
+            return None;
+        }
+        let last_beat = self.last_beat?;
+        if at >= last_beat {
+            let missed = (at - last_beat).as_nanos() / self.period.as_nanos();
+            Some(last_beat + self.period * missed as u32)
+        } else {
+            let back = (last_beat - at).as_nanos().div_ceil(self.period.as_nanos());
+            last_beat.checked_sub(self.period * back as u32)
+        }
+    }
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+    /// 1.0 right on a beat, fading to 0.0 over `PULSE_LENGTH`. `None` if it's not beating.
+    pub fn pulse(&mut self, now: Duration) -> Option<f32> {
+        let since = now.saturating_sub(self.last_beat(now)?);
+        Some(1.0 - (since.as_secs_f32() / PULSE_LENGTH.as_secs_f32()).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BeatScheduler, MIN_PERIOD, STALE_AFTER};
+    use std::time::Duration;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn beats_follow_rr() {
+        let mut beats = BeatScheduler::default();
+        assert_eq!(None, beats.last_beat(ms(0)));
+        assert_eq!(None, beats.pulse(ms(0)));
+
+        beats.update(ms(0), &[ms(900), ms(1000)]);
+        assert_eq!(Some(1.0), beats.pulse(ms(0)));
+        assert_eq!(Some(0.5), beats.pulse(ms(100)));
+        assert_eq!(Some(0.0), beats.pulse(ms(500)));
+        assert_eq!(Some(ms(1000)), beats.beat_before(ms(1000)));
+
+        // Lands on the beat between notifications
+        assert_eq!(Some(1.0), beats.pulse(ms(1000)));
+        assert_eq!(Some(ms(2000)), beats.last_beat(ms(2050)));
+    }
+
+    #[test]
+    fn beats_keep_phase() {
+        let mut beats = BeatScheduler::default();
+        beats.update(ms(0), &[ms(1000)]);
+        // Speeding up, the beat already shown at 1s stays put
+        beats.update(ms(1300), &[ms(800)]);
+        assert_eq!(Some(ms(1000)), beats.last_beat(ms(1300)));
+        assert_eq!(ms(800), beats.period());
+        assert_eq!(Some(ms(1800)), beats.beat_before(ms(1800)));
+
+        // Nothing new in the packet, carries on as it was
+        beats.update(ms(2000), &[]);
+        assert_eq!(Some(ms(2600)), beats.last_beat(ms(2700)));
+    }
+
+    #[test]
+    fn beats_before() {
+        let mut beats = BeatScheduler::default();
+        beats.update(ms(1000), &[ms(500)]);
+        assert_eq!(Some(ms(2000)), beats.last_beat(ms(2200)));
+        // Looking back doesn't undo anything
+        assert_eq!(Some(ms(1500)), beats.beat_before(ms(1700)));
+        assert_eq!(Some(ms(500)), beats.beat_before(ms(999)));
+        assert_eq!(Some(ms(0)), beats.beat_before(ms(400)));
+        assert_eq!(Some(ms(2000)), beats.last_beat(ms(2200)));
+    }
+
+    #[test]
+    fn beats_stop() {
+        let mut beats = BeatScheduler::default();
+        beats.update(ms(0), &[ms(1000)]);
+        assert_eq!(Some(ms(3000)), beats.last_beat(STALE_AFTER));
+        // Notifications dried up
+        assert_eq!(None, beats.last_beat(STALE_AFTER + ms(1)));
+
+        beats.update(ms(10_000), &[ms(1000)]);
+        assert_eq!(Some(ms(10_000)), beats.last_beat(ms(10_500)));
+        beats.stop();
+        assert_eq!(None, beats.last_beat(ms(10_500)));
+        assert_eq!(None, beats.pulse(ms(10_500)));
+    }
+
+    #[test]
+    fn beats_clamp_glitches() {
+        let mut beats = BeatScheduler::default();
+        beats.update(ms(0), &[Duration::ZERO]);
+        assert_eq!(Some(MIN_PERIOD * 4), beats.last_beat(ms(1000)));
+    }
+}
@@ -1,11 +1,20 @@
 use std::{
     collections::BTreeMap,
-    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError},
+        Arc, Mutex, PoisonError,
+    },
 };
 
 use crate::errors::{AppError, Result};
 use bstr::ByteSlice;
-use esp32_nimble::{utilities::BleUuid, uuid128, BLEAddress, BLEClient, BLEDevice, BLEScan};
+use esp32_nimble::{
+    enums::{AuthReq, PairKeyDist, SecurityIOCap},
+    utilities::BleUuid,
+    uuid128, BLEAddress, BLEAddressType, BLEClient, BLEDevice, BLEError, BLERemoteCharacteristic,
+    BLEScan,
+};
 use esp_idf_hal::delay::Delay;
 use esp_idf_svc::hal::{
     prelude::Peripherals,
@@ -13,116 +22,344 @@ use esp_idf_svc::hal::{
     timer::{TimerConfig, TimerDriver},
 };
 use log::info;
-use serde_derive::{Deserialize, Serialize};
 use takeable::Takeable;
 
-use super::measurement::parse_hrm;
+use super::{
+    energy::{self, ControlPoint, ENERGY_SATURATED},
+    measurement::BodySensorLocation,
+    presence::{NearbyBadges, PresencePayload},
+    source::{HeartRateSource, HrSource},
+};
 
 const BATTERY_SERVICE_UUID: BleUuid = uuid128!("0000180f-0000-1000-8000-00805f9b34fb");
 const BATTERY_CHAR_UUID: BleUuid = uuid128!("00002a19-0000-1000-8000-00805f9b34fb");
 
 const HR_SERVICE_UUID: BleUuid = uuid128!("0000180d-0000-1000-8000-00805f9b34fb");
 const HR_CHAR_UUID: BleUuid = uuid128!("00002a37-0000-1000-8000-00805f9b34fb");
+const BODY_LOCATION_CHAR_UUID: BleUuid = uuid128!("00002a38-0000-1000-8000-00805f9b34fb");
+const HR_CONTROL_POINT_CHAR_UUID: BleUuid = uuid128!("00002a39-0000-1000-8000-00805f9b34fb");
 
-pub type BleMacLe = [u8; 6];
-pub type Monitors = BTreeMap<BleMacLe, String>;
+const DEVICE_INFO_SERVICE_UUID: BleUuid = uuid128!("0000180a-0000-1000-8000-00805f9b34fb");
+const MODEL_CHAR_UUID: BleUuid = uuid128!("00002a24-0000-1000-8000-00805f9b34fb");
+const SERIAL_CHAR_UUID: BleUuid = uuid128!("00002a25-0000-1000-8000-00805f9b34fb");
+const FIRMWARE_CHAR_UUID: BleUuid = uuid128!("00002a26-0000-1000-8000-00805f9b34fb");
+const MANUFACTURER_CHAR_UUID: BleUuid = uuid128!("00002a29-0000-1000-8000-00805f9b34fb");
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-pub enum BatteryLevel {
-    #[default]
-    Unknown,
-    NotReported,
-    Level(u8),
-}
+pub use super::discovery::{BleAddrType, BleIdents, BleMacLe, MonitorMatch, Monitors};
+pub use super::monitor::{
+    BatteryLevel, BleHrCommand, MonitorInfo, MonitorOptions, MonitorReply, MonitorState,
+    MonitorStatus,
+};
 
-impl From<BatteryLevel> for u8 {
-    fn from(level: BatteryLevel) -> Self {
-        match level {
-            BatteryLevel::Level(battery) => battery,
-            _ => 0,
+impl From<BLEAddressType> for BleAddrType {
+    fn from(addr_type: BLEAddressType) -> Self {
+        match addr_type {
+            BLEAddressType::Public | BLEAddressType::PublicID => BleAddrType::Public,
+            BLEAddressType::Random | BLEAddressType::RandomID => BleAddrType::Random,
         }
     }
 }
-impl From<u8> for BatteryLevel {
-    fn from(val: u8) -> Self {
-        BatteryLevel::Level(val)
+
+impl From<BleAddrType> for BLEAddressType {
+    fn from(addr_type: BleAddrType) -> Self {
+        match addr_type {
+            BleAddrType::Public => BLEAddressType::Public,
+            BleAddrType::Random => BLEAddressType::Random,
+        }
     }
 }
-#[derive(Debug, Default, Deserialize, Serialize)]
-pub struct BleIdents {
-    pub mac: [u8; 6],
-    pub name: String,
+
+/// Just Works pairing: bond, but no passkey since there's nothing to type it on.
+///
+/// Bonds live in NVS (`CONFIG_BT_NIMBLE_NVS_PERSIST`), so they survive reboots.
+pub fn configure_security() {
+    BLEDevice::take()
+        .security()
+        .set_auth(AuthReq::Bond)
+        .set_io_cap(SecurityIOCap::NoInputNoOutput)
+        .set_security_init_key(PairKeyDist::ENC | PairKeyDist::ID)
+        .set_security_resp_key(PairKeyDist::ENC | PairKeyDist::ID)
+        .resolve_rpa();
 }
 
-impl std::fmt::Display for BleIdents {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name_display = if self.name.is_empty() {
-            "Unknown".to_string()
-        } else {
-            self.name.clone()
-        };
-        write!(
-            f,
-            "{}\n({:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X})",
-            name_display,
-            self.mac[0],
-            self.mac[1],
-            self.mac[2],
-            self.mac[3],
-            self.mac[4],
-            self.mac[5],
-        )
+/// Deletes any bond with the monitor, returns `false` if there wasn't one.
+pub fn forget_bond(ident: &BleIdents) -> Result<bool> {
+    let device = BLEDevice::take();
+    let types = match ident.addr_type {
+        Some(addr_type) => vec![addr_type],
+        // Don't know which it was saved as, so try both
+        None => vec![BleAddrType::Public, BleAddrType::Random],
+    };
+    let bonded = device.bonded_addresses()?;
+    let mut forgot = false;
+    for addr_type in types {
+        let address = BLEAddress::from_be_bytes(ident.mac, addr_type.into());
+        if bonded.contains(&address) {
+            device.delete_bond(&address)?;
+            info!("Forgot bond with {address:?}");
+            forgot = true;
+        }
     }
+    Ok(forgot)
 }
 
-// Lots of logic yoinked from https://github.com/nullstalgia/iron-heart
-#[derive(Debug, Default, Clone)]
-pub struct MonitorStatus {
-    pub heart_rate_bpm: u16,
-    pub latest_rr: std::time::Duration,
-    pub rr_intervals: Vec<std::time::Duration>,
-    pub battery_level: BatteryLevel,
-
-    pub twitch_up: bool,
-    pub twitch_down: bool,
-    use_real_rr: bool,
+/// NimBLE only does one scan at a time, and there can be a supervisor per monitor.
+static SCAN_LOCK: Mutex<()> = Mutex::new(());
+
+/// How long the monitor picker scans for.
+pub const SELECT_SCAN_DURATION: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Monitors not heard from in this long get dropped from the picker.
+pub const SELECT_STALE_AFTER: std::time::Duration = std::time::Duration::from_secs(8);
+
+/// How often each monitor's signal strength gets passed along to the UI.
+const SEEN_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum ScanReply {
+    /// Heard from a monitor (again), with its RSSI in dBm.
+    Seen {
+        ident: BleIdents,
+        rssi: i32,
+    },
+    Error(AppError),
+    Finished,
 }
 
-impl MonitorStatus {
-    pub fn update_from_slice(&mut self, data: &[u8]) {
-        let newest = parse_hrm(data);
+/// A picker scan running in the background, streaming monitors as they show up.
+///
+/// Dropping the handle cancels the scan.
+pub struct ScanHandle {
+    pub reply_rx: Receiver<ScanReply>,
+    cancel: Arc<AtomicBool>,
+    started: std::time::Instant,
+}
+
+impl ScanHandle {
+    pub fn build() -> Result<Self> {
+        let (reply_tx, reply_rx) = mpsc::sync_channel::<ScanReply>(16);
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let thread_cancel = cancel.clone();
+        std::thread::Builder::new()
+            .stack_size(4000)
+            .spawn(move || {
+                let reply = match block_on(scan_for_select(&reply_tx, &thread_cancel)) {
+                    Ok(()) => ScanReply::Finished,
+                    Err(e) => ScanReply::Error(e),
+                };
+                _ = reply_tx.send(reply);
+            })?;
 
-        self.heart_rate_bpm = newest.bpm;
+        Ok(Self {
+            reply_rx,
+            cancel,
+            started: std::time::Instant::now(),
+        })
+    }
+    /// Stops the scan at the next advertisement it sees.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+    /// 0.0 to 1.0, how far through the scan window we are.
+    pub fn progress(&self) -> f32 {
+        let progress = self.started.elapsed().as_secs_f32() / SELECT_SCAN_DURATION.as_secs_f32();
+        progress.min(1.0)
+    }
+}
 
-        if !newest.rr_intervals.is_empty() {
-            self.use_real_rr = true;
+impl Drop for ScanHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Scans for HR monitors, sending each one to `reply_tx` as soon as it's got a name.
+async fn scan_for_select(reply_tx: &SyncSender<ScanReply>, cancel: &AtomicBool) -> Result<()> {
+    let started = std::time::Instant::now();
+    // Monitors advertising the HR service, and when the UI last heard about them
+    let mut devices: BTreeMap<BleMacLe, (String, Option<std::time::Instant>)> = BTreeMap::new();
+    loop {
+        let scanning = SCAN_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let remaining = SELECT_SCAN_DURATION.saturating_sub(started.elapsed());
+        if remaining.is_zero() || cancel.load(Ordering::Relaxed) {
+            return Ok(());
         }
-        let mut twitch_up = false;
-        let mut twitch_down = false;
-        let rr_intervals = if self.use_real_rr {
-            newest.rr_intervals
-        } else {
-            vec![rr_from_bpm(newest.bpm)]
-        };
+        let mut ble_scan = BLEScan::new();
+        let scan_result: std::result::Result<Option<()>, _> = ble_scan
+            .active_scan(true)
+            .interval(100)
+            .window(99)
+            .filter_duplicates(false)
+            .start(
+                BLEDevice::take(),
+                remaining.as_millis() as i32,
+                |device, data| {
+                    if cancel.load(Ordering::Relaxed) {
+                        return Some(());
+                    }
+                    let address = device.addr().as_be_bytes();
+
+                    if data.service_uuids().any(|s| s == HR_SERVICE_UUID) {
+                        devices.entry(address).or_insert_with(|| {
+                            info!("Addr: {:?}", device.addr());
+                            Default::default()
+                        });
+                    }
+
+                    let Some((name, reported)) = devices.get_mut(&address) else {
+                        return None;
+                    };
+
+                    // The name usually comes in a separate scan response to the services
+                    match data.name() {
+                        Some(device_name) if name.is_empty() && !device_name.is_empty() => {
+                            *name = device_name.to_string();
+                        }
+                        _ => (),
+                    }
 
-        for new_rr in &rr_intervals {
-            const TWITCH_THRESHOLD: f32 = 0.02;
-            if self.latest_rr.abs_diff(*new_rr).as_secs_f32() > TWITCH_THRESHOLD {
-                twitch_up |= *new_rr > self.latest_rr;
-                twitch_down |= *new_rr < self.latest_rr;
+                    // Nameless ones aren't worth showing, and if the UI's behind we'll
+                    // just try again on the next advertisement
+                    let due = reported.map_or(true, |at| at.elapsed() >= SEEN_REPORT_INTERVAL);
+                    if due && !name.is_empty() {
+                        let seen = ScanReply::Seen {
+                            ident: BleIdents {
+                                mac: address,
+                                name: name.clone(),
+                                addr_type: Some(device.addr().addr_type().into()),
+                            },
+                            rssi: device.rssi(),
+                        };
+                        if reply_tx.try_send(seen).is_ok() {
+                            *reported = Some(std::time::Instant::now());
+                        }
+                    }
+
+                    None
+                },
+            )
+            .await;
+        drop(scanning);
+
+        match scan_result {
+            Ok(_) => return Ok(()),
+            // Most likely the monitor supervisor hasn't let go of the radio yet,
+            // the lock's already dropped so it can
+            Err(e) if started.elapsed() < SELECT_SCAN_DURATION / 2 => {
+                ::log::warn!("Scan failed to start, retrying: {e:?}");
+                std::thread::sleep(std::time::Duration::from_millis(500));
             }
-            self.latest_rr = *new_rr;
+            Err(e) => return Err(e.into()),
         }
-        self.rr_intervals = rr_intervals;
-        self.twitch_up = twitch_up;
-        self.twitch_down = twitch_down;
     }
 }
 
-pub fn rr_from_bpm(bpm: u16) -> std::time::Duration {
-    // Make sure it's at least 1 to prevent a potential divide by zero
-    let bpm = bpm.max(1);
-    std::time::Duration::from_secs_f32(60.0 / bpm as f32)
+/// How long each presence scan window is, the lock's let go of in between.
+const PRESENCE_SCAN_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Badges not heard from in this long are assumed to have wandered off.
+pub const PRESENCE_STALE_AFTER: std::time::Duration = std::time::Duration::from_secs(20);
+
+/// How often each badge gets passed along to the UI.
+const PRESENCE_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct PresenceSighting {
+    pub mac: BleMacLe,
+    pub presence: PresencePayload,
+    pub rssi: i32,
+}
+
+/// Passively listens for other badges' presence advertisements until dropped.
+pub struct PresenceScanHandle {
+    pub sighting_rx: Receiver<PresenceSighting>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl PresenceScanHandle {
+    pub fn build() -> Result<Self> {
+        let (sighting_tx, sighting_rx) = mpsc::sync_channel::<PresenceSighting>(16);
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let thread_cancel = cancel.clone();
+        std::thread::Builder::new()
+            .stack_size(4000)
+            .spawn(move || {
+                let mut reported: BTreeMap<BleMacLe, std::time::Instant> = BTreeMap::new();
+                while !thread_cancel.load(Ordering::Relaxed) {
+                    if let Err(e) = block_on(scan_for_presence(
+                        &sighting_tx,
+                        &thread_cancel,
+                        &mut reported,
+                    )) {
+                        ::log::warn!("Presence scan failed: {e}");
+                    }
+                    // Gives any monitor supervisors waiting on the scan lock a turn
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                }
+            })?;
+
+        Ok(Self {
+            sighting_rx,
+            cancel,
+        })
+    }
+}
+
+impl Drop for PresenceScanHandle {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// One passive scan window's worth of listening for badges.
+async fn scan_for_presence(
+    sighting_tx: &SyncSender<PresenceSighting>,
+    cancel: &AtomicBool,
+    reported: &mut BTreeMap<BleMacLe, std::time::Instant>,
+) -> Result<()> {
+    let _scanning = SCAN_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut ble_scan = BLEScan::new();
+    let _: Option<()> = ble_scan
+        .active_scan(false)
+        .interval(100)
+        .window(99)
+        .filter_duplicates(false)
+        .start(
+            BLEDevice::take(),
+            PRESENCE_SCAN_WINDOW.as_millis() as i32,
+            |device, data| {
+                if cancel.load(Ordering::Relaxed) {
+                    return Some(());
+                }
+                let manufacturer = data.manufacture_data()?;
+                let mac = device.addr().as_be_bytes();
+                if reported
+                    .get(&mac)
+                    .is_some_and(|at| at.elapsed() < PRESENCE_REPORT_INTERVAL)
+                {
+                    return None;
+                }
+                // NimBLE splits the company ID off, the payload format has it in front
+                let mut raw = manufacturer.company_identifier.to_le_bytes().to_vec();
+                raw.extend_from_slice(manufacturer.payload);
+                let Ok(presence) = PresencePayload::decode(&raw) else {
+                    return None;
+                };
+                let sighting = PresenceSighting {
+                    mac,
+                    presence,
+                    rssi: device.rssi(),
+                };
+                if sighting_tx.try_send(sighting).is_ok() {
+                    reported.insert(mac, std::time::Instant::now());
+                }
+                None
+            },
+        )
+        .await?;
+    Ok(())
 }
 
 pub struct BleStuff<'a> {
@@ -130,7 +367,9 @@ pub struct BleStuff<'a> {
     pub discovered: Monitors,
     pub chosen_discovered: usize,
     pub monitor: BLEClient,
-    // discovered_rx: Option<Receiver<BleIdents>>,
+    scan: Option<ScanHandle>,
+    pub nearby: NearbyBadges,
+    presence_scan: Option<PresenceScanHandle>,
 }
 impl<'a> BleStuff<'a> {
     pub fn build() -> Self {
@@ -143,69 +382,99 @@ impl<'a> BleStuff<'a> {
             discovered: Monitors::new(),
             chosen_discovered: 0,
             monitor,
-            // discovered_rx: None,
+            scan: None,
+            nearby: NearbyBadges::new(),
+            presence_scan: None,
         }
     }
-    pub async fn scan_for_connect(&self, ident: &BleIdents) -> Result<Option<BLEAddress>> {
-        let mut ble_scan = BLEScan::new();
-        let addr: Option<BLEAddress> = ble_scan
-            .active_scan(true)
-            .interval(100)
-            .window(99)
-            .filter_duplicates(false)
-            .start(&self.host_device, 10000, |device, data| {
-                if device.addr().as_be_bytes() == ident.mac {
-                    Some(device.addr())
-                } else if let Some(name) = data.name() {
-                    if name == ident.name {
-                        Some(device.addr())
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .await?;
-
-        Ok(addr)
+    /// Clears the list and starts a fresh background scan.
+    pub fn start_scan(&mut self) -> Result<()> {
+        self.scan = None;
+        self.discovered.clear();
+        self.chosen_discovered = 0;
+        self.scan = Some(ScanHandle::build()?);
+        Ok(())
     }
-    pub async fn scan_for_select(&self) -> Result<Monitors> {
-        let mut ble_scan = BLEScan::new();
-        let mut devices = Monitors::new();
-        let _: Option<()> = ble_scan
-            .active_scan(true)
-            .interval(100)
-            .window(99)
-            .filter_duplicates(false)
-            .start(&self.host_device, 10000, |device, data| {
-                // info!("{device:#?}\n{data:#?}");
-                let address = device.addr().as_be_bytes();
-
-                if data
-                    .service_uuids()
-                    .find(|s| *s == HR_SERVICE_UUID)
-                    .is_some()
-                {
-                    // I think this will always give me a blank name if it had the services?
-                    // Unsure, need to look into how BLE advertising works.
-                    devices.insert(address, data.name().unwrap_or_default().to_string());
-                    info!("Addr: {:?}", device.addr());
+    pub fn stop_scan(&mut self) {
+        self.scan = None;
+    }
+    pub fn scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+    pub fn scan_progress(&self) -> Option<f32> {
+        self.scan.as_ref().map(ScanHandle::progress)
+    }
+    pub fn start_presence_scan(&mut self) -> Result<()> {
+        self.nearby.clear();
+        self.presence_scan = Some(PresenceScanHandle::build()?);
+        Ok(())
+    }
+    pub fn stop_presence_scan(&mut self) {
+        self.presence_scan = None;
+    }
+    /// Pulls in badges heard from since last time, and forgets ones that left.
+    ///
+    /// Returns `true` if anything on screen would change.
+    pub fn poll_presence(&mut self) -> bool {
+        let Some(scan) = &self.presence_scan else {
+            return false;
+        };
+        let layout = self.nearby.layout();
+        let now = std::time::Instant::now();
+        while let Ok(sighting) = scan.sighting_rx.try_recv() {
+            self.nearby
+                .observe(sighting.mac, sighting.presence, sighting.rssi, now);
+        }
+        self.nearby.prune(now, PRESENCE_STALE_AFTER);
+        layout != self.nearby.layout()
+    }
+    /// Pulls in anything the background scan found, and drops monitors that went quiet.
+    ///
+    /// Returns `true` if the list changed or the scan finished, so the view needs a repaint.
+    pub fn poll_scan(&mut self) -> Result<bool> {
+        let Some(scan) = &self.scan else {
+            return Ok(false);
+        };
+        let layout = self.discovered.layout();
+        // Keep the same monitor selected as the list reshuffles around it
+        let chosen = self
+            .discovered
+            .get(self.chosen_discovered)
+            .map(|(mac, _)| mac);
+        let mut result = Ok(());
+        let mut finished = false;
+        while !finished {
+            match scan.reply_rx.try_recv() {
+                Ok(ScanReply::Seen { ident, rssi }) => {
+                    let now = std::time::Instant::now();
+                    self.discovered.observe(&ident, rssi, now);
                 }
-
-                // Populate the discovered monitors's name in the map if it's empty
-                match (devices.get(&address), data.name()) {
-                    (Some(current_name), Some(device_name)) if current_name.is_empty() => {
-                        devices.insert(address, device_name.to_string());
-                    }
-                    _ => (),
+                Ok(ScanReply::Error(e)) => {
+                    result = Err(e);
+                    finished = true;
                 }
-
-                None
-            })
-            .await?;
-
-        Ok(devices)
+                Ok(ScanReply::Finished) | Err(TryRecvError::Disconnected) => {
+                    info!("Scan finished, {} monitors", self.discovered.len());
+                    finished = true;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        if finished {
+            self.scan = None;
+        } else {
+            // Only while scanning, otherwise the list would empty out from under the user
+            let removed = self
+                .discovered
+                .prune(std::time::Instant::now(), SELECT_STALE_AFTER);
+            if removed > 0 {
+                info!("Dropped {removed} stale monitors");
+            }
+        }
+        self.chosen_discovered = chosen
+            .and_then(|mac| self.discovered.position(&mac))
+            .unwrap_or_default();
+        result.map(|_| finished || layout != self.discovered.layout())
     }
     // pub async fn is_monitor_present() -> Result<bool> {
     //     let mut found = false;
@@ -219,123 +488,536 @@ impl<'a> BleStuff<'a> {
     // }
 }
 
-// #[derive(Debug)]
-// pub enum BleHrCommand {
-//     Scan,
-//     Connect,
-//     // Disconnect
-// }
-
-#[derive(Debug)]
-pub enum MonitorReply {
-    Connected,
-    Error(AppError),
-    // ScannedDevice(BleIdents),
-    MonitorStatus(MonitorStatus),
-    Disconnected,
-}
-
 pub struct MonitorHandle {
+    pub ident: BleIdents,
     pub reply_rx: Receiver<MonitorReply>,
+    command_tx: SyncSender<BleHrCommand>,
+    /// Cuts a scan short once the handle's gone, the channels cover everything else.
+    stop: Arc<AtomicBool>,
 }
 
 impl MonitorHandle {
-    pub fn build(addr: BLEAddress, delay: Delay) -> Result<Self> {
-        // let (command_tx, command_rx) = mpsc::sync_channel::<BleHrCommand>(5);
+    /// Spawns a supervisor thread that keeps (re)connecting to the given monitor.
+    ///
+    /// Dropping the handle disconnects and stops the thread.
+    pub fn build(ident: BleIdents, options: MonitorOptions) -> Result<Self> {
+        let (command_tx, command_rx) = mpsc::sync_channel::<BleHrCommand>(5);
         let (reply_tx, reply_rx) = mpsc::sync_channel::<MonitorReply>(5);
+        let stop = Arc::new(AtomicBool::new(false));
 
+        let actor_ident = ident.clone();
+        let actor_stop = stop.clone();
         std::thread::Builder::new()
             .stack_size(4000)
             .spawn(move || {
-                let mut actor = MonitorActor::build(addr).unwrap();
-                block_on(async {
-                    let err_tx = reply_tx.clone();
-                    if let Err(e) = actor.connect(reply_tx).await {
-                        err_tx.send(MonitorReply::Error(e)).unwrap();
-                    };
-                });
-                loop {
-                    if !actor.client.connected() {
-                        break;
-                    }
-                    delay.delay_ms(1000);
-                }
+                let mut actor =
+                    MonitorActor::build(command_rx, reply_tx, actor_ident, options, actor_stop);
+                actor.supervise();
             })?;
 
         Ok(Self {
+            ident,
             reply_rx,
-            // command_tx,
+            command_tx,
+            stop,
         })
     }
-    // pub fn test(&self) {
-    //     self.command_tx.send(BleHrCommand::Scan).unwrap();
-    // }
+    pub fn command(&mut self, command: BleHrCommand) {
+        if let BleHrCommand::SwitchTo(ident) = &command {
+            self.ident = ident.clone();
+        }
+        // The supervisor doesn't take commands mid-scan, never block the UI on it
+        match self.command_tx.try_send(command) {
+            Ok(()) => (),
+            Err(TrySendError::Full(command)) => {
+                ::log::warn!("Monitor supervisor is busy, dropped {command:?}");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                ::log::error!("Monitor supervisor isn't listening");
+            }
+        }
+    }
+}
+
+impl Drop for MonitorHandle {
+    fn drop(&mut self) {
+        // Hanging up the channels stops it everywhere but mid-scan
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl HeartRateSource for MonitorHandle {
+    fn kind(&self) -> HrSource {
+        HrSource::Ble
+    }
+    fn ident(&self) -> &BleIdents {
+        &self.ident
+    }
+    fn try_recv(&mut self) -> std::result::Result<MonitorReply, TryRecvError> {
+        self.reply_rx.try_recv()
+    }
+    fn command(&mut self, command: BleHrCommand) {
+        MonitorHandle::command(self, command);
+    }
+}
+
+const BACKOFF_MIN: std::time::Duration = std::time::Duration::from_secs(1);
+const BACKOFF_MAX: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How often to check if the link's still up while waiting for commands.
+const LINK_POLL: std::time::Duration = std::time::Duration::from_secs(1);
+/// How often to read the battery from straps that can't notify it.
+const BATTERY_POLL: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Writes block, so only from the supervisor thread, never a NimBLE callback.
+struct RemoteControlPoint<'a>(&'a mut BLERemoteCharacteristic);
+
+impl ControlPoint for RemoteControlPoint<'_> {
+    type Error = BLEError;
+    fn write(&mut self, value: &[u8]) -> std::result::Result<(), BLEError> {
+        block_on(self.0.write_value(value, true))
+    }
+}
+
+/// Exponential backoff between reconnect attempts.
+struct Backoff {
+    next: std::time::Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self { next: BACKOFF_MIN }
+    }
+}
+
+impl Backoff {
+    fn next(&mut self) -> std::time::Duration {
+        let wait = self.next;
+        self.next = (self.next * 2).min(BACKOFF_MAX);
+        wait
+    }
+    fn reset(&mut self) {
+        self.next = BACKOFF_MIN;
+    }
+}
+
+/// How a connection attempt went, short of an outright error.
+enum Attempt {
+    NotFound,
+    Subscribed,
+    PairingFailed(BLEError),
+}
+
+/// What the supervisor's doing next.
+enum Phase {
+    Scan,
+    Linked,
+    Backoff(std::time::Instant),
+    Idle,
+    Exit,
 }
 
 struct MonitorActor {
-    // command_rx: Receiver<BleHrCommand>,
-    // reply_tx: Takeable<SyncSender<MonitorReply>>,
+    command_rx: Receiver<BleHrCommand>,
+    reply_tx: SyncSender<MonitorReply>,
     client: BLEClient,
-    address: BLEAddress,
-    // delay: Delay,
+    ident: BleIdents,
+    options: MonitorOptions,
+    backoff: Backoff,
+    /// Shared with the notify callbacks, so HR packets carry the latest level.
+    battery: Arc<Mutex<BatteryLevel>>,
+    battery_notifies: bool,
+    battery_read_at: std::time::Instant,
+    /// Set from the HR callback once Energy Expended stops counting.
+    energy_reset_wanted: Arc<AtomicBool>,
+    /// The handle's been dropped.
+    stop: Arc<AtomicBool>,
 }
 
 impl MonitorActor {
     pub fn build(
-        // command_rx: Receiver<BleHrCommand>,
-        // reply_tx: SyncSender<MonitorReply>,
-        target_addr: BLEAddress, // delay: Delay,
-    ) -> Result<Self> {
+        command_rx: Receiver<BleHrCommand>,
+        reply_tx: SyncSender<MonitorReply>,
+        ident: BleIdents,
+        options: MonitorOptions,
+        stop: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            command_rx,
+            reply_tx,
+            client: Self::new_client(),
+            ident,
+            options,
+            backoff: Backoff::default(),
+            battery: Arc::default(),
+            battery_notifies: false,
+            battery_read_at: std::time::Instant::now(),
+            energy_reset_wanted: Arc::default(),
+            stop,
+        }
+    }
+    fn new_client() -> BLEClient {
         let mut client = BLEClient::new();
         client.on_connect(|client| {
             client.update_conn_params(120, 120, 0, 60).unwrap();
         });
-
-        Ok(Self {
-            // command_rx,
-            // reply_tx: Takeable::new(reply_tx),
-            client,
-            address: target_addr,
-            // delay,
-        })
+        client
     }
-    async fn connect(&mut self, reply_tx: SyncSender<MonitorReply>) -> Result<()> {
-        if let Err(e) = self.client.connect(&self.address).await {
-            reply_tx.send(MonitorReply::Error(e.into())).unwrap();
-            return Ok(());
+    /// Sends a reply to the UI, returns `false` once the UI side has hung up.
+    fn report(&self, reply: MonitorReply) -> bool {
+        self.reply_tx.send(reply).is_ok()
+    }
+    /// Reports the new state and hands back `next`, or `Phase::Exit` if nobody's listening.
+    fn enter(&self, state: MonitorState, next: Phase) -> Phase {
+        if self.report(MonitorReply::State(state)) {
+            next
+        } else {
+            Phase::Exit
         }
+    }
+    fn enter_backoff(&mut self) -> Phase {
+        let wait = self.backoff.next();
+        let deadline = std::time::Instant::now() + wait;
+        self.enter(MonitorState::Backoff(wait), Phase::Backoff(deadline))
+    }
+    fn disconnect(&mut self) {
+        if self.client.connected() {
+            if let Err(e) = self.client.disconnect() {
+                ::log::error!("Failed to disconnect: {e}");
+            }
+        }
+    }
+    /// Scan -> Linked -> (link lost) -> Backoff -> Scan...
+    ///
+    /// Commands are handled whenever it isn't busy scanning or connecting.
+    fn supervise(&mut self) {
+        let mut phase = Phase::Scan;
+        loop {
+            phase = match phase {
+                Phase::Scan => {
+                    if !self.report(MonitorReply::State(MonitorState::Scanning)) {
+                        break;
+                    }
+                    match block_on(self.scan_and_connect()) {
+                        Ok(Attempt::Subscribed) => {
+                            self.backoff.reset();
+                            self.enter(MonitorState::Subscribed, Phase::Linked)
+                        }
+                        Ok(Attempt::NotFound) => {
+                            ::log::info!("{} not found", self.ident.name);
+                            self.enter_backoff()
+                        }
+                        Ok(Attempt::PairingFailed(e)) => {
+                            ::log::error!("Pairing with {} failed: {e}", self.ident.name);
+                            self.disconnect();
+                            if self.report(MonitorReply::PairingFailed(e.to_string())) {
+                                self.enter_backoff()
+                            } else {
+                                Phase::Exit
+                            }
+                        }
+                        Err(e) => {
+                            ::log::error!("Monitor connection failed: {e}");
+                            // Might've got as far as connecting, don't leave it half set up
+                            self.disconnect();
+                            if self.report(MonitorReply::Error(e.to_string())) {
+                                self.enter_backoff()
+                            } else {
+                                Phase::Exit
+                            }
+                        }
+                    }
+                }
+                Phase::Linked => match self.command_rx.recv_timeout(LINK_POLL) {
+                    Ok(command) => self.handle_command(command).unwrap_or(Phase::Linked),
+                    Err(RecvTimeoutError::Timeout) if self.client.connected() => self.poll_linked(),
+                    Err(RecvTimeoutError::Timeout) => {
+                        ::log::warn!("Lost connection to {}", self.ident.name);
+                        self.enter_backoff()
+                    }
+                    Err(RecvTimeoutError::Disconnected) => Phase::Exit,
+                },
+                Phase::Backoff(deadline) => {
+                    let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                    match self.command_rx.recv_timeout(remaining) {
+                        Ok(command) => self
+                            .handle_command(command)
+                            .unwrap_or(Phase::Backoff(deadline)),
+                        Err(RecvTimeoutError::Timeout) => Phase::Scan,
+                        Err(RecvTimeoutError::Disconnected) => Phase::Exit,
+                    }
+                }
+                Phase::Idle => match self.command_rx.recv() {
+                    Ok(command) => self.handle_command(command).unwrap_or(Phase::Idle),
+                    Err(_) => Phase::Exit,
+                },
+                Phase::Exit => break,
+            };
+        }
+        self.disconnect();
+        ::log::info!("Monitor supervisor for {} exiting", self.ident.name);
+    }
+    /// Returns the phase to move to, or `None` to stay put.
+    fn handle_command(&mut self, command: BleHrCommand) -> Option<Phase> {
+        ::log::info!("Monitor command: {command:?}");
+        match command {
+            BleHrCommand::Disconnect => {
+                self.disconnect();
+                Some(self.enter(MonitorState::Idle, Phase::Idle))
+            }
+            BleHrCommand::SwitchTo(ident) => {
+                self.disconnect();
+                self.ident = ident;
+                self.backoff.reset();
+                Some(Phase::Scan)
+            }
+            // Nothing lost resetting energy later, it happens on connect anyway
+            BleHrCommand::ResetEnergy if !self.client.connected() => {
+                ::log::warn!("Not connected, can't talk to monitor");
+                None
+            }
+            BleHrCommand::ResetEnergy => {
+                self.try_reset_energy();
+                None
+            }
+        }
+    }
+    /// Looks for the saved monitor's exact address.
+    ///
+    /// With name fallback on, a strap with the same name will do, but only once the
+    /// whole scan's gone by without the real one showing up. Closest one wins.
+    async fn scan(&self) -> Result<Option<BLEAddress>> {
+        let _scanning = SCAN_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let ident = &self.ident;
+        let name_fallback = self.options.name_fallback;
+        let stop = &self.stop;
+        let mut by_name: Option<(BLEAddress, i32)> = None;
+        let mut ble_scan = BLEScan::new();
+        // `Some(None)` ends the scan early without a match
+        let exact: Option<Option<BLEAddress>> = ble_scan
+            .active_scan(true)
+            .interval(100)
+            .window(99)
+            .filter_duplicates(false)
+            .start(BLEDevice::take(), 10000, |device, data| {
+                if stop.load(Ordering::Relaxed) {
+                    return Some(None);
+                }
+                let addr = device.addr();
+                match ident.matches(&addr.as_be_bytes(), addr.addr_type().into(), data.name()) {
+                    MonitorMatch::Exact => Some(Some(addr)),
+                    MonitorMatch::Name if name_fallback => {
+                        let rssi = device.rssi();
+                        if by_name.map_or(true, |(_, best)| rssi > best) {
+                            by_name = Some((addr, rssi));
+                        }
+                        None
+                    }
+                    _ => None,
+                }
+            })
+            .await?;
+        if matches!(exact, Some(None)) {
+            return Ok(None);
+        }
+        let exact = exact.flatten();
 
-        let mut status = MonitorStatus::default();
+        if exact.is_none() {
+            if let Some((addr, _)) = by_name {
+                info!("{} not found, falling back to {addr:?} by name", ident.name);
+                return Ok(Some(addr));
+            }
+        }
+        Ok(exact)
+    }
+    /// Things that can't be done from inside a NimBLE callback.
+    fn poll_linked(&mut self) -> Phase {
+        if self.energy_reset_wanted.swap(false, Ordering::Relaxed) {
+            self.try_reset_energy();
+        }
+        self.poll_battery()
+    }
+    /// Returns `false` if the strap doesn't count energy, so has no control point.
+    fn reset_energy(&mut self) -> Result<bool> {
+        let service = block_on(self.client.get_service(HR_SERVICE_UUID))?;
+        let Ok(characteristic) = block_on(service.get_characteristic(HR_CONTROL_POINT_CHAR_UUID))
+        else {
+            return Ok(false);
+        };
+        energy::reset_energy(&mut RemoteControlPoint(characteristic))?;
+        Ok(true)
+    }
+    fn try_reset_energy(&mut self) {
+        match self.reset_energy() {
+            Ok(true) => ::log::info!("Reset energy expended"),
+            Ok(false) => (),
+            Err(e) => ::log::warn!("Couldn't reset energy expended: {e}"),
+        }
+    }
+    fn set_battery(&self, level: BatteryLevel) {
+        *self.battery.lock().unwrap_or_else(PoisonError::into_inner) = level;
+    }
+    /// For straps that can't notify, reads the battery every so often instead.
+    fn poll_battery(&mut self) -> Phase {
+        if self.battery_notifies || self.battery_read_at.elapsed() < BATTERY_POLL {
+            return Phase::Linked;
+        }
+        self.battery_read_at = std::time::Instant::now();
+        match block_on(self.read_battery()) {
+            Ok(level) => {
+                self.set_battery(level);
+                if !self.report(MonitorReply::Battery(level)) {
+                    return Phase::Exit;
+                }
+            }
+            Err(e) => ::log::warn!("Couldn't poll battery: {e}"),
+        }
+        Phase::Linked
+    }
+    async fn read_battery(&mut self) -> Result<BatteryLevel> {
+        let Ok(service) = self.client.get_service(BATTERY_SERVICE_UUID).await else {
+            return Ok(BatteryLevel::NotReported);
+        };
+        let characteristic = service.get_characteristic(BATTERY_CHAR_UUID).await?;
+        let value = characteristic.read_value().await?;
+        ::log::info!("Battery value: {:?}%", value.first());
+        Ok(BatteryLevel::from_slice(&value))
+    }
+    /// Returns `false` if the strap has no battery service or can't notify it.
+    async fn subscribe_battery(&mut self) -> Result<bool> {
+        let Ok(service) = self.client.get_service(BATTERY_SERVICE_UUID).await else {
+            return Ok(false);
+        };
+        let characteristic = service.get_characteristic(BATTERY_CHAR_UUID).await?;
+        if !characteristic.can_notify() {
+            ::log::info!("Battery can't notify, polling it instead");
+            return Ok(false);
+        }
+        let battery = self.battery.clone();
+        let reply_tx = self.reply_tx.clone();
+        characteristic
+            .on_notify(move |data| {
+                ::log::info!("Battery Notify: {:?}", data);
+                let level = BatteryLevel::from_slice(data);
+                *battery.lock().unwrap_or_else(PoisonError::into_inner) = level;
+                _ = reply_tx.try_send(MonitorReply::Battery(level));
+            })
+            .subscribe_notify(false)
+            .await?;
+        Ok(true)
+    }
+    /// Plenty of straps skip some of these, missing ones are just left `None`.
+    async fn read_device_info(&mut self) -> Result<MonitorInfo> {
+        let mut info = MonitorInfo::default();
+        if let Ok(hr_service) = self.client.get_service(HR_SERVICE_UUID).await {
+            if let Ok(characteristic) = hr_service.get_characteristic(BODY_LOCATION_CHAR_UUID).await
+            {
+                if let Ok(value) = characteristic.read_value().await {
+                    info.location = BodySensorLocation::parse(&value);
+                }
+            }
+        }
+        let Ok(service) = self.client.get_service(DEVICE_INFO_SERVICE_UUID).await else {
+            ::log::info!("{info:?}");
+            return Ok(info);
+        };
+        for (uuid, field) in [
+            (MANUFACTURER_CHAR_UUID, &mut info.manufacturer),
+            (MODEL_CHAR_UUID, &mut info.model),
+            (FIRMWARE_CHAR_UUID, &mut info.firmware),
+            (SERIAL_CHAR_UUID, &mut info.serial),
+        ] {
+            let Ok(characteristic) = service.get_characteristic(uuid).await else {
+                continue;
+            };
+            if let Ok(value) = characteristic.read_value().await {
+                *field = Some(
+                    value
+                        .trim_end_with(|c| c == '\0')
+                        .to_str_lossy()
+                        .to_string(),
+                );
+            }
+        }
+        ::log::info!("{info:?}");
+        Ok(info)
+    }
+    async fn scan_and_connect(&mut self) -> Result<Attempt> {
+        let Some(address) = self.scan().await? else {
+            return Ok(Attempt::NotFound);
+        };
+        if !self.report(MonitorReply::State(MonitorState::Connecting)) {
+            return Ok(Attempt::NotFound);
+        }
+        // Fresh client each attempt so no stale services are left over
+        self.client = Self::new_client();
+        self.client.connect(&address).await?;
 
-        if let Ok(service) = self.client.get_service(BATTERY_SERVICE_UUID).await {
-            let characteristic = service.get_characteristic(BATTERY_CHAR_UUID).await?;
-            let value = characteristic.read_value().await?;
-            ::log::info!("Battery value: {:?}%", value[0]);
-            status.battery_level = value[0].into();
+        // Pairs the first time, after that NimBLE just encrypts with the stored bond
+        if self.options.bond {
+            if let Err(e) = self.client.secure_connection().await {
+                return Ok(Attempt::PairingFailed(e));
+            }
         }
 
+        let mut status = MonitorStatus::new(self.options.filter_rr);
+        let level = self.read_battery().await?;
+        self.set_battery(level);
+        self.battery_read_at = std::time::Instant::now();
+        // Not worth dropping the strap over, it'll just get polled
+        self.battery_notifies = self.subscribe_battery().await.unwrap_or_else(|e| {
+            ::log::warn!("Couldn't subscribe to battery: {e}");
+            false
+        });
+
         let hr_service = self.client.get_service(HR_SERVICE_UUID).await?;
 
         let characteristic = hr_service.get_characteristic(HR_CHAR_UUID).await?;
         if !characteristic.can_notify() {
             ::log::error!("characteristic can't notify: {}", characteristic);
-            return Ok(());
+            self.client.disconnect()?;
+            return Ok(Attempt::NotFound);
         }
 
         ::log::info!("subscribe to {}", characteristic);
-        // let reply_tx = self.reply_tx.take();
+        let reply_tx = self.reply_tx.clone();
+        let battery = self.battery.clone();
+        let energy_reset_wanted = self.energy_reset_wanted.clone();
         characteristic
             .on_notify(move |data| {
-                status.update_from_slice(data);
-                reply_tx
-                    .send(MonitorReply::MonitorStatus(status.clone()))
-                    .unwrap();
                 ::log::info!("HR Notify: {:?}", data);
+                status.battery_level = *battery.lock().unwrap_or_else(PoisonError::into_inner);
+                let reply = match status.update_from_slice(data) {
+                    Ok(()) => {
+                        if status.energy_expended == Some(ENERGY_SATURATED) {
+                            energy_reset_wanted.store(true, Ordering::Relaxed);
+                        }
+                        MonitorReply::MonitorStatus(status.clone())
+                    }
+                    Err(e) => {
+                        ::log::warn!("Bad HRM packet: {e}");
+                        MonitorReply::Error(e.to_string())
+                    }
+                };
+                // Never block the NimBLE host task, drop it if the UI's behind
+                _ = reply_tx.try_send(reply);
             })
             // Dunno yet why this is `false`
             .subscribe_notify(false)
             .await?;
-        Ok(())
+        // Whatever it's counted so far was for someone else's session
+        self.energy_reset_wanted.store(true, Ordering::Relaxed);
+
+        // Only once heart rate's flowing, no point holding that up
+        match self.read_device_info().await {
+            Ok(info) => {
+                if !self.report(MonitorReply::Info(info)) {
+                    return Ok(Attempt::NotFound);
+                }
+            }
+            Err(e) => ::log::warn!("Couldn't read monitor info: {e}"),
+        }
+        Ok(Attempt::Subscribed)
     }
 }
 
@@ -0,0 +1,325 @@
+// Monitors seen by the picker scan, with signal strength and when they were last heard from.
+//
+// At a con there's dozens of straps in range, so the strongest (closest) one goes first
+// and anything that's wandered off gets dropped.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+pub type BleMacLe = [u8; 6];
+
+/// Whether an address is the manufacturer's, or one the device made up.
+///
+/// The same six bytes can be both, so matching on the MAC alone isn't enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BleAddrType {
+    Public,
+    Random,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BleIdents {
+    pub mac: [u8; 6],
+    pub name: String,
+    /// `None` for monitors saved before we kept track, or typed in from a phone.
+    pub addr_type: Option<BleAddrType>,
+}
+
+impl std::fmt::Display for BleIdents {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name_display = if self.name.is_empty() {
+            "Unknown".to_string()
+        } else {
+            self.name.clone()
+        };
+        write!(
+            f,
+            "{}\n({:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X})",
+            name_display,
+            self.mac[0],
+            self.mac[1],
+            self.mac[2],
+            self.mac[3],
+            self.mac[4],
+            self.mac[5],
+        )
+    }
+}
+
+/// How well an advertisement lines up with a saved monitor, best last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MonitorMatch {
+    No,
+    /// Same name, but at a con that could be anyone's Polar H10.
+    Name,
+    Exact,
+}
+
+impl BleIdents {
+    pub fn matches(
+        &self,
+        mac: &BleMacLe,
+        addr_type: BleAddrType,
+        name: Option<&str>,
+    ) -> MonitorMatch {
+        let type_matches = self.addr_type.is_none() || self.addr_type == Some(addr_type);
+        if self.mac == *mac && type_matches {
+            MonitorMatch::Exact
+        } else if !self.name.is_empty() && name == Some(self.name.as_str()) {
+            MonitorMatch::Name
+        } else {
+            MonitorMatch::No
+        }
+    }
+}
+
+/// How much a new RSSI reading moves the smoothed value, advertisements are noisy.
+const RSSI_SMOOTHING: f32 = 0.3;
+
+/// Lower RSSI bound (dBm) for each signal bar.
+const BAR_THRESHOLDS: [i32; 4] = [-90, -80, -70, -60];
+
+pub const MAX_BARS: u8 = BAR_THRESHOLDS.len() as u8;
+
+/// Moves a smoothed RSSI towards a new reading.
+pub fn smooth_rssi(current: f32, rssi: i32) -> f32 {
+    current + (rssi as f32 - current) * RSSI_SMOOTHING
+}
+
+/// 0 to `MAX_BARS`.
+pub fn signal_bars(rssi: i32) -> u8 {
+    BAR_THRESHOLDS
+        .iter()
+        .filter(|threshold| rssi >= **threshold)
+        .count() as u8
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeenMonitor {
+    pub name: String,
+    pub addr_type: Option<BleAddrType>,
+    rssi: f32,
+    pub last_seen: Instant,
+}
+
+impl SeenMonitor {
+    /// Smoothed RSSI in dBm.
+    pub fn rssi(&self) -> i32 {
+        self.rssi.round() as i32
+    }
+    /// 0 to `MAX_BARS`.
+    pub fn bars(&self) -> u8 {
+        signal_bars(self.rssi())
+    }
+    pub fn ident(&self, mac: BleMacLe) -> BleIdents {
+        BleIdents {
+            mac,
+            name: self.name.clone(),
+            addr_type: self.addr_type,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Monitors {
+    seen: BTreeMap<BleMacLe, SeenMonitor>,
+    /// Strongest signal first.
+    order: Vec<BleMacLe>,
+}
+
+impl Monitors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn clear(&mut self) {
+        self.seen.clear();
+        self.order.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+    /// Records an advertisement. Empty names don't overwrite a known one.
+    pub fn observe(&mut self, ident: &BleIdents, rssi: i32, now: Instant) {
+        match self.seen.get_mut(&ident.mac) {
+            Some(monitor) => {
+                monitor.rssi = smooth_rssi(monitor.rssi, rssi);
+                monitor.last_seen = now;
+                if !ident.name.is_empty() {
+                    monitor.name.clone_from(&ident.name);
+                }
+                if ident.addr_type.is_some() {
+                    monitor.addr_type = ident.addr_type;
+                }
+            }
+            None => {
+                self.seen.insert(
+                    ident.mac,
+                    SeenMonitor {
+                        name: ident.name.clone(),
+                        addr_type: ident.addr_type,
+                        rssi: rssi as f32,
+                        last_seen: now,
+                    },
+                );
+            }
+        }
+        self.sort();
+    }
+    /// Drops anything not heard from in `max_age`, returns how many went.
+    pub fn prune(&mut self, now: Instant, max_age: Duration) -> usize {
+        let before = self.seen.len();
+        self.seen
+            .retain(|_, monitor| now.saturating_duration_since(monitor.last_seen) <= max_age);
+        let removed = before - self.seen.len();
+        if removed > 0 {
+            self.sort();
+        }
+        removed
+    }
+    /// The `index`th strongest monitor.
+    pub fn get(&self, index: usize) -> Option<(BleMacLe, &SeenMonitor)> {
+        let mac = self.order.get(index)?;
+        Some((*mac, &self.seen[mac]))
+    }
+    pub fn position(&self, mac: &BleMacLe) -> Option<usize> {
+        self.order.iter().position(|seen| seen == mac)
+    }
+    /// Strongest signal first.
+    pub fn iter(&self) -> impl Iterator<Item = (BleMacLe, &SeenMonitor)> {
+        self.order.iter().map(|mac| (*mac, &self.seen[mac]))
+    }
+    /// Everything that'd be visible in the picker, to tell if it needs a repaint.
+    pub fn layout(&self) -> Vec<(BleMacLe, u8)> {
+        self.iter()
+            .map(|(mac, monitor)| (mac, monitor.bars()))
+            .collect()
+    }
+    fn sort(&mut self) {
+        self.order = self.seen.keys().copied().collect();
+        // Stable, so equal signals stay in MAC order instead of shuffling around
+        self.order
+            .sort_by(|a, b| self.seen[b].rssi.total_cmp(&self.seen[a].rssi));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BleAddrType, BleIdents, BleMacLe, MonitorMatch, Monitors, MAX_BARS};
+    use std::time::{Duration, Instant};
+
+    const A: BleMacLe = [0, 0, 0, 0, 0, 1];
+    const B: BleMacLe = [0, 0, 0, 0, 0, 2];
+    const C: BleMacLe = [0, 0, 0, 0, 0, 3];
+
+    fn ident(mac: BleMacLe, name: &str) -> BleIdents {
+        BleIdents {
+            mac,
+            name: name.to_string(),
+            addr_type: Some(BleAddrType::Public),
+        }
+    }
+
+    #[test]
+    fn discovery_sorted_by_signal() {
+        let now = Instant::now();
+        let mut monitors = Monitors::new();
+        monitors.observe(&ident(A, "Far"), -90, now);
+        monitors.observe(&ident(B, "Close"), -40, now);
+        monitors.observe(&ident(C, "Middle"), -70, now);
+        let order: Vec<BleMacLe> = monitors.iter().map(|(mac, _)| mac).collect();
+        assert_eq!(vec![B, C, A], order);
+        assert_eq!(Some(0), monitors.position(&B));
+        assert_eq!("Close", monitors.get(0).unwrap().1.name);
+    }
+
+    #[test]
+    fn discovery_rssi_smoothed() {
+        let now = Instant::now();
+        let mut monitors = Monitors::new();
+        monitors.observe(&ident(A, "A"), -80, now);
+        monitors.observe(&ident(B, "B"), -60, now);
+        // One lucky advertisement shouldn't leapfrog it straight to the top
+        monitors.observe(&ident(A, "A"), -40, now);
+        assert_eq!(-68, monitors.get(1).unwrap().1.rssi());
+        assert_eq!(Some(1), monitors.position(&A));
+        // But holding it next to the badge should
+        for _ in 0..5 {
+            monitors.observe(&ident(A, "A"), -40, now);
+        }
+        assert_eq!(Some(0), monitors.position(&A));
+    }
+
+    #[test]
+    fn discovery_keeps_name() {
+        let now = Instant::now();
+        let mut monitors = Monitors::new();
+        monitors.observe(&ident(A, ""), -60, now);
+        assert_eq!("", monitors.get(0).unwrap().1.name);
+        monitors.observe(&ident(A, "Polar H10"), -60, now);
+        monitors.observe(&ident(A, ""), -60, now);
+        assert_eq!("Polar H10", monitors.get(0).unwrap().1.name);
+        assert_eq!(1, monitors.len());
+    }
+
+    #[test]
+    fn discovery_prunes_stale() {
+        let start = Instant::now();
+        let mut monitors = Monitors::new();
+        monitors.observe(&ident(A, "A"), -60, start);
+        monitors.observe(&ident(B, "B"), -50, start);
+        let later = start + Duration::from_secs(10);
+        monitors.observe(&ident(A, "A"), -60, later);
+        assert_eq!(1, monitors.prune(later, Duration::from_secs(5)));
+        assert_eq!(1, monitors.len());
+        assert_eq!(A, monitors.get(0).unwrap().0);
+        assert_eq!(None, monitors.get(1));
+        assert_eq!(None, monitors.position(&B));
+    }
+
+    #[test]
+    fn discovery_bars() {
+        let now = Instant::now();
+        let mut monitors = Monitors::new();
+        for (index, rssi) in [-30, -60, -61, -75, -85, -91].into_iter().enumerate() {
+            monitors.observe(&ident([index as u8; 6], ""), rssi, now);
+        }
+        let bars: Vec<u8> = monitors.iter().map(|(_, monitor)| monitor.bars()).collect();
+        assert_eq!(vec![MAX_BARS, 4, 3, 2, 1, 0], bars);
+    }
+
+    #[test]
+    fn discovery_matches_saved() {
+        let saved = ident(A, "Polar H10");
+        let public = BleAddrType::Public;
+        let random = BleAddrType::Random;
+        assert_eq!(MonitorMatch::Exact, saved.matches(&A, public, None));
+        assert_eq!(
+            MonitorMatch::Exact,
+            saved.matches(&A, public, Some("Renamed"))
+        );
+        // Someone else's strap with the same name
+        assert_eq!(
+            MonitorMatch::Name,
+            saved.matches(&B, public, Some("Polar H10"))
+        );
+        // Same bytes, but a different address
+        assert_eq!(MonitorMatch::No, saved.matches(&A, random, None));
+        assert_eq!(MonitorMatch::No, saved.matches(&B, public, None));
+
+        // Saved before the type was, so anything goes
+        let legacy = BleIdents {
+            addr_type: None,
+            ..saved
+        };
+        assert_eq!(MonitorMatch::Exact, legacy.matches(&A, random, None));
+
+        let nameless = ident(A, "");
+        assert_eq!(MonitorMatch::No, nameless.matches(&B, public, Some("")));
+        assert!(MonitorMatch::Exact > MonitorMatch::Name);
+    }
+}
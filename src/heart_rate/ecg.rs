@@ -0,0 +1,166 @@
+// Hospital monitor style trace for the badge. Straps don't send the actual
+// ECG, so it's a made up PQRST complex drawn at each (predicted) beat, swept
+// across left to right and erasing just ahead of itself.
+
+use std::time::Duration;
+
+use super::beats::BeatScheduler;
+
+/// Columns per second, 240 of them is about three seconds.
+pub const SWEEP_SPEED: u32 = 80;
+/// Blank columns kept in front of the cursor.
+pub const ERASE_AHEAD: usize = 12;
+/// Times per column the waveform's checked, so the R spike doesn't fall between them.
+const SUBSAMPLES: u32 = 4;
+
+/// (centre, width, height) relative to the R peak, in seconds.
+const PQRST: [(f32, f32, f32); 5] = [
+    (-0.16, 0.025, 0.12),
+    (-0.03, 0.008, -0.1),
+    (0.0, 0.012, 1.0),
+    (0.035, 0.01, -0.25),
+    (0.25, 0.045, 0.25),
+];
+
+/// Roughly -0.25 to 1.0, with the R peak at `since_beat` zero.
+pub fn waveform(since_beat: f32, period: f32) -> f32 {
+    // The next beat's P wave starts before it lands
+    [since_beat, since_beat - period]
+        .into_iter()
+        .flat_map(|t| {
+            PQRST.iter().map(move |(centre, width, height)| {
+                height * (-0.5 * ((t - centre) / width).powi(2)).exp()
+            })
+        })
+        .sum()
+}
+
+pub struct EcgSweep {
+    /// One level per column, `None` where it's been erased.
+    trace: Vec<Option<f32>>,
+    cursor: usize,
+    /// Time at the cursor.
+    cursor_at: Option<Duration>,
+}
+
+impl EcgSweep {
+    pub fn new(width: usize) -> Self {
+        Self {
+            trace: vec![None; width],
+            cursor: 0,
+            cursor_at: None,
+        }
+    }
+    pub fn trace(&self) -> &[Option<f32>] {
+        &self.trace
+    }
+    /// Where the next column gets drawn.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+    /// Draws columns up to `now`, flat where there's no beat. Returns how many.
+    ///
+    /// `now` is any clock that doesn't go backwards.
+    pub fn advance(&mut self, now: Duration, beats: &BeatScheduler) -> usize {
+        let column = Duration::from_secs(1) / SWEEP_SPEED;
+        let Some(mut at) = self.cursor_at else {
+            self.cursor_at = Some(now);
+            return 0;
+        };
+        // Been away, no point drawing more than a screen's worth
+        let behind = self.trace.len() as u32 * column;
+        if now.saturating_sub(at) > behind {
+            at = now - behind;
+        }
+        let mut moved = 0;
+        while at + column <= now {
+            let level = (1..=SUBSAMPLES)
+                .map(|step| at + column * step / SUBSAMPLES)
+                .map(|t| self.level_at(t, beats))
+                .fold(0.0, |extreme: f32, level| {
+                    if level.abs() > extreme.abs() {
+                        level
+                    } else {
+                        extreme
+                    }
+                });
+            at += column;
+            self.trace[self.cursor] = Some(level);
+            self.cursor = (self.cursor + 1) % self.trace.len();
+            let erase = (self.cursor + ERASE_AHEAD - 1) % self.trace.len();
+            self.trace[erase] = None;
+            moved += 1;
+        }
+        self.cursor_at = Some(at);
+        moved
+    }
+    fn level_at(&self, at: Duration, beats: &BeatScheduler) -> f32 {
+        match beats.beat_before(at) {
+            Some(beat) => waveform((at - beat).as_secs_f32(), beats.period().as_secs_f32()),
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{waveform, EcgSweep, ERASE_AHEAD, SWEEP_SPEED};
+    use crate::heart_rate::beats::BeatScheduler;
+    use std::time::Duration;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn ecg_waveform() {
+        assert!((waveform(0.0, 1.0) - 1.0).abs() < 0.01);
+        assert!(waveform(0.035, 1.0) < -0.2);
+        assert!(waveform(0.25, 1.0) > 0.2);
+        // Flat between the T wave and the next P wave
+        assert!(waveform(0.6, 1.0).abs() < 0.01);
+        assert!(waveform(0.84, 1.0) > 0.1);
+    }
+
+    #[test]
+    fn ecg_sweeps() {
+        let column = Duration::from_secs(1) / SWEEP_SPEED;
+        let mut beats = BeatScheduler::default();
+        let mut sweep = EcgSweep::new(100);
+        assert_eq!(0, sweep.advance(ms(0), &beats));
+        // No beats yet, flat
+        assert_eq!(10, sweep.advance(column * 10, &beats));
+        assert_eq!(10, sweep.cursor());
+        assert!(sweep.trace()[..10].iter().all(|level| *level == Some(0.0)));
+
+        beats.update(column * 10, &[ms(1000)]);
+        assert_eq!(5, sweep.advance(column * 15, &beats));
+        let spike = sweep.trace()[10..15]
+            .iter()
+            .map(|level| level.unwrap())
+            .fold(0.0, f32::max);
+        assert!(spike > 0.9);
+        // Erased just ahead of the cursor
+        assert!(sweep.trace()[15..15 + ERASE_AHEAD]
+            .iter()
+            .all(Option::is_none));
+    }
+
+    #[test]
+    fn ecg_wraps() {
+        let column = Duration::from_secs(1) / SWEEP_SPEED;
+        let beats = BeatScheduler::default();
+        let mut sweep = EcgSweep::new(100);
+        sweep.advance(ms(0), &beats);
+        sweep.advance(column * 95, &beats);
+        assert_eq!(10, sweep.advance(column * 105, &beats));
+        assert_eq!(5, sweep.cursor());
+        assert!(sweep.trace()[5..5 + ERASE_AHEAD]
+            .iter()
+            .all(Option::is_none));
+        assert!(sweep.trace()[5 + ERASE_AHEAD..].iter().all(Option::is_some));
+
+        // Off the badge for a minute, only catches up one screen
+        assert_eq!(100, sweep.advance(ms(60_000), &beats));
+    }
+}
@@ -0,0 +1,158 @@
+// Energy Expended from the Heart Rate Measurement, in kJ. Straps count up from
+// whenever they were last reset and then stick at 0xFFFF, so the only way to
+// keep it going is writing a reset to the Heart Rate Control Point (0x2A39).
+// We do that at session start and whenever it saturates, and add up the
+// differences here so the total carries on across resets.
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Heart Rate Control Point op code to zero Energy Expended, the only one there is.
+pub const RESET_ENERGY_EXPENDED: u8 = 0x01;
+/// The strap stops counting here until it's reset.
+pub const ENERGY_SATURATED: u16 = u16::MAX;
+const KJ_PER_KCAL: f32 = 4.184;
+
+/// Whatever we can write the Heart Rate Control Point through.
+pub trait ControlPoint {
+    type Error;
+    fn write(&mut self, value: &[u8]) -> Result<(), Self::Error>;
+}
+
+pub fn reset_energy<C: ControlPoint>(control_point: &mut C) -> Result<(), C::Error> {
+    control_point.write(&[RESET_ENERGY_EXPENDED])
+}
+
+pub fn kj_to_kcal(kj: u32) -> u32 {
+    (kj as f32 / KJ_PER_KCAL).round() as u32
+}
+
+/// Serializes as just the total, the last reading means nothing after a reboot.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EnergyCounter {
+    pub total_kj: u32,
+    #[serde(skip)]
+    last: Option<u16>,
+}
+
+impl EnergyCounter {
+    /// Adds on whatever the strap's counted since its last reading.
+    pub fn update(&mut self, reported: u16) {
+        match self.last {
+            Some(last) if reported >= last => self.total_kj += (reported - last) as u32,
+            // Strap got reset, it's counting up from zero again
+            Some(_) => self.total_kj += reported as u32,
+            // Whatever it had before is from some other session
+            None => (),
+        }
+        self.last = Some(reported);
+    }
+    /// The next reading could be from a different strap, or one that's been reset meanwhile.
+    pub fn pause(&mut self) {
+        self.last = None;
+    }
+    pub fn kcal(&self) -> u32 {
+        kj_to_kcal(self.total_kj)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        kj_to_kcal, reset_energy, ControlPoint, EnergyCounter, ENERGY_SATURATED,
+        RESET_ENERGY_EXPENDED,
+    };
+
+    /// Counts like a real strap would, and resets when told to.
+    #[derive(Default)]
+    struct FakeStrap {
+        energy: u16,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl FakeStrap {
+        fn burn(&mut self, kj: u16) -> u16 {
+            self.energy = self.energy.saturating_add(kj);
+            self.energy
+        }
+    }
+
+    impl ControlPoint for FakeStrap {
+        type Error = u8;
+        fn write(&mut self, value: &[u8]) -> Result<(), u8> {
+            self.writes.push(value.to_vec());
+            match value {
+                [RESET_ENERGY_EXPENDED] => {
+                    self.energy = 0;
+                    Ok(())
+                }
+                // Control Point Not Supported
+                _ => Err(0x80),
+            }
+        }
+    }
+
+    #[test]
+    fn energy_accumulates_across_resets() {
+        let mut strap = FakeStrap {
+            energy: 900,
+            ..Default::default()
+        };
+        let mut counter = EnergyCounter::default();
+        // Left over from before, doesn't count
+        counter.update(strap.burn(0));
+        reset_energy(&mut strap).unwrap();
+        assert_eq!(vec![vec![RESET_ENERGY_EXPENDED]], strap.writes);
+
+        counter.update(strap.burn(5));
+        counter.update(strap.burn(10));
+        assert_eq!(15, counter.total_kj);
+
+        reset_energy(&mut strap).unwrap();
+        counter.update(strap.burn(3));
+        assert_eq!(18, counter.total_kj);
+
+        assert_eq!(Err(0x80), strap.write(&[0x02]));
+    }
+
+    #[test]
+    fn energy_saturates_until_reset() {
+        let mut strap = FakeStrap {
+            energy: ENERGY_SATURATED - 100,
+            ..Default::default()
+        };
+        let mut counter = EnergyCounter::default();
+        counter.update(strap.burn(0));
+        counter.update(strap.burn(60));
+        // Stuck at the top, the rest of it's lost
+        assert_eq!(ENERGY_SATURATED, strap.burn(60));
+        counter.update(ENERGY_SATURATED);
+        counter.update(strap.burn(60));
+        assert_eq!(100, counter.total_kj);
+
+        reset_energy(&mut strap).unwrap();
+        counter.update(strap.burn(7));
+        assert_eq!(107, counter.total_kj);
+    }
+
+    #[test]
+    fn energy_pause_drops_baseline() {
+        let mut counter = EnergyCounter::default();
+        counter.update(10);
+        counter.update(20);
+        counter.pause();
+        counter.update(500);
+        counter.update(502);
+        assert_eq!(12, counter.total_kj);
+    }
+
+    #[test]
+    fn energy_kcal() {
+        assert_eq!(0, kj_to_kcal(0));
+        assert_eq!(1000, kj_to_kcal(4184));
+        let counter = EnergyCounter {
+            total_kj: 2092,
+            last: None,
+        };
+        assert_eq!(500, counter.kcal());
+    }
+}
@@ -0,0 +1,156 @@
+// Artifact and ectopic beat filtering for incoming RR intervals.
+//
+// Straps will happily report 0.2s or 2.5s intervals when they shift around on
+// someone's chest, this tries to keep those out of the twitch logic and HRV.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// ~220 BPM
+const MIN_RR: Duration = Duration::from_millis(270);
+/// ~30 BPM
+const MAX_RR: Duration = Duration::from_millis(2000);
+
+/// How many accepted intervals make up the "local" median.
+const MEDIAN_WINDOW: usize = 5;
+/// Beats further than this fraction from the local median are treated as ectopic.
+const ECTOPIC_THRESHOLD: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RrVerdict {
+    Accepted(Duration),
+    /// Ectopic beat, replaced by the local median.
+    Interpolated(Duration),
+    /// Physiologically impossible, dropped entirely.
+    Rejected,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RrFilter {
+    recent: VecDeque<Duration>,
+    pub rejected: u32,
+    pub interpolated: u32,
+}
+
+impl RrFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+    /// Median of the last few plausible intervals, if there's enough of them.
+    pub fn local_median(&self) -> Option<Duration> {
+        if self.recent.len() < 3 {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.recent.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+    pub fn check(&mut self, rr: Duration) -> RrVerdict {
+        if !(MIN_RR..=MAX_RR).contains(&rr) {
+            self.rejected += 1;
+            return RrVerdict::Rejected;
+        }
+
+        let verdict = match self.local_median() {
+            Some(median)
+                if rr.abs_diff(median).as_secs_f32() > median.as_secs_f32() * ECTOPIC_THRESHOLD =>
+            {
+                self.interpolated += 1;
+                RrVerdict::Interpolated(median)
+            }
+            _ => RrVerdict::Accepted(rr),
+        };
+
+        // The raw (plausible) value still goes into the median window,
+        // so a real sustained change in HR wins out after a few beats.
+        if self.recent.len() == MEDIAN_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(rr);
+
+        verdict
+    }
+    /// Runs every interval through `check`, dropping rejected ones.
+    pub fn process(&mut self, rr_intervals: &[Duration]) -> Vec<Duration> {
+        rr_intervals
+            .iter()
+            .filter_map(|rr| match self.check(*rr) {
+                RrVerdict::Accepted(rr) | RrVerdict::Interpolated(rr) => Some(rr),
+                RrVerdict::Rejected => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RrFilter, RrVerdict};
+    use std::time::Duration;
+
+    fn ms(value: u64) -> Duration {
+        Duration::from_millis(value)
+    }
+
+    #[test]
+    fn filter_rejects_impossible() {
+        let mut filter = RrFilter::new();
+        assert_eq!(RrVerdict::Rejected, filter.check(ms(200)));
+        assert_eq!(RrVerdict::Rejected, filter.check(ms(2500)));
+        assert_eq!(RrVerdict::Accepted(ms(800)), filter.check(ms(800)));
+        assert_eq!(2, filter.rejected);
+        assert_eq!(0, filter.interpolated);
+    }
+
+    #[test]
+    fn filter_accepts_until_median_known() {
+        let mut filter = RrFilter::new();
+        // Wildly different, but there's nothing to compare against yet
+        assert_eq!(RrVerdict::Accepted(ms(800)), filter.check(ms(800)));
+        assert_eq!(RrVerdict::Accepted(ms(1200)), filter.check(ms(1200)));
+        assert_eq!(RrVerdict::Accepted(ms(810)), filter.check(ms(810)));
+        assert_eq!(Some(ms(810)), filter.local_median());
+    }
+
+    #[test]
+    fn filter_interpolates_ectopic() {
+        let mut filter = RrFilter::new();
+        let filtered = filter.process(&[ms(800), ms(810), ms(790), ms(500), ms(805)]);
+        assert_eq!(vec![ms(800), ms(810), ms(790), ms(800), ms(805)], filtered);
+        assert_eq!(1, filter.interpolated);
+        assert_eq!(0, filter.rejected);
+    }
+
+    #[test]
+    fn filter_drops_rejected_from_output() {
+        let mut filter = RrFilter::new();
+        let filtered = filter.process(&[ms(800), ms(150), ms(810), ms(3000), ms(790)]);
+        assert_eq!(vec![ms(800), ms(810), ms(790)], filtered);
+        assert_eq!(2, filter.rejected);
+    }
+
+    #[test]
+    fn filter_follows_sustained_change() {
+        let mut filter = RrFilter::new();
+        filter.process(&[ms(1000); 5]);
+        // Someone started running, first few get smoothed over...
+        for _ in 0..3 {
+            assert_eq!(RrVerdict::Interpolated(ms(1000)), filter.check(ms(600)));
+        }
+        // ...but once it's the majority it's the new normal.
+        assert_eq!(RrVerdict::Accepted(ms(600)), filter.check(ms(600)));
+        assert_eq!(3, filter.interpolated);
+    }
+
+    #[test]
+    fn filter_reset() {
+        let mut filter = RrFilter::new();
+        filter.process(&[ms(100), ms(800), ms(800), ms(800), ms(400)]);
+        filter.reset();
+        assert_eq!(0, filter.rejected);
+        assert_eq!(0, filter.interpolated);
+        assert_eq!(None, filter.local_median());
+    }
+}
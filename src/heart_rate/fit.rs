@@ -0,0 +1,551 @@
+// FIT activity files from recorded sessions, for Garmin Connect, Strava and friends.
+//
+// Only what a heart rate strap can fill in: file_id, timer events, a record per
+// notification, HRV messages with the RR intervals, then a lap, session and
+// activity summing it all up. Everything's little endian, and each message
+// type is defined once up front so data messages are just a header byte and values.
+//
+// Sessions get exported next to their CSV, `0003.csv` becomes `0003.fit`.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, SyncSender, TrySendError},
+    time::Duration,
+};
+
+use log::{info, warn};
+
+use super::session::{session_number, SessionParseError, SessionRow, SESSION_HEADER};
+use crate::clock::WallClock;
+
+/// FIT times count from 1989-12-31T00:00:00Z.
+const FIT_EPOCH_UNIX: u64 = 631_065_600;
+const HEADER_SIZE: u8 = 14;
+/// 1.0, we don't need anything 2.0 added.
+const PROTOCOL_VERSION: u8 = 0x10;
+/// 21.00
+const PROFILE_VERSION: u16 = 2100;
+/// `manufacturer` for anything that's not a registered product.
+const MANUFACTURER_DEVELOPMENT: u16 = 255;
+/// HRV messages hold this many RR intervals, extras go in another one.
+const RR_PER_HRV: usize = 5;
+
+// Base types
+const ENUM: u8 = 0x00;
+const UINT8: u8 = 0x02;
+const UINT16: u8 = 0x84;
+const UINT32: u8 = 0x86;
+
+const INVALID_UINT8: u8 = 0xFF;
+const INVALID_UINT16: u16 = 0xFFFF;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FitError {
+    #[error("Session never had the clock set, can't tell when it was")]
+    NoWallClock,
+    #[error(transparent)]
+    Session(#[from] SessionParseError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// CRC-16 the way FIT does it, four bits at a time.
+pub fn crc16(mut crc: u16, bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+    for &byte in bytes {
+        for nibble in [byte & 0xF, byte >> 4] {
+            let low = TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) & 0x0FFF;
+            crc = crc ^ low ^ TABLE[nibble as usize];
+        }
+    }
+    crc
+}
+
+/// A message type, and the fields we fill in, as (number, size, base type).
+struct Definition {
+    local: u8,
+    global: u16,
+    fields: &'static [(u8, u8, u8)],
+}
+
+const FILE_ID: Definition = Definition {
+    local: 0,
+    global: 0,
+    // type, manufacturer, product, time_created
+    fields: &[(0, 1, ENUM), (1, 2, UINT16), (2, 2, UINT16), (4, 4, UINT32)],
+};
+const EVENT: Definition = Definition {
+    local: 1,
+    global: 21,
+    // timestamp, event, event_type
+    fields: &[(253, 4, UINT32), (0, 1, ENUM), (1, 1, ENUM)],
+};
+const RECORD: Definition = Definition {
+    local: 2,
+    global: 20,
+    // timestamp, heart_rate
+    fields: &[(253, 4, UINT32), (3, 1, UINT8)],
+};
+const HRV: Definition = Definition {
+    local: 3,
+    global: 78,
+    // time, an array of RR intervals in ms
+    fields: &[(0, 2 * RR_PER_HRV as u8, UINT16)],
+};
+const LAP: Definition = Definition {
+    local: 4,
+    global: 19,
+    // timestamp, start_time, total_elapsed_time, total_timer_time, event, event_type,
+    // avg_heart_rate, max_heart_rate
+    fields: &[
+        (253, 4, UINT32),
+        (2, 4, UINT32),
+        (7, 4, UINT32),
+        (8, 4, UINT32),
+        (0, 1, ENUM),
+        (1, 1, ENUM),
+        (15, 1, UINT8),
+        (16, 1, UINT8),
+    ],
+};
+const SESSION: Definition = Definition {
+    local: 5,
+    global: 18,
+    // timestamp, start_time, total_elapsed_time, total_timer_time, first_lap_index, num_laps,
+    // event, event_type, sport, sub_sport, avg_heart_rate, max_heart_rate
+    fields: &[
+        (253, 4, UINT32),
+        (2, 4, UINT32),
+        (7, 4, UINT32),
+        (8, 4, UINT32),
+        (25, 2, UINT16),
+        (26, 2, UINT16),
+        (0, 1, ENUM),
+        (1, 1, ENUM),
+        (5, 1, ENUM),
+        (6, 1, ENUM),
+        (16, 1, UINT8),
+        (17, 1, UINT8),
+    ],
+};
+const ACTIVITY: Definition = Definition {
+    local: 6,
+    global: 34,
+    // timestamp, total_timer_time, local_timestamp, num_sessions, type, event, event_type
+    fields: &[
+        (253, 4, UINT32),
+        (0, 4, UINT32),
+        (5, 4, UINT32),
+        (1, 2, UINT16),
+        (2, 1, ENUM),
+        (3, 1, ENUM),
+        (4, 1, ENUM),
+    ],
+};
+
+// Enum values from the FIT profile
+const FILE_ACTIVITY: u32 = 4;
+const EVENT_TIMER: u32 = 0;
+const EVENT_SESSION: u32 = 8;
+const EVENT_LAP: u32 = 9;
+const EVENT_ACTIVITY: u32 = 26;
+const EVENT_TYPE_START: u32 = 0;
+const EVENT_TYPE_STOP: u32 = 1;
+const EVENT_TYPE_STOP_ALL: u32 = 4;
+const SPORT_GENERIC: u32 = 0;
+const ACTIVITY_MANUAL: u32 = 0;
+
+/// When a recording started, going by the first row that knows the time.
+pub fn session_start(row: &SessionRow) -> Option<WallClock> {
+    let clock = row.wall_clock?;
+    Some(WallClock {
+        unix: clock.unix.checked_sub(row.elapsed)?,
+        utc_offset_min: clock.utc_offset_min,
+    })
+}
+
+pub struct FitEncoder {
+    bytes: Vec<u8>,
+    start: WallClock,
+    /// Of the latest row.
+    elapsed: Duration,
+    bpm_sum: u64,
+    bpm_count: u64,
+    max_bpm: u8,
+}
+
+impl FitEncoder {
+    pub fn new(start: WallClock) -> Self {
+        let mut encoder = Self {
+            bytes: vec![0; HEADER_SIZE as usize],
+            start,
+            elapsed: Duration::ZERO,
+            bpm_sum: 0,
+            bpm_count: 0,
+            max_bpm: 0,
+        };
+        let started = encoder.timestamp(Duration::ZERO);
+        encoder.define(&FILE_ID);
+        encoder.data(
+            &FILE_ID,
+            &[FILE_ACTIVITY, MANUFACTURER_DEVELOPMENT.into(), 0, started],
+        );
+        encoder.define(&EVENT);
+        encoder.data(&EVENT, &[started, EVENT_TIMER, EVENT_TYPE_START]);
+        encoder.define(&RECORD);
+        encoder.define(&HRV);
+        encoder
+    }
+    /// Adds a row's heart rate, and its RR intervals if there are any.
+    pub fn record(&mut self, row: &SessionRow) {
+        // No contact, leave a gap rather than a drop to zero (or whatever the strap
+        // kept sending after it lost contact)
+        let measurement = &row.measurement;
+        let bpm = if measurement.bpm == 0 || measurement.is_sensor_contact_detected == Some(false) {
+            INVALID_UINT8
+        } else {
+            measurement.bpm.min(INVALID_UINT8 as u16 - 1) as u8
+        };
+        let timestamp = self.timestamp(row.elapsed);
+        self.data(&RECORD, &[timestamp, bpm.into()]);
+        for chunk in row.measurement.rr_intervals.chunks(RR_PER_HRV) {
+            self.bytes.push(HRV.local);
+            for slot in 0..RR_PER_HRV {
+                let rr = match chunk.get(slot) {
+                    Some(rr) => rr.as_millis().min(INVALID_UINT16 as u128 - 1) as u16,
+                    None => INVALID_UINT16,
+                };
+                self.bytes.extend(rr.to_le_bytes());
+            }
+        }
+
+        self.elapsed = self.elapsed.max(row.elapsed);
+        // No contact readings would drag the average down
+        if bpm != INVALID_UINT8 {
+            self.bpm_sum += bpm as u64;
+            self.bpm_count += 1;
+            self.max_bpm = self.max_bpm.max(bpm);
+        }
+    }
+    /// Sums it all up and seals the file.
+    pub fn finish(mut self) -> Vec<u8> {
+        let started = self.timestamp(Duration::ZERO);
+        let ended = self.timestamp(self.elapsed);
+        let total_ms = self.elapsed.as_millis().min(u32::MAX as u128) as u32;
+        let (avg_bpm, max_bpm) = match self.bpm_count {
+            0 => (INVALID_UINT8, INVALID_UINT8),
+            count => (
+                (self.bpm_sum as f32 / count as f32).round() as u8,
+                self.max_bpm,
+            ),
+        };
+        self.data(&EVENT, &[ended, EVENT_TIMER, EVENT_TYPE_STOP_ALL]);
+        self.define(&LAP);
+        #[rustfmt::skip]
+        self.data(&LAP, &[
+            ended, started, total_ms, total_ms,
+            EVENT_LAP, EVENT_TYPE_STOP,
+            avg_bpm.into(), max_bpm.into(),
+        ]);
+        self.define(&SESSION);
+        #[rustfmt::skip]
+        self.data(&SESSION, &[
+            ended, started, total_ms, total_ms,
+            0, 1,
+            EVENT_SESSION, EVENT_TYPE_STOP,
+            SPORT_GENERIC, 0,
+            avg_bpm.into(), max_bpm.into(),
+        ]);
+        let local_offset = self.start.utc_offset_min as i64 * 60;
+        let local_ended = (ended as i64 + local_offset).clamp(0, u32::MAX as i64) as u32;
+        self.define(&ACTIVITY);
+        #[rustfmt::skip]
+        self.data(&ACTIVITY, &[
+            ended, total_ms, local_ended,
+            1, ACTIVITY_MANUAL,
+            EVENT_ACTIVITY, EVENT_TYPE_STOP,
+        ]);
+
+        let data_size = (self.bytes.len() - HEADER_SIZE as usize) as u32;
+        let mut header = vec![HEADER_SIZE, PROTOCOL_VERSION];
+        header.extend(PROFILE_VERSION.to_le_bytes());
+        header.extend(data_size.to_le_bytes());
+        header.extend(b".FIT");
+        header.extend(crc16(0, &header).to_le_bytes());
+        self.bytes[..HEADER_SIZE as usize].copy_from_slice(&header);
+        self.bytes.extend(crc16(0, &self.bytes).to_le_bytes());
+        self.bytes
+    }
+    fn timestamp(&self, elapsed: Duration) -> u32 {
+        let unix = (self.start.unix + elapsed).as_secs();
+        unix.saturating_sub(FIT_EPOCH_UNIX).min(u32::MAX as u64) as u32
+    }
+    fn define(&mut self, definition: &Definition) {
+        // Reserved, then little endian
+        self.bytes.extend([0x40 | definition.local, 0, 0]);
+        self.bytes.extend(definition.global.to_le_bytes());
+        self.bytes.push(definition.fields.len() as u8);
+        for &(number, size, base_type) in definition.fields {
+            self.bytes.extend([number, size, base_type]);
+        }
+    }
+    /// Values in the same order as the definition's fields, cut down to their size.
+    fn data(&mut self, definition: &Definition, values: &[u32]) {
+        debug_assert_eq!(definition.fields.len(), values.len());
+        self.bytes.push(definition.local);
+        for (&(_, size, _), value) in definition.fields.iter().zip(values) {
+            self.bytes
+                .extend_from_slice(&value.to_le_bytes()[..size as usize]);
+        }
+    }
+}
+
+/// Goes through a recording a row at a time, skipping ones that don't parse.
+fn for_each_row(csv: &Path, mut f: impl FnMut(SessionRow) -> bool) -> Result<(), FitError> {
+    let mut lines = BufReader::new(fs::File::open(csv)?).lines();
+    let header = lines.next().transpose()?;
+    if header.as_deref().map(str::trim) != Some(SESSION_HEADER) {
+        return Err(SessionParseError::MissingHeader.into());
+    }
+    for line in lines {
+        if let Ok(row) = SessionRow::from_csv(&line?) {
+            if !f(row) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a `.fit` next to a recording. Goes through it twice rather than
+/// holding a whole workout's worth of rows.
+pub fn export_session(csv: &Path) -> Result<PathBuf, FitError> {
+    let mut rows = 0;
+    let mut start = None;
+    for_each_row(csv, |row| {
+        rows += 1;
+        start = session_start(&row);
+        start.is_none()
+    })?;
+    if rows == 0 {
+        return Err(SessionParseError::Empty.into());
+    }
+    let mut encoder = FitEncoder::new(start.ok_or(FitError::NoWallClock)?);
+    for_each_row(csv, |row| {
+        encoder.record(&row);
+        true
+    })?;
+
+    // Only shows up once it's all there, so a pulled card doesn't leave half a file
+    let fit = csv.with_extension("fit");
+    let partial = csv.with_extension("tmp");
+    fs::write(&partial, encoder.finish())?;
+    fs::rename(&partial, &fit)?;
+    Ok(fit)
+}
+
+/// Recordings in `dir` that haven't been exported yet.
+pub fn unexported_sessions(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut names = Vec::new();
+    let mut exported = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        match name.rsplit_once('.') {
+            Some((stem, extension)) if extension.eq_ignore_ascii_case("fit") => {
+                exported.push(stem.to_string());
+            }
+            _ => names.push(name),
+        }
+    }
+    let mut pending: Vec<(u32, PathBuf)> = names
+        .iter()
+        .filter_map(|name| {
+            let number = session_number(name)?;
+            let (stem, _) = name.rsplit_once('.')?;
+            (!exported.iter().any(|done| done == stem)).then(|| (number, dir.join(name)))
+        })
+        .collect();
+    pending.sort();
+    Ok(pending.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Converts recordings in the background, exporting can take a few seconds.
+pub struct FitExporter {
+    tx: SyncSender<PathBuf>,
+}
+
+impl FitExporter {
+    /// Starts on everything already in `dir`. Finished recordings get sent over later.
+    pub fn spawn(dir: &Path) -> io::Result<Self> {
+        // Listed now so a recording that starts while it's busy isn't picked up half done
+        let backlog = unexported_sessions(dir)?;
+        let (tx, rx) = mpsc::sync_channel::<PathBuf>(4);
+        std::thread::Builder::new()
+            .stack_size(6000)
+            .spawn(move || {
+                for csv in backlog.into_iter().chain(rx) {
+                    match export_session(&csv) {
+                        Ok(fit) => info!("Exported {fit:?}"),
+                        Err(e) => warn!("Couldn't export {csv:?}: {e}"),
+                    }
+                }
+            })?;
+        Ok(Self { tx })
+    }
+    pub fn export(&self, csv: PathBuf) {
+        if let Err(TrySendError::Full(csv) | TrySendError::Disconnected(csv)) =
+            self.tx.try_send(csv)
+        {
+            warn!("Couldn't queue {csv:?} for export, it'll go next boot");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        crc16, export_session, session_start, unexported_sessions, FitEncoder, FitError,
+        INVALID_UINT8,
+    };
+    use crate::{
+        clock::WallClock,
+        heart_rate::{
+            measurement::HeartRateMeasurement,
+            session::{SessionRow, SESSION_HEADER},
+        },
+    };
+    use std::{fs, time::Duration};
+
+    /// 2024-12-22T14:03:09Z, 1103810589 in FIT time.
+    const START_UNIX: u64 = 1_734_876_189;
+
+    fn row(elapsed_ms: u64, bpm: u16, rr_ms: &[u64]) -> SessionRow {
+        SessionRow {
+            elapsed: Duration::from_millis(elapsed_ms),
+            wall_clock: None,
+            measurement: HeartRateMeasurement {
+                bpm,
+                is_sensor_contact_detected: Some(true),
+                energy_expended: None,
+                rr_intervals: rr_ms.iter().copied().map(Duration::from_millis).collect(),
+            },
+            battery: None,
+        }
+    }
+
+    #[test]
+    fn fit_crc() {
+        // Same as CRC-16/ARC
+        assert_eq!(0xBB3D, crc16(0, b"123456789"));
+        assert_eq!(0, crc16(0, &[]));
+        // Running over the CRC leaves zero, which is how readers check it
+        let mut bytes = b"123456789".to_vec();
+        bytes.extend(crc16(0, &bytes).to_le_bytes());
+        assert_eq!(0, crc16(0, &bytes));
+    }
+
+    #[test]
+    fn fit_session_start() {
+        let mut late = row(90_000, 70, &[]);
+        assert_eq!(None, session_start(&late));
+        late.wall_clock = Some(WallClock {
+            unix: Duration::from_secs(START_UNIX + 90),
+            utc_offset_min: 60,
+        });
+        let start = session_start(&late).unwrap();
+        assert_eq!(Duration::from_secs(START_UNIX), start.unix);
+        assert_eq!(60, start.utc_offset_min);
+    }
+
+    #[test]
+    fn fit_encodes_fixture() {
+        let mut encoder = FitEncoder::new(WallClock {
+            unix: Duration::from_secs(START_UNIX),
+            utc_offset_min: 60,
+        });
+        encoder.record(&row(0, 60, &[]));
+        encoder.record(&row(1000, 90, &[1000, 650, 640, 630, 620, 610]));
+        let fit = encoder.finish();
+
+        // Hand assembled from the FIT SDK profile. There was no decoder around to
+        // check it with, so after changing it run it through the SDK's
+        // `java -jar FitCSVTool.jar two_rows.fit` (or fitparse) and make sure it
+        // decodes cleanly: two records, one hrv message carrying over into a second.
+        let expected: &[u8] = include_bytes!("testdata/two_rows.fit");
+        assert_eq!(expected, &fit[..]);
+        assert_eq!(0, crc16(0, &fit));
+    }
+
+    #[test]
+    fn fit_summary_skips_no_contact() {
+        let mut encoder = FitEncoder::new(WallClock {
+            unix: Duration::from_secs(START_UNIX),
+            utc_offset_min: 0,
+        });
+        encoder.record(&row(0, 0, &[]));
+        // Invalid rather than zero
+        assert_eq!(Some(&INVALID_UINT8), encoder.bytes.last());
+        encoder.record(&row(1000, 100, &[]));
+        // Straps keep sending their last BPM after losing contact
+        let mut lost = row(2000, 140, &[]);
+        lost.measurement.is_sensor_contact_detected = Some(false);
+        encoder.record(&lost);
+        assert_eq!(Some(&INVALID_UINT8), encoder.bytes.last());
+        assert_eq!(100, encoder.bpm_sum / encoder.bpm_count);
+        assert_eq!(100, encoder.max_bpm);
+        let fit = encoder.finish();
+        assert_eq!(0, crc16(0, &fit));
+    }
+
+    #[test]
+    fn fit_exports_from_card() {
+        let dir = std::env::temp_dir().join(format!("fit_export_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Clock got set partway through
+        let synced = format!("{SESSION_HEADER}\n0,,,60,1,,,\n1000,1734876190000,2024-12-22T15:03:10+01:00,61,1,,,980\n");
+        fs::write(dir.join("0001.csv"), synced).unwrap();
+        fs::write(
+            dir.join("0002.csv"),
+            format!("{SESSION_HEADER}\n0,,,60,1,,,\n"),
+        )
+        .unwrap();
+        fs::write(dir.join("0003.csv"), "half a hea").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let pending = unexported_sessions(&dir).unwrap();
+        let names: Vec<_> = pending
+            .iter()
+            .map(|path| path.file_name().unwrap())
+            .collect();
+        assert_eq!(vec!["0001.csv", "0002.csv", "0003.csv"], names);
+
+        let fit = export_session(&pending[0]).unwrap();
+        assert_eq!(dir.join("0001.fit"), fit);
+        let bytes = fs::read(&fit).unwrap();
+        assert_eq!(b".FIT", &bytes[8..12]);
+        assert_eq!(0, crc16(0, &bytes));
+        assert!(matches!(
+            export_session(&pending[1]),
+            Err(FitError::NoWallClock)
+        ));
+        assert!(matches!(
+            export_session(&pending[2]),
+            Err(FitError::Session(_))
+        ));
+
+        let pending = unexported_sessions(&dir).unwrap();
+        assert_eq!(2, pending.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,135 @@
+// Several monitors at once, for showing a whole group's heart rates on one badge.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::TryRecvError;
+
+use log::{error, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+use super::ble::{BleIdents, BleMacLe, MonitorHandle, MonitorOptions, MonitorReply, MonitorState};
+
+/// Each one's a connection and a supervisor thread, NimBLE only allows a handful.
+pub const MAX_TRACKED: usize = 4;
+
+/// Enough to fit in a grid cell.
+const MAX_LABEL_LEN: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrackedMonitor {
+    pub ident: BleIdents,
+    /// What the grid shows instead of the strap's name.
+    pub label: String,
+}
+
+impl TrackedMonitor {
+    pub fn new(ident: BleIdents) -> Self {
+        let label = ident.name.chars().take(MAX_LABEL_LEN).collect();
+        Self { ident, label }
+    }
+}
+
+/// What the grid needs to know about each tracked monitor.
+#[derive(Debug, Clone, Default)]
+pub struct GroupEntry {
+    pub state: Option<MonitorState>,
+    pub bpm: Option<u16>,
+    pub contact_lost: bool,
+}
+
+impl GroupEntry {
+    pub fn linked(&self) -> bool {
+        self.state == Some(MonitorState::Subscribed)
+    }
+}
+
+/// One supervisor per tracked monitor, keyed by MAC.
+#[derive(Default)]
+pub struct MonitorRegistry {
+    monitors: BTreeMap<BleMacLe, (MonitorHandle, GroupEntry)>,
+    options: MonitorOptions,
+}
+
+impl MonitorRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Starts supervisors for newly tracked monitors, and stops ones that aren't anymore.
+    /// All of them start over if `options` changed.
+    pub fn sync(&mut self, tracked: &[TrackedMonitor], options: MonitorOptions) -> Result<()> {
+        let tracked = &tracked[..tracked.len().min(MAX_TRACKED)];
+        // Supervisors only take their options when they're built
+        if options != self.options {
+            self.monitors.clear();
+            self.options = options;
+        }
+        self.monitors
+            .retain(|mac, _| tracked.iter().any(|monitor| monitor.ident.mac == *mac));
+        for monitor in tracked {
+            if self.monitors.contains_key(&monitor.ident.mac) {
+                continue;
+            }
+            let handle = MonitorHandle::build(monitor.ident.clone(), options)?;
+            self.monitors
+                .insert(monitor.ident.mac, (handle, GroupEntry::default()));
+        }
+        Ok(())
+    }
+    /// Drops every handle, which disconnects them all.
+    pub fn clear(&mut self) {
+        self.monitors.clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.monitors.is_empty()
+    }
+    pub fn get(&self, mac: &BleMacLe) -> Option<&GroupEntry> {
+        self.monitors.get(mac).map(|(_, entry)| entry)
+    }
+    /// Drains every monitor's replies, returning the ones that changed.
+    pub fn poll(&mut self) -> Result<Vec<BleMacLe>> {
+        let mut changed = Vec::new();
+        let mut dead = Vec::new();
+        for (mac, (handle, entry)) in self.monitors.iter_mut() {
+            loop {
+                match handle.reply_rx.try_recv() {
+                    Ok(MonitorReply::MonitorStatus(status)) => {
+                        entry.contact_lost = status.contact_lost();
+                        if status.heart_rate_bpm > 0 {
+                            entry.bpm = Some(status.heart_rate_bpm);
+                        }
+                        changed.push(*mac);
+                    }
+                    Ok(MonitorReply::State(state)) => {
+                        entry.state = Some(state);
+                        if state == MonitorState::Subscribed {
+                            entry.contact_lost = false;
+                        }
+                        changed.push(*mac);
+                    }
+                    Ok(MonitorReply::Error(e)) => {
+                        warn!("{} reported an error: {e}", handle.ident.name);
+                    }
+                    Ok(MonitorReply::PairingFailed(e)) => {
+                        warn!("Couldn't pair with {}: {e}", handle.ident.name);
+                    }
+                    Ok(_) => (),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        error!("Supervisor for {} died! Restarting...", handle.ident.name);
+                        dead.push(handle.ident.clone());
+                        break;
+                    }
+                }
+            }
+        }
+        for ident in dead {
+            let mac = ident.mac;
+            let handle = MonitorHandle::build(ident, self.options)?;
+            self.monitors.insert(mac, (handle, GroupEntry::default()));
+            changed.push(mac);
+        }
+        changed.dedup();
+        Ok(changed)
+    }
+}
@@ -0,0 +1,203 @@
+// Time-domain HRV over a rolling window of RR intervals.
+//
+// Only meaningful when the strap actually reports RR intervals, anything
+// derived from `rr_from_bpm` is just the BPM wearing a trench coat.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Enough for roughly a minute of beats at a resting heart rate.
+pub const DEFAULT_HRV_WINDOW: usize = 64;
+
+/// Fewer intervals than this and the numbers are mostly noise.
+const MIN_INTERVALS: usize = 8;
+
+const NN50_THRESHOLD_MS: f32 = 50.0;
+
+#[derive(Debug, Clone)]
+pub struct HrvWindow {
+    intervals: VecDeque<(Duration, bool)>,
+    capacity: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HrvStats {
+    pub mean_nn_ms: f32,
+    pub sdnn_ms: f32,
+    pub rmssd_ms: f32,
+    /// Percentage (0-100) of successive differences over 50ms.
+    pub pnn50: f32,
+    pub count: usize,
+    /// `false` if any interval in the window was synthesized from BPM.
+    pub valid: bool,
+}
+
+impl Default for HrvWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_HRV_WINDOW)
+    }
+}
+
+impl HrvWindow {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2);
+        Self {
+            intervals: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+    pub fn push(&mut self, rr: Duration, synthesized: bool) {
+        if self.intervals.len() == self.capacity {
+            self.intervals.pop_front();
+        }
+        self.intervals.push_back((rr, synthesized));
+    }
+    pub fn extend(&mut self, rr_intervals: &[Duration], synthesized: bool) {
+        for rr in rr_intervals {
+            self.push(*rr, synthesized);
+        }
+    }
+    pub fn clear(&mut self) {
+        self.intervals.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+    /// `true` if every interval in the window came from the strap itself.
+    pub fn is_real(&self) -> bool {
+        self.intervals.iter().all(|(_, synthesized)| !synthesized)
+    }
+    /// Returns `None` until the window has enough intervals to say anything.
+    pub fn stats(&self) -> Option<HrvStats> {
+        let count = self.intervals.len();
+        if count < MIN_INTERVALS {
+            return None;
+        }
+        let nn_ms: Vec<f32> = self
+            .intervals
+            .iter()
+            .map(|(rr, _)| rr.as_secs_f32() * 1000.0)
+            .collect();
+
+        let mean_nn_ms = nn_ms.iter().sum::<f32>() / count as f32;
+        let variance = nn_ms
+            .iter()
+            .map(|nn| (nn - mean_nn_ms).powi(2))
+            .sum::<f32>()
+            / (count - 1) as f32;
+
+        let diffs: Vec<f32> = nn_ms.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let rmssd_ms = (diffs.iter().map(|d| d * d).sum::<f32>() / diffs.len() as f32).sqrt();
+        let nn50 = diffs.iter().filter(|d| d.abs() > NN50_THRESHOLD_MS).count();
+
+        Some(HrvStats {
+            mean_nn_ms,
+            sdnn_ms: variance.sqrt(),
+            rmssd_ms,
+            pnn50: nn50 as f32 / diffs.len() as f32 * 100.0,
+            count,
+            valid: self.is_real(),
+        })
+    }
+}
+
+impl HrvStats {
+    /// A 0-100 "calm score" from ln(RMSSD), the same scaling most HRV apps use.
+    ///
+    /// `None` if the window wasn't real HRV.
+    pub fn calm_score(&self) -> Option<u8> {
+        if !self.valid {
+            return None;
+        }
+        if self.rmssd_ms <= 1.0 {
+            return Some(0);
+        }
+        // ln(RMSSD) tops out around 6.5 for even very relaxed people
+        let score = self.rmssd_ms.ln() / 6.5 * 100.0;
+        Some(score.clamp(0.0, 100.0) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HrvWindow;
+    use std::time::Duration;
+
+    fn ms(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|v| Duration::from_millis(*v)).collect()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn hrv_not_enough_intervals() {
+        let mut window = HrvWindow::default();
+        window.extend(&ms(&[800, 810, 790]), false);
+        assert_eq!(None, window.stats());
+    }
+
+    #[test]
+    fn hrv_known_values() {
+        let mut window = HrvWindow::default();
+        window.extend(&ms(&[800, 850, 780, 900, 820, 760, 880, 810]), false);
+        let stats = window.stats().unwrap();
+        assert_eq!(8, stats.count);
+        assert!(stats.valid);
+        assert!(close(stats.mean_nn_ms, 825.0));
+        // Sample standard deviation
+        assert!(close(stats.sdnn_ms, (16_400.0f32 / 7.0).sqrt()));
+        // Successive diffs: 50, -70, 120, -80, -60, 120, -70
+        assert!(close(stats.rmssd_ms, (51_100.0f32 / 7.0).sqrt()));
+        // All but the first are over 50ms
+        assert!(close(stats.pnn50, 6.0 / 7.0 * 100.0));
+    }
+
+    #[test]
+    fn hrv_steady_rhythm() {
+        let mut window = HrvWindow::default();
+        window.extend(&ms(&[1000; 10]), false);
+        let stats = window.stats().unwrap();
+        assert!(close(stats.mean_nn_ms, 1000.0));
+        assert!(close(stats.sdnn_ms, 0.0));
+        assert!(close(stats.rmssd_ms, 0.0));
+        assert!(close(stats.pnn50, 0.0));
+        assert_eq!(Some(0), stats.calm_score());
+    }
+
+    #[test]
+    fn hrv_window_rolls() {
+        let mut window = HrvWindow::new(8);
+        window.extend(&ms(&[2000; 8]), false);
+        window.extend(&ms(&[1000; 8]), false);
+        assert_eq!(8, window.len());
+        assert!(close(window.stats().unwrap().mean_nn_ms, 1000.0));
+    }
+
+    #[test]
+    fn hrv_synthesized_is_invalid() {
+        let mut window = HrvWindow::new(8);
+        window.extend(&ms(&[800, 850, 780, 900, 820, 760, 880]), false);
+        window.push(Duration::from_millis(810), true);
+        let stats = window.stats().unwrap();
+        assert!(!stats.valid);
+        assert_eq!(None, stats.calm_score());
+
+        // Once the synthesized interval rolls out, it's valid again
+        window.push(Duration::from_millis(810), false);
+        assert!(!window.stats().unwrap().valid);
+        window.extend(&ms(&[800; 7]), false);
+        assert!(window.stats().unwrap().valid);
+    }
+
+    #[test]
+    fn hrv_calm_score_clamped() {
+        let mut window = HrvWindow::default();
+        window.extend(&ms(&[400, 1600, 400, 1600, 400, 1600, 400, 1600]), false);
+        assert_eq!(Some(100), window.stats().unwrap().calm_score());
+    }
+}
@@ -0,0 +1,364 @@
+// Records sessions to the SD card, see `session` for the format.
+//
+// Rows are buffered and appended in chunks, opening and closing the file each
+// time. FAT isn't rewriting a sector per notification that way, and pulling
+// the card only loses what's in the buffer. Writes keep getting retried, so
+// putting the card back in picks up where it left off. All of it happens on a
+// writer thread, a slow card shouldn't hold up the UI.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, SyncSender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use super::session::{next_session_name, SessionRow, SESSION_DIR, SESSION_HEADER};
+
+/// A few notifications short of a FAT cluster.
+const FLUSH_BYTES: usize = 4096;
+/// Don't sit on rows longer than this, even if there's not many.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// If the card's gone for a while, the oldest rows past this get dropped.
+const MAX_BUFFERED: usize = 32 * 1024;
+/// Rows waiting for the writer thread. It's only slow if the card is, and
+/// then the logger's dropping rows anyway.
+const ROW_QUEUE: usize = 16;
+
+pub trait SessionSink {
+    /// Adds `chunk` to the end of the recording, or nothing at all if it fails.
+    fn append(&mut self, chunk: &[u8]) -> io::Result<()>;
+}
+
+pub struct FileSink {
+    pub path: PathBuf,
+    /// How much of the file is from appends that worked.
+    written: u64,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, written: 0 }
+    }
+}
+
+impl SessionSink for FileSink {
+    fn append(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // Anything past that is part of a chunk that failed, it's getting written again
+        if file.metadata()?.len() > self.written {
+            file.set_len(self.written)?;
+        }
+        file.write_all(chunk)?;
+        file.sync_all()?;
+        self.written += chunk.len() as u64;
+        Ok(())
+    }
+}
+
+pub struct SessionLogger<S: SessionSink> {
+    sink: S,
+    started: Instant,
+    buffer: String,
+    header_written: bool,
+    last_flush: Duration,
+    /// Rows lost because the card was gone too long.
+    pub dropped: usize,
+}
+
+impl SessionLogger<FileSink> {
+    /// Starts a new file in `SESSION_DIR`, numbered after the latest one.
+    pub fn create() -> io::Result<Self> {
+        fs::create_dir_all(SESSION_DIR)?;
+        let mut names = Vec::new();
+        for entry in fs::read_dir(SESSION_DIR)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        let name = next_session_name(names.iter().map(String::as_str));
+        let path = Path::new(SESSION_DIR).join(name);
+        info!("Recording session to {path:?}");
+        Ok(Self::new(FileSink::new(path)))
+    }
+    pub fn path(&self) -> &Path {
+        &self.sink.path
+    }
+}
+
+impl<S: SessionSink> SessionLogger<S> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            started: Instant::now(),
+            buffer: String::with_capacity(FLUSH_BYTES),
+            header_written: false,
+            last_flush: Duration::ZERO,
+            dropped: 0,
+        }
+    }
+    /// Since recording started, for `SessionRow::elapsed`.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+    /// Buffers a row, writing everything out if it's due.
+    pub fn record(&mut self, row: &SessionRow) -> io::Result<()> {
+        self.buffer.push_str(&row.to_csv());
+        self.buffer.push('\n');
+        let due = row.elapsed.saturating_sub(self.last_flush) >= FLUSH_INTERVAL;
+        if self.buffer.len() >= FLUSH_BYTES || due {
+            self.last_flush = row.elapsed;
+            return self.flush();
+        }
+        Ok(())
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = if self.header_written {
+            self.sink.append(self.buffer.as_bytes())
+        } else {
+            let chunk = format!("{SESSION_HEADER}\n{}", self.buffer);
+            self.sink.append(chunk.as_bytes())
+        };
+        match result {
+            Ok(()) => {
+                self.header_written = true;
+                self.buffer.clear();
+            }
+            Err(_) => self.trim_buffer(),
+        }
+        result
+    }
+    /// Drops whole rows off the front until it's under `MAX_BUFFERED`.
+    fn trim_buffer(&mut self) {
+        while self.buffer.len() > MAX_BUFFERED {
+            let row_end = self.buffer.find('\n').map_or(self.buffer.len(), |i| i + 1);
+            self.buffer.drain(..row_end);
+            self.dropped += 1;
+        }
+    }
+}
+
+impl<S: SessionSink> Drop for SessionLogger<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("Lost the end of the session: {e}");
+        }
+    }
+}
+
+/// A `SessionLogger` on its own thread. Dropping it waits for the end of the
+/// session to be written, so the file's finished once it's gone.
+pub struct SessionRecorder {
+    path: PathBuf,
+    started: Instant,
+    row_tx: Option<SyncSender<SessionRow>>,
+    /// The last write didn't make it to the card.
+    failing: Arc<AtomicBool>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl SessionRecorder {
+    /// Starts a new file in `SESSION_DIR`, see `SessionLogger::create`.
+    pub fn spawn() -> io::Result<Self> {
+        let mut logger = SessionLogger::create()?;
+        let path = logger.path().to_path_buf();
+        let started = logger.started;
+        let (row_tx, row_rx) = mpsc::sync_channel::<SessionRow>(ROW_QUEUE);
+        let failing = Arc::new(AtomicBool::new(false));
+        let writer_failing = failing.clone();
+        let writer = std::thread::Builder::new()
+            .stack_size(6000)
+            .spawn(move || loop {
+                let result = match row_rx.recv_timeout(FLUSH_INTERVAL) {
+                    Ok(row) => logger.record(&row),
+                    // Nothing's coming in, don't sit on what's there (or keep retrying it)
+                    Err(RecvTimeoutError::Timeout) => logger.flush(),
+                    // Dropping the logger flushes the rest
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                match result {
+                    Ok(()) => writer_failing.store(false, Ordering::Relaxed),
+                    Err(e) => {
+                        if !writer_failing.swap(true, Ordering::Relaxed) {
+                            warn!("Couldn't write to the session log: {e}");
+                        }
+                    }
+                }
+            })?;
+        Ok(Self {
+            path,
+            started,
+            row_tx: Some(row_tx),
+            failing,
+            writer: Some(writer),
+        })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Since recording started, for `SessionRow::elapsed`.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+    pub fn failing(&self) -> bool {
+        self.failing.load(Ordering::Relaxed)
+    }
+    pub fn record(&self, row: SessionRow) {
+        if let Some(row_tx) = &self.row_tx {
+            // Only full if the card's stuck, the writer's already in trouble
+            _ = row_tx.try_send(row);
+        }
+    }
+}
+
+impl Drop for SessionRecorder {
+    fn drop(&mut self) {
+        self.row_tx = None;
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                warn!("Session writer panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSink, SessionLogger, SessionSink, FLUSH_BYTES, FLUSH_INTERVAL, MAX_BUFFERED};
+    use crate::heart_rate::{
+        measurement::HeartRateMeasurement,
+        session::{parse_session, SessionRow},
+    };
+    use std::{
+        cell::RefCell,
+        fs,
+        io::{self, Write},
+        rc::Rc,
+        time::Duration,
+    };
+
+    /// Shared so the test can peek while the logger owns it.
+    #[derive(Clone, Default)]
+    struct FakeCard {
+        written: Rc<RefCell<String>>,
+        appends: Rc<RefCell<usize>>,
+        pulled: Rc<RefCell<bool>>,
+    }
+
+    impl SessionSink for FakeCard {
+        fn append(&mut self, chunk: &[u8]) -> io::Result<()> {
+            if *self.pulled.borrow() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "card pulled"));
+            }
+            *self.appends.borrow_mut() += 1;
+            self.written
+                .borrow_mut()
+                .push_str(std::str::from_utf8(chunk).unwrap());
+            Ok(())
+        }
+    }
+
+    fn row(elapsed_ms: u64, bpm: u16) -> SessionRow {
+        SessionRow {
+            elapsed: Duration::from_millis(elapsed_ms),
+            wall_clock: None,
+            measurement: HeartRateMeasurement {
+                bpm,
+                is_sensor_contact_detected: Some(true),
+                energy_expended: None,
+                rr_intervals: vec![Duration::from_millis(800)],
+            },
+            battery: Some(90),
+        }
+    }
+
+    #[test]
+    fn logger_buffers_until_due() {
+        let card = FakeCard::default();
+        let mut logger = SessionLogger::new(card.clone());
+        for second in 0..10 {
+            logger.record(&row(second * 1000, 70)).unwrap();
+        }
+        assert_eq!(0, *card.appends.borrow());
+
+        // Time's up
+        let late = FLUSH_INTERVAL.as_millis() as u64;
+        logger.record(&row(late, 71)).unwrap();
+        assert_eq!(1, *card.appends.borrow());
+
+        // Or the buffer's full
+        let mut elapsed = late;
+        while *card.appends.borrow() == 1 {
+            elapsed += 1;
+            logger.record(&row(elapsed, 72)).unwrap();
+        }
+        assert!(card.written.borrow().len() >= FLUSH_BYTES);
+
+        logger.record(&row(elapsed + 1, 73)).unwrap();
+        drop(logger);
+        let session = parse_session(&card.written.borrow()).unwrap();
+        assert_eq!(0, session.skipped);
+        assert_eq!(Some(&row(elapsed + 1, 73)), session.rows.last());
+        assert_eq!(row(0, 70), session.rows[0]);
+    }
+
+    #[test]
+    fn logger_survives_card_pull() {
+        let card = FakeCard::default();
+        let mut logger = SessionLogger::new(card.clone());
+        *card.pulled.borrow_mut() = true;
+        logger.record(&row(0, 60)).unwrap();
+        assert!(logger.flush().is_err());
+
+        // Gone long enough to run out of room
+        let mut elapsed = 0;
+        while logger.dropped == 0 {
+            elapsed += 1000;
+            _ = logger.record(&row(elapsed, 61));
+        }
+        assert!(logger.buffer.len() <= MAX_BUFFERED);
+
+        // Back in, everything still buffered makes it, header first
+        *card.pulled.borrow_mut() = false;
+        logger.flush().unwrap();
+        logger.record(&row(elapsed + 1000, 62)).unwrap();
+        logger.flush().unwrap();
+        let session = parse_session(&card.written.borrow()).unwrap();
+        assert_eq!(0, session.skipped);
+        assert_eq!(row(elapsed + 1000, 62), *session.rows.last().unwrap());
+        assert_eq!(
+            Duration::from_millis(logger.dropped as u64 * 1000),
+            session.rows[0].elapsed
+        );
+    }
+
+    #[test]
+    fn file_sink_drops_half_written_chunks() {
+        let path = std::env::temp_dir().join(format!("session_sink_{}.csv", std::process::id()));
+        _ = fs::remove_file(&path);
+        let mut sink = FileSink::new(path.clone());
+        sink.append(b"one\n").unwrap();
+        // What a write that failed partway leaves behind
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"tw")
+            .unwrap();
+        sink.append(b"two\n").unwrap();
+        assert_eq!("one\ntwo\n", fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+}
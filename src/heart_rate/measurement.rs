@@ -28,22 +28,49 @@ pub struct HeartRateMeasurement {
     pub rr_intervals: Vec<Duration>,
 }
 
-// Notably, this function always assumes a valid input
-pub fn parse_hrm(data: &[u8]) -> HeartRateMeasurement {
-    let is_16_bit = data[0] & 1 == 1;
-    let has_sensor_detection = data[0] & 0b100 == 0b100;
-    let has_energy_expended = data[0] & 0b1000 == 0b1000;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum HrmParseError {
+    #[error("Empty HRM payload")]
+    Empty,
+    #[error("HRM payload too short for BPM ({0} bytes)")]
+    TruncatedBpm(usize),
+    #[error("HRM payload too short for energy expended ({0} bytes)")]
+    TruncatedEnergy(usize),
+    #[error("HRM payload has an odd-length RR tail ({0} bytes)")]
+    OddRrTail(usize),
+}
+
+/// Parses a Heart Rate Measurement (0x2A37) notification, checking every
+/// field's bounds before reading it.
+pub fn try_parse_hrm(data: &[u8]) -> Result<HeartRateMeasurement, HrmParseError> {
+    let Some(&flags) = data.first() else {
+        return Err(HrmParseError::Empty);
+    };
+    let is_16_bit = flags & 1 == 1;
+    let has_sensor_detection = flags & 0b100 == 0b100;
+    let has_energy_expended = flags & 0b1000 == 0b1000;
     let energy_expended_index = 2 + if is_16_bit { 1 } else { 0 };
-    let rr_interval_index =
-        2 + if has_energy_expended { 2 } else { 0 } + if is_16_bit { 1 } else { 0 };
-    HeartRateMeasurement {
+    let rr_interval_index = energy_expended_index + if has_energy_expended { 2 } else { 0 };
+
+    if data.len() < energy_expended_index {
+        return Err(HrmParseError::TruncatedBpm(data.len()));
+    }
+    if data.len() < rr_interval_index {
+        return Err(HrmParseError::TruncatedEnergy(data.len()));
+    }
+    let rr_tail = &data[rr_interval_index..];
+    if rr_tail.len() % 2 != 0 {
+        return Err(HrmParseError::OddRrTail(rr_tail.len()));
+    }
+
+    Ok(HeartRateMeasurement {
         bpm: if is_16_bit {
             u16::from_le_bytes([data[1], data[2]])
         } else {
             data[1] as u16
         },
         is_sensor_contact_detected: if has_sensor_detection {
-            Some(data[0] & 0b10 == 0b10)
+            Some(flags & 0b10 == 0b10)
         } else {
             None
         },
@@ -55,27 +82,103 @@ pub fn parse_hrm(data: &[u8]) -> HeartRateMeasurement {
         } else {
             None
         },
-        rr_intervals: {
-            let rr_interval_count = (data.len() - rr_interval_index) / 2;
-            let mut vec = Vec::with_capacity(rr_interval_count);
-            for i in 0..rr_interval_count {
-                let as_u16 = u16::from_le_bytes([
-                    data[rr_interval_index + 2 * i],
-                    data[rr_interval_index + 2 * i + 1],
-                ]);
-                vec.push(Duration::from_secs_f32(as_u16 as f32 / 1024.0));
-            }
-            vec
-        },
+        rr_intervals: rr_tail
+            .chunks_exact(2)
+            .map(|rr| {
+                let as_u16 = u16::from_le_bytes([rr[0], rr[1]]);
+                Duration::from_secs_f32(as_u16 as f32 / 1024.0)
+            })
+            .collect(),
+    })
+}
+
+/// The inverse of `try_parse_hrm`.
+///
+/// Picks the smallest encoding that fits, so the flags won't always match
+/// whatever the strap originally sent, but the parsed result will.
+pub fn encode_hrm(measurement: &HeartRateMeasurement) -> Vec<u8> {
+    let is_16_bit = measurement.bpm > u8::MAX as u16;
+    let mut flags = 0;
+    if is_16_bit {
+        flags |= 1;
+    }
+    if let Some(contact) = measurement.is_sensor_contact_detected {
+        flags |= 0b100;
+        if contact {
+            flags |= 0b10;
+        }
+    }
+    if measurement.energy_expended.is_some() {
+        flags |= 0b1000;
+    }
+    if !measurement.rr_intervals.is_empty() {
+        flags |= 0b10000;
+    }
+
+    let mut data = Vec::with_capacity(5 + 2 * measurement.rr_intervals.len());
+    data.push(flags);
+    if is_16_bit {
+        data.extend_from_slice(&measurement.bpm.to_le_bytes());
+    } else {
+        data.push(measurement.bpm as u8);
+    }
+    if let Some(energy) = measurement.energy_expended {
+        data.extend_from_slice(&energy.to_le_bytes());
+    }
+    for rr in &measurement.rr_intervals {
+        let as_u16 = (rr.as_secs_f32() * 1024.0)
+            .round()
+            .clamp(0.0, u16::MAX as f32) as u16;
+        data.extend_from_slice(&as_u16.to_le_bytes());
+    }
+    data
+}
+
+/// Body Sensor Location (0x2A38), where the strap says it's worn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+pub enum BodySensorLocation {
+    Other,
+    Chest,
+    Wrist,
+    Finger,
+    Hand,
+    #[strum(to_string = "Ear Lobe")]
+    EarLobe,
+    Foot,
+    /// Reserved for future use, as of writing.
+    #[strum(to_string = "Unknown ({0})")]
+    Unknown(u8),
+}
+
+impl BodySensorLocation {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        Some(match *data.first()? {
+            0 => Self::Other,
+            1 => Self::Chest,
+            2 => Self::Wrist,
+            3 => Self::Finger,
+            4 => Self::Hand,
+            5 => Self::EarLobe,
+            6 => Self::Foot,
+            other => Self::Unknown(other),
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::parse_hrm;
+    use super::encode_hrm;
+    use super::try_parse_hrm;
+    use super::BodySensorLocation;
     use super::HeartRateMeasurement;
+    use super::HrmParseError;
     use std::time::Duration;
 
+    // The fixtures below are all well formed
+    fn parse_hrm(data: &[u8]) -> HeartRateMeasurement {
+        try_parse_hrm(data).expect("Invalid HRM payload")
+    }
+
     #[test]
     fn parse_hrm_16_bit_energy_expended_and_one_rr_intervals() {
         assert_eq!(
@@ -222,4 +325,124 @@ mod tests {
             parse_hrm(&[0, 70])
         );
     }
+
+    #[test]
+    fn try_parse_hrm_matches_parse_hrm() {
+        let data = [0b11001, 70, 0, 11, 2, 10, 1];
+        assert_eq!(Ok(parse_hrm(&data)), try_parse_hrm(&data));
+    }
+
+    #[test]
+    fn try_parse_hrm_empty() {
+        assert_eq!(Err(HrmParseError::Empty), try_parse_hrm(&[]));
+    }
+
+    #[test]
+    fn try_parse_hrm_missing_bpm() {
+        assert_eq!(Err(HrmParseError::TruncatedBpm(1)), try_parse_hrm(&[0]));
+    }
+
+    #[test]
+    fn try_parse_hrm_16_bit_truncated_bpm() {
+        assert_eq!(Err(HrmParseError::TruncatedBpm(2)), try_parse_hrm(&[1, 70]));
+    }
+
+    #[test]
+    fn try_parse_hrm_missing_energy_expended() {
+        assert_eq!(
+            Err(HrmParseError::TruncatedEnergy(2)),
+            try_parse_hrm(&[0b1000, 70])
+        );
+    }
+
+    #[test]
+    fn try_parse_hrm_16_bit_truncated_energy_expended() {
+        assert_eq!(
+            Err(HrmParseError::TruncatedEnergy(4)),
+            try_parse_hrm(&[0b1001, 70, 0, 10])
+        );
+    }
+
+    #[test]
+    fn try_parse_hrm_odd_rr_tail() {
+        assert_eq!(
+            Err(HrmParseError::OddRrTail(3)),
+            try_parse_hrm(&[0b10000, 70, 10, 1, 11])
+        );
+    }
+
+    #[test]
+    fn try_parse_hrm_energy_expended_and_odd_rr_tail() {
+        assert_eq!(
+            Err(HrmParseError::OddRrTail(1)),
+            try_parse_hrm(&[0b11001, 70, 0, 11, 2, 10])
+        );
+    }
+
+    const FIXTURES: &[&[u8]] = &[
+        &[0b11001, 70, 0, 11, 2, 10, 1],
+        &[0b10001, 70, 0, 10, 1],
+        &[0b10000, 70, 10, 1, 11, 2, 12, 3],
+        &[0b10000, 70, 10, 1],
+        &[0b1001, 70, 0, 10, 1],
+        &[0b1000, 70, 10, 1],
+        &[0b100, 70],
+        &[0b110, 70],
+        &[1, 10, 1],
+        &[1, 70, 0],
+        &[0, 70],
+    ];
+
+    #[test]
+    fn encode_hrm_round_trips_fixtures() {
+        for fixture in FIXTURES {
+            let parsed = parse_hrm(fixture);
+            assert_eq!(parsed, parse_hrm(&encode_hrm(&parsed)), "{fixture:?}");
+        }
+    }
+
+    #[test]
+    fn encode_hrm_smallest_encoding() {
+        // 8-bit BPM fixtures should come back byte-for-byte
+        for fixture in FIXTURES.iter().filter(|f| f[0] & 1 == 0) {
+            assert_eq!(fixture.to_vec(), encode_hrm(&parse_hrm(fixture)));
+        }
+        // 16-bit BPMs that fit in 8 bits get shrunk
+        assert_eq!(
+            vec![0b11000, 70, 11, 2, 10, 1],
+            encode_hrm(&parse_hrm(&[0b11001, 70, 0, 11, 2, 10, 1]))
+        );
+    }
+
+    #[test]
+    fn encode_hrm_16_bit() {
+        assert_eq!(
+            vec![0b111, 10, 1],
+            encode_hrm(&HeartRateMeasurement {
+                bpm: 266,
+                is_sensor_contact_detected: Some(true),
+                energy_expended: None,
+                rr_intervals: Vec::with_capacity(0),
+            })
+        );
+    }
+
+    #[test]
+    fn body_sensor_location() {
+        assert_eq!(
+            Some(BodySensorLocation::Chest),
+            BodySensorLocation::parse(&[1])
+        );
+        assert_eq!(
+            Some(BodySensorLocation::Foot),
+            BodySensorLocation::parse(&[6, 0])
+        );
+        assert_eq!(
+            Some(BodySensorLocation::Unknown(9)),
+            BodySensorLocation::parse(&[9])
+        );
+        assert_eq!(None, BodySensorLocation::parse(&[]));
+        assert_eq!("Ear Lobe", BodySensorLocation::EarLobe.to_string());
+        assert_eq!("Unknown (9)", BodySensorLocation::Unknown(9).to_string());
+    }
 }
@@ -1,2 +1,19 @@
+pub mod beats;
 pub mod ble;
-mod measurement;
+pub mod discovery;
+pub mod ecg;
+pub mod energy;
+pub mod filter;
+pub mod fit;
+pub mod group;
+pub mod hrv;
+pub mod logger;
+pub mod measurement;
+pub mod monitor;
+pub mod peripheral;
+pub mod presence;
+pub mod session;
+pub mod simulate;
+pub mod source;
+pub mod stats;
+pub mod zones;
@@ -0,0 +1,216 @@
+// What a heart rate source and the app say to each other. Kept apart from the
+// BLE side so the made up sources (and their tests) don't need a radio.
+
+use super::{
+    discovery::BleIdents,
+    filter::RrFilter,
+    measurement::{try_parse_hrm, BodySensorLocation, HeartRateMeasurement, HrmParseError},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatteryLevel {
+    #[default]
+    Unknown,
+    NotReported,
+    Level(u8),
+}
+
+impl BatteryLevel {
+    pub fn percent(&self) -> Option<u8> {
+        match self {
+            BatteryLevel::Level(battery) => Some(*battery),
+            _ => None,
+        }
+    }
+    /// From a Battery Level read or notification.
+    pub fn from_slice(data: &[u8]) -> Self {
+        data.first()
+            .map_or(BatteryLevel::NotReported, |level| (*level).min(100).into())
+    }
+    /// A threshold of 0 never warns.
+    pub fn is_low(&self, threshold: u8) -> bool {
+        self.percent().is_some_and(|level| level < threshold)
+    }
+}
+
+impl From<BatteryLevel> for u8 {
+    fn from(level: BatteryLevel) -> Self {
+        match level {
+            BatteryLevel::Level(battery) => battery,
+            _ => 0,
+        }
+    }
+}
+impl From<u8> for BatteryLevel {
+    fn from(val: u8) -> Self {
+        BatteryLevel::Level(val)
+    }
+}
+// Lots of logic yoinked from https://github.com/nullstalgia/iron-heart
+#[derive(Debug, Default, Clone)]
+pub struct MonitorStatus {
+    pub heart_rate_bpm: u16,
+    pub latest_rr: std::time::Duration,
+    pub rr_intervals: Vec<std::time::Duration>,
+    pub battery_level: BatteryLevel,
+    /// `None` if the strap doesn't report sensor contact at all.
+    pub sensor_contact: Option<bool>,
+    pub energy_expended: Option<u16>,
+
+    pub twitch_up: bool,
+    pub twitch_down: bool,
+    use_real_rr: bool,
+
+    /// `None` if RR filtering is turned off in settings.
+    rr_filter: Option<RrFilter>,
+    pub rr_rejected: u32,
+    pub rr_interpolated: u32,
+}
+
+impl MonitorStatus {
+    pub fn new(filter_rr: bool) -> Self {
+        Self {
+            rr_filter: filter_rr.then(RrFilter::new),
+            ..Default::default()
+        }
+    }
+    pub fn update_from_slice(&mut self, data: &[u8]) -> std::result::Result<(), HrmParseError> {
+        let mut newest = try_parse_hrm(data)?;
+
+        self.heart_rate_bpm = newest.bpm;
+        self.sensor_contact = newest.is_sensor_contact_detected;
+        self.energy_expended = newest.energy_expended;
+
+        if self.contact_lost() {
+            // Anything else in here is garbage without skin contact
+            self.rr_intervals.clear();
+            self.twitch_up = false;
+            self.twitch_down = false;
+            return Ok(());
+        }
+
+        if !newest.rr_intervals.is_empty() {
+            self.use_real_rr = true;
+        }
+
+        if let Some(filter) = self.rr_filter.as_mut() {
+            newest.rr_intervals = filter.process(&newest.rr_intervals);
+            self.rr_rejected = filter.rejected;
+            self.rr_interpolated = filter.interpolated;
+        }
+        let mut twitch_up = false;
+        let mut twitch_down = false;
+        let rr_intervals = if self.use_real_rr {
+            newest.rr_intervals
+        } else {
+            vec![rr_from_bpm(newest.bpm)]
+        };
+
+        for new_rr in &rr_intervals {
+            const TWITCH_THRESHOLD: f32 = 0.02;
+            if self.latest_rr.abs_diff(*new_rr).as_secs_f32() > TWITCH_THRESHOLD {
+                twitch_up |= *new_rr > self.latest_rr;
+                twitch_down |= *new_rr < self.latest_rr;
+            }
+            self.latest_rr = *new_rr;
+        }
+        self.rr_intervals = rr_intervals;
+        self.twitch_up = twitch_up;
+        self.twitch_down = twitch_down;
+        Ok(())
+    }
+    /// `true` only if the strap supports contact detection and says it has none.
+    pub fn contact_lost(&self) -> bool {
+        self.sensor_contact == Some(false)
+    }
+    /// `false` if `rr_intervals` was synthesized from the BPM.
+    pub fn has_real_rr(&self) -> bool {
+        self.use_real_rr
+    }
+    /// Back to a measurement, leaving out any synthesized RR intervals.
+    pub fn to_measurement(&self) -> HeartRateMeasurement {
+        HeartRateMeasurement {
+            bpm: self.heart_rate_bpm,
+            is_sensor_contact_detected: self.sensor_contact,
+            energy_expended: self.energy_expended,
+            rr_intervals: if self.use_real_rr {
+                self.rr_intervals.clone()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+pub fn rr_from_bpm(bpm: u16) -> std::time::Duration {
+    // Make sure it's at least 1 to prevent a potential divide by zero
+    let bpm = bpm.max(1);
+    std::time::Duration::from_secs_f32(60.0 / bpm as f32)
+}
+
+#[derive(Debug)]
+pub enum BleHrCommand {
+    /// Drop the link and stay idle until told otherwise.
+    Disconnect,
+    /// Zero the strap's Energy Expended, for the start of a session.
+    ResetEnergy,
+    /// Disconnect and go look for a different monitor instead.
+    SwitchTo(BleIdents),
+}
+
+/// Where the monitor supervisor is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorState {
+    /// Told to disconnect, waiting for a command.
+    Idle,
+    Scanning,
+    Connecting,
+    Subscribed,
+    /// Waiting this long before trying again.
+    Backoff(std::time::Duration),
+}
+
+impl std::fmt::Display for MonitorState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MonitorState::Idle => write!(f, "Disconnected"),
+            MonitorState::Scanning => write!(f, "Scanning"),
+            MonitorState::Connecting => write!(f, "Connecting"),
+            MonitorState::Subscribed => write!(f, "Live"),
+            MonitorState::Backoff(wait) => write!(f, "Retry in {}s", wait.as_secs()),
+        }
+    }
+}
+
+/// Strings from the Device Information Service and where it's worn, if the monitor has them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MonitorInfo {
+    pub location: Option<BodySensorLocation>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    pub serial: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum MonitorReply {
+    State(MonitorState),
+    /// Only ever logged, so just the message.
+    Error(String),
+    // ScannedDevice(BleIdents),
+    MonitorStatus(MonitorStatus),
+    Battery(BatteryLevel),
+    Info(MonitorInfo),
+    /// Connected, but couldn't pair/encrypt. A stale bond on one end, usually.
+    PairingFailed(String),
+}
+
+/// How a supervisor treats its monitor, from `HrSettings`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MonitorOptions {
+    pub filter_rr: bool,
+    /// Connect to anything with the saved name if the saved address can't be found.
+    pub name_fallback: bool,
+    /// Pair (Just Works) and encrypt the link before subscribing.
+    pub bond: bool,
+}
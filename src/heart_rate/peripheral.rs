@@ -0,0 +1,134 @@
+// Re-publishes the connected strap's data as a standard Heart Rate Service,
+// so phones/OBS/other badges can subscribe to the badge instead.
+//
+// Also owns what goes into our advertisements, since the rebroadcast and
+// badge presence have to share them.
+
+use std::sync::Arc;
+
+use esp32_nimble::{
+    utilities::{mutex::Mutex, BleUuid},
+    BLEAdvertisementData, BLECharacteristic, BLEDevice, NimbleProperties,
+};
+use log::{info, warn};
+
+use crate::errors::Result;
+
+use super::{ble::MonitorStatus, measurement::encode_hrm};
+
+// These need to be the 16-bit forms, otherwise centrals see a custom 128-bit service.
+const HR_SERVICE_UUID: BleUuid = BleUuid::from_uuid16(0x180D);
+const HR_CHAR_UUID: BleUuid = BleUuid::from_uuid16(0x2A37);
+const BODY_SENSOR_LOCATION_UUID: BleUuid = BleUuid::from_uuid16(0x2A38);
+
+/// Body Sensor Location: Chest. Close enough for a badge.
+const BODY_SENSOR_LOCATION_CHEST: u8 = 1;
+
+/// Advertising packets only have 31 bytes, most of which the name gets.
+const MAX_NAME_LEN: usize = 20;
+
+/// Cuts `name` down to `MAX_NAME_LEN` bytes without splitting a character.
+fn truncate_name(name: &str) -> &str {
+    let mut end = name.len().min(MAX_NAME_LEN);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+/// Everything we'd like to be advertising right now.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Advertised {
+    /// Name to show up under, if anything should be connecting to us.
+    pub name: Option<String>,
+    /// Let centrals know we've got a Heart Rate Service.
+    pub hr_service: bool,
+    /// Encoded presence manufacturer data, if visible to other badges.
+    pub presence: Option<Vec<u8>>,
+}
+
+/// Replaces our advertisement with `content`, or stops advertising if there's nothing to say.
+pub fn advertise(content: &Advertised) -> Result<()> {
+    let mut advertising = BLEDevice::take().get_advertising().lock();
+    if content.name.is_none() && content.presence.is_none() {
+        advertising.stop()?;
+        info!("Stopped advertising");
+        return Ok(());
+    }
+    let name = content.name.as_deref().map(truncate_name);
+
+    let mut data = BLEAdvertisementData::new();
+    let mut scan_response = BLEAdvertisementData::new();
+    if content.hr_service {
+        data.add_service_uuid(HR_SERVICE_UUID);
+    }
+    if let Some(name) = name {
+        match content.presence {
+            // No room for both in 31 bytes, centrals looking for HR scan actively anyway
+            Some(_) => scan_response.name(name),
+            None => data.name(name),
+        };
+    }
+    if let Some(presence) = &content.presence {
+        data.manufacturer_data(presence);
+    }
+    advertising.set_data(&mut data)?;
+    advertising.set_scan_response_data(&mut scan_response)?;
+    advertising.start()?;
+    info!("Advertising {content:?}");
+    Ok(())
+}
+
+pub struct HrPeripheral {
+    hr_characteristic: Arc<Mutex<BLECharacteristic>>,
+    /// Put back whenever a central disconnects.
+    advertised: Arc<Mutex<Advertised>>,
+}
+
+impl HrPeripheral {
+    /// Registers the GATT service, `advertise` handles letting centrals know it's there.
+    ///
+    /// NimBLE can't unregister services, so only build this once. Services also need
+    /// registering before advertising first starts, so it's built whether or not we're
+    /// rebroadcasting.
+    pub fn build() -> Result<Self> {
+        let device = BLEDevice::take();
+        let server = device.get_server();
+        // NimBLE would restart whatever it was advertising when the central connected,
+        // which might be out of date by now
+        server.advertise_on_disconnect(false);
+        let advertised = Arc::new(Mutex::new(Advertised::default()));
+        let current = advertised.clone();
+        server.on_disconnect(move |_desc, _reason| {
+            let content = current.lock().clone();
+            if let Err(e) = advertise(&content) {
+                warn!("Couldn't advertise again after disconnect: {e}");
+            }
+        });
+
+        let service = server.create_service(HR_SERVICE_UUID);
+        let hr_characteristic = service
+            .lock()
+            .create_characteristic(HR_CHAR_UUID, NimbleProperties::NOTIFY);
+        let location = service
+            .lock()
+            .create_characteristic(BODY_SENSOR_LOCATION_UUID, NimbleProperties::READ);
+        location.lock().set_value(&[BODY_SENSOR_LOCATION_CHEST]);
+
+        Ok(Self {
+            hr_characteristic,
+            advertised,
+        })
+    }
+    /// `advertise`, remembering it for after the next disconnect.
+    pub fn advertise(&self, content: &Advertised) -> Result<()> {
+        advertise(content)?;
+        *self.advertised.lock() = content.clone();
+        Ok(())
+    }
+    /// Notifies any subscribers with the latest status.
+    pub fn publish(&self, status: &MonitorStatus) {
+        let data = encode_hrm(&status.to_measurement());
+        self.hr_characteristic.lock().set_value(&data).notify();
+    }
+}
@@ -0,0 +1,317 @@
+// Badge-to-badge presence, squeezed into the manufacturer data of our advertisements.
+//
+// Layout (version 1):
+//   0..2    Company ID 0xFFFF (LE), the "no company/testing" ID
+//   2..4    b"MF"
+//   4       Version
+//   5       BPM, 0 if unknown, clamped to 255
+//   6       Zone index, 0xFF if unknown
+//   7       Username length N
+//   8..8+N  Username, UTF-8
+//
+// Anything after the username is ignored, so fields can be tacked on later
+// without breaking older badges.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use strum::VariantArray;
+
+use super::discovery::{signal_bars, smooth_rssi, BleMacLe};
+use super::zones::HrZone;
+
+const COMPANY_ID: [u8; 2] = [0xFF, 0xFF];
+const MAGIC: [u8; 2] = *b"MF";
+pub const PRESENCE_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 8;
+const NO_ZONE: u8 = 0xFF;
+
+/// Advertisements only have 31 bytes, and the HR service UUID and flags need some too.
+pub const MAX_USERNAME_LEN: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresencePayload {
+    pub username: String,
+    pub bpm: Option<u16>,
+    pub zone: Option<HrZone>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PresenceError {
+    #[error("Not a badge presence payload")]
+    NotOurs,
+    #[error("Unsupported presence version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Presence payload too short ({0} bytes)")]
+    Truncated(usize),
+    #[error("Unknown zone index {0}")]
+    BadZone(u8),
+    #[error("Username isn't valid UTF-8")]
+    BadUsername,
+}
+
+impl PresencePayload {
+    /// Encodes as manufacturer data, company ID included.
+    ///
+    /// Usernames get cut to `MAX_USERNAME_LEN` bytes, on a char boundary.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut end = self.username.len().min(MAX_USERNAME_LEN);
+        while !self.username.is_char_boundary(end) {
+            end -= 1;
+        }
+        let username = &self.username.as_bytes()[..end];
+
+        let zone = self
+            .zone
+            .and_then(|zone| HrZone::VARIANTS.iter().position(|v| *v == zone))
+            .map_or(NO_ZONE, |index| index as u8);
+
+        let mut data = Vec::with_capacity(HEADER_LEN + username.len());
+        data.extend_from_slice(&COMPANY_ID);
+        data.extend_from_slice(&MAGIC);
+        data.push(PRESENCE_VERSION);
+        data.push(self.bpm.map_or(0, |bpm| bpm.min(u8::MAX as u16) as u8));
+        data.push(zone);
+        data.push(username.len() as u8);
+        data.extend_from_slice(username);
+        data
+    }
+    pub fn decode(data: &[u8]) -> Result<Self, PresenceError> {
+        if data.len() < 5 || data[0..2] != COMPANY_ID || data[2..4] != MAGIC {
+            return Err(PresenceError::NotOurs);
+        }
+        if data[4] != PRESENCE_VERSION {
+            return Err(PresenceError::UnsupportedVersion(data[4]));
+        }
+        if data.len() < HEADER_LEN {
+            return Err(PresenceError::Truncated(data.len()));
+        }
+        let name_end = HEADER_LEN + data[7] as usize;
+        let Some(username) = data.get(HEADER_LEN..name_end) else {
+            return Err(PresenceError::Truncated(data.len()));
+        };
+        let zone = match data[6] {
+            NO_ZONE => None,
+            index => match HrZone::VARIANTS.get(index as usize) {
+                Some(zone) => Some(*zone),
+                None => return Err(PresenceError::BadZone(index)),
+            },
+        };
+        Ok(Self {
+            username: std::str::from_utf8(username)
+                .map_err(|_| PresenceError::BadUsername)?
+                .to_string(),
+            bpm: match data[5] {
+                0 => None,
+                bpm => Some(bpm as u16),
+            },
+            zone,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearbyBadge {
+    pub presence: PresencePayload,
+    rssi: f32,
+    pub last_seen: Instant,
+}
+
+impl NearbyBadge {
+    /// Smoothed RSSI in dBm.
+    pub fn rssi(&self) -> i32 {
+        self.rssi.round() as i32
+    }
+    pub fn bars(&self) -> u8 {
+        signal_bars(self.rssi())
+    }
+}
+
+/// Other badges we've heard from recently.
+#[derive(Debug, Clone, Default)]
+pub struct NearbyBadges {
+    seen: BTreeMap<BleMacLe, NearbyBadge>,
+}
+
+impl NearbyBadges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn clear(&mut self) {
+        self.seen.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+    pub fn observe(&mut self, mac: BleMacLe, presence: PresencePayload, rssi: i32, now: Instant) {
+        match self.seen.get_mut(&mac) {
+            Some(badge) => {
+                badge.presence = presence;
+                badge.rssi = smooth_rssi(badge.rssi, rssi);
+                badge.last_seen = now;
+            }
+            None => {
+                self.seen.insert(
+                    mac,
+                    NearbyBadge {
+                        presence,
+                        rssi: rssi as f32,
+                        last_seen: now,
+                    },
+                );
+            }
+        }
+    }
+    /// Drops anyone not heard from in `max_age`, returns how many went.
+    pub fn prune(&mut self, now: Instant, max_age: Duration) -> usize {
+        let before = self.seen.len();
+        self.seen
+            .retain(|_, badge| now.saturating_duration_since(badge.last_seen) <= max_age);
+        before - self.seen.len()
+    }
+    /// Everything that'd be visible on the friends screen, to tell if it needs a repaint.
+    pub fn layout(&self) -> Vec<(BleMacLe, PresencePayload, u8)> {
+        self.sorted()
+            .into_iter()
+            .map(|(mac, badge)| (mac, badge.presence.clone(), badge.bars()))
+            .collect()
+    }
+    /// Closest first.
+    pub fn sorted(&self) -> Vec<(BleMacLe, &NearbyBadge)> {
+        let mut badges: Vec<(BleMacLe, &NearbyBadge)> =
+            self.seen.iter().map(|(mac, badge)| (*mac, badge)).collect();
+        badges.sort_by(|(_, a), (_, b)| b.rssi.total_cmp(&a.rssi));
+        badges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NearbyBadges, PresenceError, PresencePayload, MAX_USERNAME_LEN};
+    use crate::heart_rate::zones::HrZone;
+    use std::time::{Duration, Instant};
+
+    fn goobinski() -> PresencePayload {
+        PresencePayload {
+            username: String::from("Goobinski"),
+            bpm: Some(72),
+            zone: Some(HrZone::Zone2),
+        }
+    }
+
+    #[test]
+    fn presence_encode_fixture() {
+        #[rustfmt::skip]
+        let expected = [
+            0xFF, 0xFF, b'M', b'F', 1,
+            72, 2, 9,
+            b'G', b'o', b'o', b'b', b'i', b'n', b's', b'k', b'i',
+        ];
+        assert_eq!(expected.to_vec(), goobinski().encode());
+    }
+
+    #[test]
+    fn presence_round_trip() {
+        let unknown = PresencePayload {
+            username: String::from("Bingus"),
+            bpm: None,
+            zone: None,
+        };
+        for payload in [goobinski(), unknown] {
+            assert_eq!(
+                Ok(payload.clone()),
+                PresencePayload::decode(&payload.encode())
+            );
+        }
+    }
+
+    #[test]
+    fn presence_clamps_and_truncates() {
+        let payload = PresencePayload {
+            username: String::from("Sixteen Letters!"),
+            bpm: Some(300),
+            zone: Some(HrZone::Zone5),
+        };
+        let decoded = PresencePayload::decode(&payload.encode()).unwrap();
+        assert_eq!(Some(255), decoded.bpm);
+        assert_eq!("Sixteen Lett", decoded.username);
+
+        // Multi-byte char straddling the limit gets dropped whole
+        let payload = PresencePayload {
+            username: String::from("Goobinski Jé"),
+            ..payload
+        };
+        let encoded = payload.encode();
+        assert_eq!(MAX_USERNAME_LEN - 1, encoded.len() - 8);
+        let decoded = PresencePayload::decode(&encoded).unwrap();
+        assert_eq!("Goobinski J", decoded.username);
+    }
+
+    #[test]
+    fn presence_ignores_trailing_fields() {
+        let mut encoded = goobinski().encode();
+        encoded.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(Ok(goobinski()), PresencePayload::decode(&encoded));
+    }
+
+    #[test]
+    fn presence_rejects() {
+        let encoded = goobinski().encode();
+        assert_eq!(Err(PresenceError::NotOurs), PresencePayload::decode(&[]));
+        assert_eq!(
+            Err(PresenceError::NotOurs),
+            PresencePayload::decode(&[0x4C, 0x00, b'M', b'F', 1, 72, 2, 0])
+        );
+
+        let mut future = encoded.clone();
+        future[4] = 2;
+        assert_eq!(
+            Err(PresenceError::UnsupportedVersion(2)),
+            PresencePayload::decode(&future)
+        );
+
+        assert_eq!(
+            Err(PresenceError::Truncated(6)),
+            PresencePayload::decode(&encoded[..6])
+        );
+        assert_eq!(
+            Err(PresenceError::Truncated(12)),
+            PresencePayload::decode(&encoded[..12])
+        );
+
+        let mut bad_zone = encoded.clone();
+        bad_zone[6] = 9;
+        assert_eq!(
+            Err(PresenceError::BadZone(9)),
+            PresencePayload::decode(&bad_zone)
+        );
+
+        let mut bad_name = encoded;
+        bad_name[8] = 0xFF;
+        assert_eq!(
+            Err(PresenceError::BadUsername),
+            PresencePayload::decode(&bad_name)
+        );
+    }
+
+    #[test]
+    fn presence_nearby_sorted_and_pruned() {
+        let start = Instant::now();
+        let mut nearby = NearbyBadges::new();
+        nearby.observe([1; 6], goobinski(), -80, start);
+        nearby.observe([2; 6], goobinski(), -50, start);
+        let later = start + Duration::from_secs(20);
+        nearby.observe([1; 6], goobinski(), -80, later);
+
+        let order: Vec<[u8; 6]> = nearby.sorted().iter().map(|(mac, _)| *mac).collect();
+        assert_eq!(vec![[2; 6], [1; 6]], order);
+
+        assert_eq!(1, nearby.prune(later, Duration::from_secs(10)));
+        assert_eq!(1, nearby.len());
+        assert_eq!(2, nearby.sorted()[0].1.bars());
+    }
+}
@@ -0,0 +1,293 @@
+// Recorded sessions, one CSV row per notification from the monitor:
+//
+//   elapsed_ms,unix_ms,local_time,bpm,contact,energy_kj,battery,rr_ms
+//   1000,1734876189500,2024-12-22T16:03:09+02:00,72,1,,85,812 830
+//
+// `elapsed_ms` is from when recording started. `unix_ms` and `local_time` are
+// empty until the clock's been set. `contact` is 1/0, or empty if the strap doesn't report it,
+// same for `energy_kj` and `battery`.
+// RR intervals are space separated, so a row's always the same number of columns.
+//
+// Files are numbered in order, `0001.csv`, `0002.csv`...
+
+use std::time::Duration;
+
+use super::measurement::HeartRateMeasurement;
+use crate::clock::{parse_utc_offset, WallClock};
+
+/// Where sessions get recorded to, and replayed from.
+pub const SESSION_DIR: &str = "/sdcard/HR";
+pub const SESSION_HEADER: &str =
+    "elapsed_ms,unix_ms,local_time,bpm,contact,energy_kj,battery,rr_ms";
+const COLUMNS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SessionParseError {
+    #[error("Expected the {SESSION_HEADER:?} header")]
+    MissingHeader,
+    #[error("No rows in session")]
+    Empty,
+    #[error("Expected {COLUMNS} columns, got {0}")]
+    Columns(usize),
+    #[error("Bad {column} value {value:?}")]
+    BadValue { column: &'static str, value: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionRow {
+    /// Since the recording started.
+    pub elapsed: Duration,
+    /// If the clock was set.
+    pub wall_clock: Option<WallClock>,
+    pub measurement: HeartRateMeasurement,
+    /// Percent.
+    pub battery: Option<u8>,
+}
+
+impl SessionRow {
+    /// Without the newline.
+    pub fn to_csv(&self) -> String {
+        let contact = match self.measurement.is_sensor_contact_detected {
+            Some(true) => "1",
+            Some(false) => "0",
+            None => "",
+        };
+        let unix = optional(self.wall_clock.map(|clock| clock.unix.as_millis()));
+        let local = optional(self.wall_clock.map(|clock| clock.iso8601()));
+        let energy = optional(self.measurement.energy_expended);
+        let battery = optional(self.battery);
+        let rr: Vec<String> = self
+            .measurement
+            .rr_intervals
+            .iter()
+            .map(|rr| rr.as_millis().to_string())
+            .collect();
+        format!(
+            "{},{unix},{local},{},{contact},{energy},{battery},{}",
+            self.elapsed.as_millis(),
+            self.measurement.bpm,
+            rr.join(" ")
+        )
+    }
+    pub fn from_csv(line: &str) -> Result<Self, SessionParseError> {
+        let columns: Vec<&str> = line.trim().split(',').collect();
+        let [elapsed, unix, local, bpm, contact, energy, battery, rr] = columns[..] else {
+            return Err(SessionParseError::Columns(columns.len()));
+        };
+        let contact = match contact {
+            "1" => Some(true),
+            "0" => Some(false),
+            "" => None,
+            _ => return Err(bad_value("contact", contact)),
+        };
+        let wall_clock = match parse_optional("unix_ms", unix)? {
+            Some(unix) => Some(WallClock {
+                unix: Duration::from_millis(unix),
+                utc_offset_min: parse_utc_offset(local)
+                    .ok_or_else(|| bad_value("local_time", local))?,
+            }),
+            None => None,
+        };
+        let energy_expended = parse_optional("energy_kj", energy)?;
+        let battery = parse_optional("battery", battery)?;
+        let rr_intervals = rr
+            .split_whitespace()
+            .map(|rr| parse_column("rr_ms", rr).map(Duration::from_millis))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            elapsed: Duration::from_millis(parse_column("elapsed_ms", elapsed)?),
+            wall_clock,
+            measurement: HeartRateMeasurement {
+                bpm: parse_column("bpm", bpm)?,
+                is_sensor_contact_detected: contact,
+                energy_expended,
+                rr_intervals,
+            },
+            battery,
+        })
+    }
+}
+
+fn bad_value(column: &'static str, value: &str) -> SessionParseError {
+    SessionParseError::BadValue {
+        column,
+        value: value.to_string(),
+    }
+}
+
+fn parse_column<T: std::str::FromStr>(
+    column: &'static str,
+    value: &str,
+) -> Result<T, SessionParseError> {
+    value.parse().map_err(|_| bad_value(column, value))
+}
+
+/// Empty column for `None`.
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn parse_optional<T: std::str::FromStr>(
+    column: &'static str,
+    value: &str,
+) -> Result<Option<T>, SessionParseError> {
+    match value {
+        "" => Ok(None),
+        value => parse_column(column, value).map(Some),
+    }
+}
+
+/// `0012.csv` is 12, anything else isn't a session.
+pub fn session_number(file_name: &str) -> Option<u32> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if !extension.eq_ignore_ascii_case("csv") || !stem.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    stem.parse().ok()
+}
+
+/// The latest session out of some file names.
+pub fn newest_session<'a>(file_names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    file_names
+        .into_iter()
+        .filter_map(|name| session_number(name).map(|number| (number, name)))
+        .max_by_key(|(number, _)| *number)
+        .map(|(_, name)| name)
+}
+
+/// What to call a new session, one after the latest.
+pub fn next_session_name<'a>(file_names: impl IntoIterator<Item = &'a str>) -> String {
+    let next = newest_session(file_names)
+        .and_then(session_number)
+        .map_or(1, |number| number.saturating_add(1));
+    format!("{next:04}.csv")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub rows: Vec<SessionRow>,
+    /// Rows that didn't parse, usually a half-written last one from a power cut.
+    pub skipped: usize,
+}
+
+/// Reads a whole recording, skipping blank lines and rows that don't parse.
+pub fn parse_session(text: &str) -> Result<Session, SessionParseError> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    if lines.next().map(str::trim) != Some(SESSION_HEADER) {
+        return Err(SessionParseError::MissingHeader);
+    }
+    let mut rows = Vec::new();
+    let mut skipped = 0;
+    for line in lines {
+        match SessionRow::from_csv(line) {
+            Ok(row) => rows.push(row),
+            Err(_) => skipped += 1,
+        }
+    }
+    if rows.is_empty() {
+        return Err(SessionParseError::Empty);
+    }
+    Ok(Session { rows, skipped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        newest_session, next_session_name, parse_session, session_number, SessionParseError,
+        SessionRow, SESSION_HEADER,
+    };
+    use crate::{clock::WallClock, heart_rate::measurement::HeartRateMeasurement};
+    use std::time::Duration;
+
+    fn row(elapsed_ms: u64, bpm: u16, rr_ms: &[u64]) -> SessionRow {
+        SessionRow {
+            elapsed: Duration::from_millis(elapsed_ms),
+            wall_clock: None,
+            measurement: HeartRateMeasurement {
+                bpm,
+                is_sensor_contact_detected: Some(true),
+                energy_expended: None,
+                rr_intervals: rr_ms.iter().copied().map(Duration::from_millis).collect(),
+            },
+            battery: None,
+        }
+    }
+
+    #[test]
+    fn session_row_round_trip() {
+        let mut full = row(1000, 72, &[812, 830]);
+        assert_eq!("1000,,,72,1,,,812 830", full.to_csv());
+        full.wall_clock = Some(WallClock {
+            unix: Duration::from_millis(1_734_876_189_500),
+            utc_offset_min: 120,
+        });
+        full.measurement.energy_expended = Some(12);
+        full.measurement.is_sensor_contact_detected = None;
+        full.battery = Some(85);
+        assert_eq!(
+            "1000,1734876189500,2024-12-22T16:03:09+02:00,72,,12,85,812 830",
+            full.to_csv()
+        );
+        assert_eq!(Ok(full.clone()), SessionRow::from_csv(&full.to_csv()));
+
+        let bare = row(0, 60, &[]);
+        assert_eq!("0,,,60,1,,,", bare.to_csv());
+        assert_eq!(Ok(bare.clone()), SessionRow::from_csv(&bare.to_csv()));
+    }
+
+    #[test]
+    fn session_row_rejects_garbage() {
+        assert_eq!(
+            Err(SessionParseError::Columns(3)),
+            SessionRow::from_csv("1000,72,1")
+        );
+        assert_eq!(
+            Err(SessionParseError::BadValue {
+                column: "contact",
+                value: String::from("yes"),
+            }),
+            SessionRow::from_csv("1000,,,72,yes,,,")
+        );
+        assert!(SessionRow::from_csv("1000,,,72,1,,,812 eight").is_err());
+        assert!(SessionRow::from_csv("1000,,,-5,1,,,").is_err());
+        assert!(SessionRow::from_csv("1000,,,72,1,,101%,").is_err());
+        // Time without a zone
+        assert!(SessionRow::from_csv("1000,1734876189500,,72,1,,,").is_err());
+    }
+
+    #[test]
+    fn session_parse_skips_bad_rows() {
+        let text = format!("{SESSION_HEADER}\r\n0,,,60,1,,,1000\n\n1000,,,61,1,,,983\n2000,,6");
+        let session = parse_session(&text).unwrap();
+        assert_eq!(
+            vec![row(0, 60, &[1000]), row(1000, 61, &[983])],
+            session.rows
+        );
+        assert_eq!(1, session.skipped);
+
+        assert_eq!(
+            Err(SessionParseError::MissingHeader),
+            parse_session("0,,,60,1,,,1000\n")
+        );
+        assert_eq!(
+            Err(SessionParseError::Empty),
+            parse_session(&format!("{SESSION_HEADER}\n"))
+        );
+    }
+
+    #[test]
+    fn session_file_names() {
+        assert_eq!(Some(12), session_number("0012.csv"));
+        assert_eq!(Some(3), session_number("3.CSV"));
+        assert_eq!(None, session_number("notes.csv"));
+        assert_eq!(None, session_number("0012.txt"));
+        assert_eq!(None, session_number("-12.csv"));
+
+        assert_eq!("0001.csv", next_session_name([]));
+        let names = ["0002.csv", "0010.csv", "notes.csv", "0009.csv", "9999.txt"];
+        assert_eq!(Some("0010.csv"), newest_session(names));
+        assert_eq!("0011.csv", next_session_name(names));
+        // Padding's only cosmetic, goes by number not name
+        assert_eq!(Some("10000.csv"), newest_session(["9999.csv", "10000.csv"]));
+    }
+}
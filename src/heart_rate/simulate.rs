@@ -0,0 +1,270 @@
+// Fake heart rate, for demos and testing without a strap on.
+//
+// Follows a looping BPM curve, with seeded jitter on every beat so the HRV
+// screen has something to chew on. Same config, same beats, every time.
+
+use std::time::Duration;
+
+use super::measurement::HeartRateMeasurement;
+
+/// How often a real strap notifies.
+pub const NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Jitter can't push a beat shorter than this (240 BPM).
+const MIN_RR: Duration = Duration::from_millis(250);
+
+/// BPM over time, straight lines between points, starting over after the last one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BpmCurve {
+    /// Time since the start and the BPM there, in time order.
+    points: Vec<(Duration, f32)>,
+}
+
+impl BpmCurve {
+    /// Points should be in time order, starting at zero. No points means a flat 60.
+    pub fn new(points: Vec<(Duration, f32)>) -> Self {
+        if points.is_empty() {
+            return Self::flat(60.0);
+        }
+        Self { points }
+    }
+    pub fn flat(bpm: f32) -> Self {
+        Self {
+            points: vec![(Duration::ZERO, bpm)],
+        }
+    }
+    /// How long before it loops.
+    pub fn period(&self) -> Duration {
+        self.points.last().map_or(Duration::ZERO, |(time, _)| *time)
+    }
+    pub fn bpm_at(&self, time: Duration) -> f32 {
+        let period = self.period();
+        if period.is_zero() {
+            return self.points[0].1;
+        }
+        let time = Duration::from_nanos((time.as_nanos() % period.as_nanos()) as u64);
+        for pair in self.points.windows(2) {
+            let (start, start_bpm) = pair[0];
+            let (end, end_bpm) = pair[1];
+            if time >= start && time < end {
+                let progress = (time - start).as_secs_f32() / (end - start).as_secs_f32();
+                return start_bpm + (end_bpm - start_bpm) * progress;
+            }
+        }
+        self.points[0].1
+    }
+}
+
+impl Default for BpmCurve {
+    /// A short workout: resting, ramp up, hold, cool back down.
+    fn default() -> Self {
+        let secs = Duration::from_secs;
+        Self::new(vec![
+            (secs(0), 65.0),
+            (secs(20), 65.0),
+            (secs(60), 150.0),
+            (secs(90), 155.0),
+            (secs(150), 65.0),
+        ])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    pub curve: BpmCurve,
+    /// Each beat lands up to this much early or late.
+    pub rr_jitter: Duration,
+    pub seed: u32,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            curve: BpmCurve::default(),
+            rr_jitter: Duration::from_millis(25),
+            seed: 0x1BAD_B002,
+        }
+    }
+}
+
+/// xorshift32, plenty for wobbling some beats around.
+#[derive(Debug, Clone)]
+struct XorShift(u32);
+
+impl XorShift {
+    fn new(seed: u32) -> Self {
+        // Zero would get stuck at zero
+        Self(seed.max(1))
+    }
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+    /// Somewhere in -1.0..=1.0.
+    fn next_signed(&mut self) -> f32 {
+        (self.next() as f64 / u32::MAX as f64 * 2.0 - 1.0) as f32
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    config: SimConfig,
+    rng: XorShift,
+    last_beat: Duration,
+    next_rr: Duration,
+    next_notify: Duration,
+    /// Beats since the last notification.
+    pending_rr: Vec<Duration>,
+}
+
+impl Simulator {
+    pub fn new(config: SimConfig) -> Self {
+        let mut simulator = Self {
+            rng: XorShift::new(config.seed),
+            config,
+            last_beat: Duration::ZERO,
+            next_rr: Duration::ZERO,
+            next_notify: NOTIFY_INTERVAL,
+            pending_rr: Vec::new(),
+        };
+        simulator.next_rr = simulator.roll_rr();
+        simulator
+    }
+    /// Length of the beat starting at `last_beat`.
+    fn roll_rr(&mut self) -> Duration {
+        let bpm = self.config.curve.bpm_at(self.last_beat).max(1.0);
+        let jitter = self.config.rr_jitter.as_secs_f32() * self.rng.next_signed();
+        Duration::from_secs_f32((60.0 / bpm + jitter).max(MIN_RR.as_secs_f32()))
+    }
+    /// Every notification the fake strap would've sent by `now`, since it started.
+    pub fn advance(&mut self, now: Duration) -> Vec<HeartRateMeasurement> {
+        let mut sent = Vec::new();
+        while self.next_notify <= now {
+            while self.last_beat + self.next_rr <= self.next_notify {
+                self.last_beat += self.next_rr;
+                self.pending_rr.push(self.next_rr);
+                self.next_rr = self.roll_rr();
+            }
+            let bpm = self.config.curve.bpm_at(self.next_notify).round();
+            sent.push(HeartRateMeasurement {
+                bpm: bpm.clamp(0.0, u16::MAX as f32) as u16,
+                is_sensor_contact_detected: Some(true),
+                energy_expended: None,
+                rr_intervals: std::mem::take(&mut self.pending_rr),
+            });
+            self.next_notify += NOTIFY_INTERVAL;
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BpmCurve, SimConfig, Simulator, MIN_RR, NOTIFY_INTERVAL};
+    use std::time::Duration;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn sim_curve_interpolates_and_loops() {
+        let curve = BpmCurve::new(vec![(secs(0), 60.0), (secs(10), 120.0), (secs(20), 60.0)]);
+        assert_eq!(60.0, curve.bpm_at(secs(0)));
+        assert_eq!(90.0, curve.bpm_at(secs(5)));
+        assert_eq!(120.0, curve.bpm_at(secs(10)));
+        assert_eq!(90.0, curve.bpm_at(secs(15)));
+        // Back around to the start
+        assert_eq!(60.0, curve.bpm_at(secs(20)));
+        assert_eq!(90.0, curve.bpm_at(secs(25)));
+
+        assert_eq!(72.0, BpmCurve::flat(72.0).bpm_at(secs(1000)));
+        assert_eq!(60.0, BpmCurve::new(Vec::new()).bpm_at(secs(3)));
+    }
+
+    #[test]
+    fn sim_steady_without_jitter() {
+        let mut sim = Simulator::new(SimConfig {
+            curve: BpmCurve::flat(60.0),
+            rr_jitter: Duration::ZERO,
+            seed: 1,
+        });
+        assert!(sim.advance(Duration::from_millis(999)).is_empty());
+        let sent = sim.advance(secs(5));
+        assert_eq!(5, sent.len());
+        for measurement in sent {
+            assert_eq!(60, measurement.bpm);
+            assert_eq!(Some(true), measurement.is_sensor_contact_detected);
+            assert_eq!(vec![secs(1)], measurement.rr_intervals);
+        }
+        // Nothing new until the next interval
+        assert!(sim.advance(secs(5)).is_empty());
+    }
+
+    #[test]
+    fn sim_follows_curve() {
+        let curve = BpmCurve::new(vec![(secs(0), 60.0), (secs(60), 180.0)]);
+        let mut sim = Simulator::new(SimConfig {
+            curve: curve.clone(),
+            ..Default::default()
+        });
+        let sent = sim.advance(secs(59));
+        assert_eq!(59, sent.len());
+        for (index, measurement) in sent.iter().enumerate() {
+            let at = NOTIFY_INTERVAL * (index as u32 + 1);
+            assert_eq!(curve.bpm_at(at).round() as u16, measurement.bpm);
+        }
+        // More beats per notification as it speeds up
+        let beats = |measurements: &[_]| -> usize {
+            measurements
+                .iter()
+                .map(|m: &super::HeartRateMeasurement| m.rr_intervals.len())
+                .sum()
+        };
+        assert!(beats(&sent[..10]) < beats(&sent[49..]));
+    }
+
+    #[test]
+    fn sim_jitter_is_seeded_and_bounded() {
+        let config = SimConfig {
+            curve: BpmCurve::flat(75.0),
+            rr_jitter: Duration::from_millis(40),
+            seed: 1234,
+        };
+        let run = |config: SimConfig| -> Vec<Duration> {
+            Simulator::new(config)
+                .advance(secs(120))
+                .into_iter()
+                .flat_map(|m| m.rr_intervals)
+                .collect()
+        };
+        let first = run(config.clone());
+        assert_eq!(first, run(config.clone()));
+        assert_ne!(
+            first,
+            run(SimConfig {
+                seed: 4321,
+                ..config.clone()
+            })
+        );
+
+        let expected = Duration::from_millis(800);
+        assert!(first
+            .iter()
+            .all(|rr| rr.abs_diff(expected) <= Duration::from_millis(41)));
+        // Actually wobbles, not stuck at one value
+        assert!(first.iter().any(|rr| *rr != first[0]));
+
+        // Can't jitter into nonsense
+        let wild = run(SimConfig {
+            curve: BpmCurve::flat(220.0),
+            rr_jitter: Duration::from_millis(500),
+            seed: 7,
+        });
+        assert!(wild.iter().all(|rr| *rr >= MIN_RR));
+    }
+}
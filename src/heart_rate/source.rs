@@ -0,0 +1,290 @@
+// Where the badge's heart rate comes from. Usually a strap over BLE, but the
+// simulator and recorded sessions speak the same replies for demos and testing.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::TryRecvError,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+    discovery::BleIdents,
+    measurement::{encode_hrm, HeartRateMeasurement},
+    monitor::{BleHrCommand, MonitorReply, MonitorState, MonitorStatus},
+    session::{newest_session, parse_session, SessionParseError, SessionRow, SESSION_DIR},
+    simulate::{SimConfig, Simulator},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Parse(#[from] SessionParseError),
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    strum_macros::Display,
+    strum_macros::VariantArray,
+)]
+pub enum HrSource {
+    #[default]
+    #[strum(to_string = "BLE")]
+    Ble,
+    Simulator,
+    Replay,
+}
+
+pub trait HeartRateSource {
+    fn kind(&self) -> HrSource;
+    /// What to call it on screen.
+    fn ident(&self) -> &BleIdents;
+    /// `Disconnected` means it died and needs rebuilding.
+    fn try_recv(&mut self) -> std::result::Result<MonitorReply, TryRecvError>;
+    fn command(&mut self, command: BleHrCommand);
+}
+
+/// The bits shared by sources that make up their own notifications.
+struct FakeLink {
+    ident: BleIdents,
+    status: MonitorStatus,
+    replies: VecDeque<MonitorReply>,
+    /// When it "connected", `None` while told to disconnect.
+    started: Option<Instant>,
+}
+
+impl FakeLink {
+    fn new(name: &str, filter_rr: bool) -> Self {
+        Self {
+            ident: BleIdents {
+                mac: [0; 6],
+                name: name.to_string(),
+                addr_type: None,
+            },
+            status: MonitorStatus::new(filter_rr),
+            replies: VecDeque::from([MonitorReply::State(MonitorState::Subscribed)]),
+            started: Some(Instant::now()),
+        }
+    }
+    fn elapsed(&self, now: Instant) -> Option<Duration> {
+        self.started
+            .map(|started| now.saturating_duration_since(started))
+    }
+    /// Goes through the same parsing and filtering as a real notification.
+    fn notify(&mut self, measurement: &HeartRateMeasurement) {
+        let reply = match self.status.update_from_slice(&encode_hrm(measurement)) {
+            Ok(()) => MonitorReply::MonitorStatus(self.status.clone()),
+            Err(e) => MonitorReply::Error(e.to_string()),
+        };
+        self.replies.push_back(reply);
+    }
+    fn command(&mut self, command: BleHrCommand) {
+        match command {
+            BleHrCommand::Disconnect => {
+                self.started = None;
+                self.replies
+                    .push_back(MonitorReply::State(MonitorState::Idle));
+            }
+            // Nothing made up counts energy
+            BleHrCommand::ResetEnergy => (),
+            BleHrCommand::SwitchTo(ident) => {
+                info!("Ignoring switch to {ident}, not using BLE");
+            }
+        }
+    }
+    fn try_recv(&mut self) -> std::result::Result<MonitorReply, TryRecvError> {
+        self.replies.pop_front().ok_or(TryRecvError::Empty)
+    }
+}
+
+pub struct SimulatedSource {
+    link: FakeLink,
+    simulator: Simulator,
+}
+
+impl SimulatedSource {
+    pub fn new(config: SimConfig, filter_rr: bool) -> Self {
+        Self {
+            link: FakeLink::new("Simulator", filter_rr),
+            simulator: Simulator::new(config),
+        }
+    }
+    fn recv_at(&mut self, now: Instant) -> std::result::Result<MonitorReply, TryRecvError> {
+        if let Some(elapsed) = self.link.elapsed(now) {
+            for measurement in self.simulator.advance(elapsed) {
+                self.link.notify(&measurement);
+            }
+        }
+        self.link.try_recv()
+    }
+}
+
+impl HeartRateSource for SimulatedSource {
+    fn kind(&self) -> HrSource {
+        HrSource::Simulator
+    }
+    fn ident(&self) -> &BleIdents {
+        &self.link.ident
+    }
+    fn try_recv(&mut self) -> std::result::Result<MonitorReply, TryRecvError> {
+        self.recv_at(Instant::now())
+    }
+    fn command(&mut self, command: BleHrCommand) {
+        self.link.command(command);
+    }
+}
+
+/// Plays back a recorded session in real time, looping at the end.
+pub struct ReplaySource {
+    link: FakeLink,
+    rows: Vec<SessionRow>,
+    next: usize,
+}
+
+impl ReplaySource {
+    pub fn open(path: &Path, filter_rr: bool) -> Result<Self, ReplayError> {
+        let session = parse_session(&fs::read_to_string(path)?)?;
+        if session.skipped > 0 {
+            warn!("Skipped {} bad rows in {path:?}", session.skipped);
+        }
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        info!("Replaying {} rows from {path:?}", session.rows.len());
+        Ok(Self::from_rows(&name, session.rows, filter_rr))
+    }
+    /// `rows` can't be empty, `parse_session` makes sure of that.
+    fn from_rows(name: &str, rows: Vec<SessionRow>, filter_rr: bool) -> Self {
+        Self {
+            link: FakeLink::new(name, filter_rr),
+            rows,
+            next: 0,
+        }
+    }
+    /// Newest recording, if there are any.
+    pub fn newest() -> std::io::Result<Option<PathBuf>> {
+        if !fs::exists(SESSION_DIR)? {
+            return Ok(None);
+        }
+        let mut names = Vec::new();
+        for entry in fs::read_dir(SESSION_DIR)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        let newest = newest_session(names.iter().map(String::as_str));
+        Ok(newest.map(|name| Path::new(SESSION_DIR).join(name)))
+    }
+    fn recv_at(&mut self, now: Instant) -> std::result::Result<MonitorReply, TryRecvError> {
+        if let Some(elapsed) = self.link.elapsed(now) {
+            // Recordings might not start at zero
+            let offset = self.rows[0].elapsed;
+            while let Some(row) = self.rows.get(self.next) {
+                if row.elapsed.saturating_sub(offset) > elapsed {
+                    break;
+                }
+                let measurement = row.measurement.clone();
+                self.link.notify(&measurement);
+                self.next += 1;
+            }
+            if self.next == self.rows.len() {
+                info!("Replay finished, starting over");
+                self.next = 0;
+                self.link.started = Some(now);
+            }
+        }
+        self.link.try_recv()
+    }
+}
+
+impl HeartRateSource for ReplaySource {
+    fn kind(&self) -> HrSource {
+        HrSource::Replay
+    }
+    fn ident(&self) -> &BleIdents {
+        &self.link.ident
+    }
+    fn try_recv(&mut self) -> std::result::Result<MonitorReply, TryRecvError> {
+        self.recv_at(Instant::now())
+    }
+    fn command(&mut self, command: BleHrCommand) {
+        self.link.command(command);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeartRateSource, ReplaySource, SimulatedSource};
+    use crate::heart_rate::{
+        monitor::{BleHrCommand, MonitorReply, MonitorState},
+        session::{parse_session, SESSION_HEADER},
+        simulate::SimConfig,
+    };
+    use std::{sync::mpsc::TryRecvError, time::Duration};
+
+    fn bpm(reply: Result<MonitorReply, TryRecvError>) -> u16 {
+        match reply {
+            Ok(MonitorReply::MonitorStatus(status)) => status.heart_rate_bpm,
+            other => panic!("Expected a status, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn simulated_replies() {
+        let mut sim = SimulatedSource::new(SimConfig::default(), false);
+        let start = sim.link.started.unwrap();
+        assert!(matches!(
+            sim.recv_at(start),
+            Ok(MonitorReply::State(MonitorState::Subscribed))
+        ));
+        assert!(matches!(sim.recv_at(start), Err(TryRecvError::Empty)));
+
+        assert!(bpm(sim.recv_at(start + Duration::from_secs(1))) > 0);
+        assert!(bpm(sim.recv_at(start + Duration::from_secs(2))) > 0);
+        assert!(matches!(sim.recv_at(start), Err(TryRecvError::Empty)));
+
+        // Nothing more once it's "disconnected"
+        sim.command(BleHrCommand::Disconnect);
+        assert!(matches!(
+            sim.recv_at(start),
+            Ok(MonitorReply::State(MonitorState::Idle))
+        ));
+        let later = start + Duration::from_secs(10);
+        assert!(matches!(sim.recv_at(later), Err(TryRecvError::Empty)));
+    }
+
+    #[test]
+    fn replay_reaches_end() {
+        let text = format!("{SESSION_HEADER}\n1000,,,60,1,,,\n2000,,,70,1,,,857\n");
+        let rows = parse_session(&text).unwrap().rows;
+        let mut replay = ReplaySource::from_rows("0001", rows, false);
+        let start = replay.link.started.unwrap();
+        assert_eq!("0001", replay.ident().name);
+        assert!(matches!(
+            replay.recv_at(start),
+            Ok(MonitorReply::State(MonitorState::Subscribed))
+        ));
+        // Starts from the first row, not the recording's zero
+        assert_eq!(60, bpm(replay.recv_at(start)));
+        assert!(matches!(replay.recv_at(start), Err(TryRecvError::Empty)));
+
+        let end = start + Duration::from_secs(1);
+        assert_eq!(70, bpm(replay.recv_at(end)));
+        // Went back around to the first row
+        assert_eq!(60, bpm(replay.recv_at(end)));
+        assert!(matches!(replay.recv_at(end), Err(TryRecvError::Empty)));
+        assert_eq!(Some(end), replay.link.started);
+    }
+}
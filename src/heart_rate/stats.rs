@@ -0,0 +1,228 @@
+// Running totals for the whole session, unlike `hr_history` which only keeps
+// what fits on the badge's plot. Kept until someone clears it from the summary
+// screen, and saved to littlefs so a reboot partway through the day doesn't lose it.
+
+use std::{fs, io, path::Path, time::Duration};
+
+use derivative::Derivative;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use strum::VariantArray;
+
+use super::{energy::EnergyCounter, zones::HrZone};
+
+/// Points in the whole-session graph, it gets coarser rather than longer.
+pub const GRAPH_POINTS: usize = 120;
+/// How much time each graph point starts out covering.
+const FIRST_BUCKET_MS: u64 = 10_000;
+/// Gaps longer than this (dropouts, time off the badge) only count this much.
+const MAX_GAP: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Derivative)]
+#[derivative(Default)]
+pub struct SessionStats {
+    /// Time spent with a heart rate coming in.
+    pub active_ms: u64,
+    pub min_bpm: Option<u16>,
+    pub max_bpm: Option<u16>,
+    bpm_sum: u64,
+    samples: u64,
+    /// Indexed the same as `HrZone::VARIANTS`.
+    pub zone_ms: [u64; HrZone::VARIANTS.len()],
+    /// Added up across strap resets.
+    pub energy: EnergyCounter,
+    /// Average BPM over each `bucket_ms` of active time, oldest first.
+    pub graph: Vec<u16>,
+    #[derivative(Default(value = "FIRST_BUCKET_MS"))]
+    pub bucket_ms: u64,
+    bucket_sum: u64,
+    bucket_samples: u64,
+    #[serde(skip)]
+    last_at: Option<Duration>,
+}
+
+impl SessionStats {
+    /// `now` is any clock that doesn't go backwards, only the gaps matter.
+    pub fn record(&mut self, now: Duration, bpm: u16, energy_kj: Option<u16>, zone: HrZone) {
+        if bpm == 0 {
+            return;
+        }
+        let gap = match self.last_at {
+            Some(last_at) => now.saturating_sub(last_at).min(MAX_GAP),
+            None => Duration::ZERO,
+        };
+        self.last_at = Some(now);
+        let gap_ms = gap.as_millis() as u64;
+
+        // Time since the last reading goes to the zone we're in now
+        let zone_index = HrZone::VARIANTS.iter().position(|z| *z == zone);
+        if let Some(zone_ms) = zone_index.and_then(|index| self.zone_ms.get_mut(index)) {
+            *zone_ms += gap_ms;
+        }
+        self.min_bpm = Some(self.min_bpm.map_or(bpm, |min| min.min(bpm)));
+        self.max_bpm = Some(self.max_bpm.map_or(bpm, |max| max.max(bpm)));
+        self.bpm_sum += bpm as u64;
+        self.samples += 1;
+
+        if let Some(energy) = energy_kj {
+            self.energy.update(energy);
+        }
+
+        // Close off the graph point once it's covered enough time
+        let bucket_started = self.active_ms / self.bucket_ms;
+        self.active_ms += gap_ms;
+        if self.active_ms / self.bucket_ms != bucket_started && self.bucket_samples > 0 {
+            self.push_graph_point();
+        }
+        self.bucket_sum += bpm as u64;
+        self.bucket_samples += 1;
+    }
+    /// Stops the time off the badge counting, and the next strap's energy
+    /// getting mixed up with this one's.
+    pub fn pause(&mut self) {
+        self.last_at = None;
+        self.energy.pause();
+    }
+    pub fn active(&self) -> Duration {
+        Duration::from_millis(self.active_ms)
+    }
+    pub fn average_bpm(&self) -> Option<u16> {
+        (self.samples > 0).then(|| (self.bpm_sum as f32 / self.samples as f32).round() as u16)
+    }
+    pub fn zone_time(&self, zone: HrZone) -> Duration {
+        let index = HrZone::VARIANTS.iter().position(|z| *z == zone);
+        Duration::from_millis(index.map_or(0, |index| self.zone_ms[index]))
+    }
+    /// The graph, with whatever's gone into the point that's still filling up.
+    pub fn graph_points(&self) -> Vec<u16> {
+        let mut points = self.graph.clone();
+        if let Some(bpm) = self.bucket_sum.checked_div(self.bucket_samples) {
+            points.push(bpm as u16);
+        }
+        points
+    }
+    fn push_graph_point(&mut self) {
+        self.graph
+            .push((self.bucket_sum / self.bucket_samples) as u16);
+        self.bucket_sum = 0;
+        self.bucket_samples = 0;
+        if self.graph.len() >= GRAPH_POINTS {
+            // Halve the resolution, pairs of points become one
+            self.graph = self
+                .graph
+                .chunks(2)
+                .map(|pair| {
+                    (pair.iter().map(|&bpm| bpm as u32).sum::<u32>() / pair.len() as u32) as u16
+                })
+                .collect();
+            self.bucket_ms *= 2;
+        }
+    }
+    /// Starts fresh if there's nothing saved, or it's from an older layout.
+    pub fn load(path: &Path) -> Self {
+        match fs::read(path) {
+            Ok(bytes) => postcard::from_bytes(&bytes).unwrap_or_else(|e| {
+                warn!("Couldn't load session stats: {e}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let bytes = postcard::to_allocvec(self).map_err(io::Error::other)?;
+        fs::write(path, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionStats, FIRST_BUCKET_MS, GRAPH_POINTS, MAX_GAP};
+    use crate::heart_rate::zones::HrZone;
+    use std::time::Duration;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn stats_totals() {
+        let mut stats = SessionStats::default();
+        assert_eq!(None, stats.average_bpm());
+        stats.record(secs(0), 60, Some(100), HrZone::Rest);
+        stats.record(secs(1), 120, Some(103), HrZone::Zone2);
+        stats.record(secs(2), 90, Some(104), HrZone::Zone1);
+        // No contact
+        stats.record(secs(3), 0, None, HrZone::Rest);
+        // Strap got reset
+        stats.record(secs(4), 90, Some(2), HrZone::Zone1);
+
+        assert_eq!(Some(60), stats.min_bpm);
+        assert_eq!(Some(120), stats.max_bpm);
+        assert_eq!(Some(90), stats.average_bpm());
+        assert_eq!(secs(4), stats.active());
+        assert_eq!(Duration::ZERO, stats.zone_time(HrZone::Rest));
+        assert_eq!(secs(1), stats.zone_time(HrZone::Zone2));
+        assert_eq!(secs(3), stats.zone_time(HrZone::Zone1));
+        assert_eq!(3 + 1 + 2, stats.energy.total_kj);
+    }
+
+    #[test]
+    fn stats_gaps_dont_count() {
+        let mut stats = SessionStats::default();
+        stats.record(secs(0), 70, Some(10), HrZone::Rest);
+        // Dropped out for a minute
+        stats.record(secs(60), 70, Some(11), HrZone::Rest);
+        assert_eq!(MAX_GAP, stats.active());
+
+        // Off the badge for an hour, with a different strap
+        stats.pause();
+        stats.record(secs(3660), 70, Some(500), HrZone::Rest);
+        stats.record(secs(3661), 70, Some(501), HrZone::Rest);
+        assert_eq!(MAX_GAP + secs(1), stats.active());
+        assert_eq!(2, stats.energy.total_kj);
+    }
+
+    #[test]
+    fn stats_graph_downsamples() {
+        let mut stats = SessionStats::default();
+        let bucket_secs = FIRST_BUCKET_MS / 1000;
+        // First bucket at 60, the second at 80
+        for second in 0..bucket_secs * 2 {
+            let bpm = if second < bucket_secs { 60 } else { 80 };
+            stats.record(secs(second), bpm, None, HrZone::Rest);
+        }
+        assert_eq!(vec![60], stats.graph);
+        assert_eq!(vec![60, 80], stats.graph_points());
+
+        let mut second = bucket_secs * 2;
+        while stats.bucket_ms == FIRST_BUCKET_MS {
+            stats.record(secs(second), 100, None, HrZone::Rest);
+            second += 1;
+        }
+        assert_eq!(GRAPH_POINTS / 2, stats.graph.len());
+        assert_eq!(70, stats.graph[0]);
+        assert_eq!(100, *stats.graph.last().unwrap());
+    }
+
+    #[test]
+    fn stats_save_round_trip() {
+        let path = std::env::temp_dir().join(format!("stats_{}", std::process::id()));
+        let mut stats = SessionStats::default();
+        stats.record(secs(0), 60, Some(1), HrZone::Rest);
+        stats.record(secs(1), 61, Some(2), HrZone::Zone1);
+        stats.save(&path).unwrap();
+        let mut loaded = SessionStats::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(stats.graph_points(), loaded.graph_points());
+        assert_eq!(stats.zone_ms, loaded.zone_ms);
+
+        // Carries on as if it never stopped, other than the gap
+        loaded.record(secs(100), 62, Some(3), HrZone::Zone1);
+        assert_eq!(secs(1), loaded.active());
+        assert_eq!(1, loaded.energy.total_kj);
+
+        std::fs::write(&path, [0xFF; 3]).unwrap();
+        assert_eq!(SessionStats::default(), SessionStats::load(&path));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
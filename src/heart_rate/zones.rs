@@ -0,0 +1,200 @@
+// Heart rate zones, either by %HRR (Karvonen) or plain %max.
+
+use derivative::Derivative;
+use embedded_graphics::pixelcolor::{Rgb565, RgbColor, WebColors};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    strum_macros::VariantArray,
+    strum_macros::Display,
+    Serialize,
+    Deserialize,
+)]
+pub enum ZoneModel {
+    /// Percentage of heart rate reserve, takes resting HR into account.
+    #[default]
+    Karvonen,
+    #[strum(to_string = "% Max")]
+    PercentMax,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Derivative)]
+#[derivative(Default)]
+pub struct ZoneSettings {
+    pub model: ZoneModel,
+    #[derivative(Default(value = "60"))]
+    pub resting_hr: u8,
+    #[derivative(Default(value = "190"))]
+    pub max_hr: u8,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, strum_macros::VariantArray,
+)]
+pub enum HrZone {
+    #[default]
+    Rest,
+    Zone1,
+    Zone2,
+    Zone3,
+    Zone4,
+    Zone5,
+}
+
+/// Lower bound (as a fraction of intensity) of each zone after `Rest`.
+const ZONE_FLOORS: [f32; 5] = [0.5, 0.6, 0.7, 0.8, 0.9];
+
+impl HrZone {
+    pub fn label(&self) -> &'static str {
+        match self {
+            HrZone::Rest => "Resting",
+            HrZone::Zone1 => "Very Light",
+            HrZone::Zone2 => "Light",
+            HrZone::Zone3 => "Moderate",
+            HrZone::Zone4 => "Hard",
+            HrZone::Zone5 => "Maximum",
+        }
+    }
+    pub fn color(&self) -> Rgb565 {
+        match self {
+            HrZone::Rest => Rgb565::CSS_HOT_PINK,
+            HrZone::Zone1 => Rgb565::CSS_LIGHT_GRAY,
+            HrZone::Zone2 => Rgb565::CSS_DODGER_BLUE,
+            HrZone::Zone3 => Rgb565::CSS_LIME_GREEN,
+            HrZone::Zone4 => Rgb565::CSS_ORANGE,
+            HrZone::Zone5 => Rgb565::RED,
+        }
+    }
+    /// Fraction of intensity this zone starts at.
+    fn floor(&self) -> f32 {
+        match self {
+            HrZone::Rest => 0.0,
+            HrZone::Zone1 => ZONE_FLOORS[0],
+            HrZone::Zone2 => ZONE_FLOORS[1],
+            HrZone::Zone3 => ZONE_FLOORS[2],
+            HrZone::Zone4 => ZONE_FLOORS[3],
+            HrZone::Zone5 => ZONE_FLOORS[4],
+        }
+    }
+}
+
+impl ZoneSettings {
+    /// How hard the heart is working, 0.0 at rest (or zero BPM) to 1.0 at max HR.
+    ///
+    /// Not clamped, so BPMs past max HR give values above 1.0.
+    pub fn intensity(&self, bpm: u16) -> f32 {
+        let bpm = bpm as f32;
+        let max = self.max_hr.max(1) as f32;
+        match self.model {
+            ZoneModel::PercentMax => bpm / max,
+            ZoneModel::Karvonen => {
+                let resting = (self.resting_hr as f32).min(max - 1.0);
+                ((bpm - resting) / (max - resting)).max(0.0)
+            }
+        }
+    }
+    pub fn zone_for(&self, bpm: u16) -> HrZone {
+        let intensity = self.intensity(bpm);
+        match ZONE_FLOORS.iter().rposition(|floor| intensity >= *floor) {
+            Some(0) => HrZone::Zone1,
+            Some(1) => HrZone::Zone2,
+            Some(2) => HrZone::Zone3,
+            Some(3) => HrZone::Zone4,
+            Some(_) => HrZone::Zone5,
+            None => HrZone::Rest,
+        }
+    }
+    /// Lowest BPM that lands in the given zone.
+    pub fn zone_floor_bpm(&self, zone: HrZone) -> u16 {
+        let max = self.max_hr as f32;
+        let floor = zone.floor();
+        let bpm = match self.model {
+            ZoneModel::PercentMax => max * floor,
+            ZoneModel::Karvonen => {
+                let resting = self.resting_hr as f32;
+                resting + (max - resting) * floor
+            }
+        };
+        // Nudged down so float error can't push an exact boundary up a beat
+        (bpm - 0.001).ceil() as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HrZone, ZoneModel, ZoneSettings};
+    use strum::VariantArray;
+
+    const KARVONEN: ZoneSettings = ZoneSettings {
+        model: ZoneModel::Karvonen,
+        resting_hr: 60,
+        max_hr: 200,
+    };
+
+    const PERCENT_MAX: ZoneSettings = ZoneSettings {
+        model: ZoneModel::PercentMax,
+        resting_hr: 60,
+        max_hr: 200,
+    };
+
+    #[test]
+    fn zones_karvonen() {
+        // Reserve of 140, so zones start at 130, 144, 158, 172, 186
+        assert_eq!(HrZone::Rest, KARVONEN.zone_for(0));
+        assert_eq!(HrZone::Rest, KARVONEN.zone_for(129));
+        assert_eq!(HrZone::Zone1, KARVONEN.zone_for(130));
+        assert_eq!(HrZone::Zone2, KARVONEN.zone_for(144));
+        assert_eq!(HrZone::Zone3, KARVONEN.zone_for(158));
+        assert_eq!(HrZone::Zone4, KARVONEN.zone_for(172));
+        assert_eq!(HrZone::Zone4, KARVONEN.zone_for(185));
+        assert_eq!(HrZone::Zone5, KARVONEN.zone_for(186));
+        assert_eq!(HrZone::Zone5, KARVONEN.zone_for(230));
+    }
+
+    #[test]
+    fn zones_percent_max() {
+        assert_eq!(HrZone::Rest, PERCENT_MAX.zone_for(99));
+        assert_eq!(HrZone::Zone1, PERCENT_MAX.zone_for(100));
+        assert_eq!(HrZone::Zone2, PERCENT_MAX.zone_for(120));
+        assert_eq!(HrZone::Zone3, PERCENT_MAX.zone_for(140));
+        assert_eq!(HrZone::Zone4, PERCENT_MAX.zone_for(160));
+        assert_eq!(HrZone::Zone5, PERCENT_MAX.zone_for(180));
+    }
+
+    #[test]
+    fn zones_floor_bpm_round_trips() {
+        for settings in [KARVONEN, PERCENT_MAX] {
+            for zone in HrZone::VARIANTS {
+                let floor = settings.zone_floor_bpm(*zone);
+                assert_eq!(*zone, settings.zone_for(floor), "{settings:?} {zone:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn zones_resting_above_max_doesnt_explode() {
+        let settings = ZoneSettings {
+            model: ZoneModel::Karvonen,
+            resting_hr: 220,
+            max_hr: 180,
+        };
+        assert!(settings.intensity(100).is_finite());
+        assert_eq!(HrZone::Rest, settings.zone_for(100));
+        assert_eq!(HrZone::Zone5, settings.zone_for(200));
+    }
+
+    #[test]
+    fn zones_distinct_colors() {
+        for (index, zone) in HrZone::VARIANTS.iter().enumerate() {
+            for other in &HrZone::VARIANTS[index + 1..] {
+                assert_ne!(zone.color(), other.color());
+            }
+        }
+    }
+}
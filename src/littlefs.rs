@@ -4,6 +4,8 @@ use crate::errors::Result;
 
 pub mod paths {
     pub const TOUCH_CAL_PATH: &str = "/littlefs/touch_cal";
+    pub const CLOCK_PATH: &str = "/littlefs/clock";
+    pub const STATS_PATH: &str = "/littlefs/stats";
 }
 
 /// Initializes a littlefs file system.
@@ -27,6 +27,9 @@ use mipidsi::{
 use xpt2046::{TouchEvent, TouchKind};
 
 mod app;
+mod clock;
+mod config_service;
+mod cts;
 mod errors;
 mod heart_rate;
 mod littlefs;
@@ -283,6 +286,7 @@ fn main() -> Result<()> {
     //     .unwrap();
 
     let _ble_device = esp32_nimble::BLEDevice::take();
+    heart_rate::ble::configure_security();
 
     let mut app = App::build(touch_rx, display, delay)?;
     if mounted_fatfs.is_some() {
@@ -1,17 +1,52 @@
 use std::{fs, io::Write};
 
 use crate::{
-    app::SlideshowLength,
+    app::{BadgeLayout, SlideshowLength, Theme},
     errors::{AppError, Result},
-    heart_rate::ble::BleIdents,
+    heart_rate::{
+        ble::{BleIdents, MonitorOptions},
+        group::TrackedMonitor,
+        source::HrSource,
+        zones::ZoneSettings,
+    },
 };
 use derivative::Derivative;
 use embassy_time::Duration;
+use log::{info, warn};
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Derivative)]
+#[derivative(Default)]
 pub struct HrSettings {
     pub saved: Option<BleIdents>,
+    /// Drop impossible RR intervals and smooth over ectopic beats.
+    #[derivative(Default(value = "true"))]
+    pub filter_rr: bool,
+    /// Re-publish the strap's data as our own Heart Rate Service.
+    pub rebroadcast: bool,
+    /// Monitors shown together on the group display.
+    pub tracked: Vec<TrackedMonitor>,
+    /// Connect to any monitor with the saved one's name if its address doesn't show up.
+    pub name_fallback: bool,
+    /// Pair with monitors before subscribing, some won't send anything otherwise.
+    pub bond: bool,
+    /// Simulator and replay are for demos, BLE is the real thing.
+    pub source: HrSource,
+    /// Log every notification to the SD card.
+    pub record: bool,
+    /// Warn below this strap battery percentage, 0 turns it off.
+    #[derivative(Default(value = "15"))]
+    pub low_battery: u8,
+}
+
+impl HrSettings {
+    pub fn monitor_options(&self) -> MonitorOptions {
+        MonitorOptions {
+            filter_rr: self.filter_rr,
+            name_fallback: self.name_fallback,
+            bond: self.bond,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Derivative)]
@@ -20,12 +55,27 @@ pub struct Settings {
     #[derivative(Default(value = "String::from(\"Goobinski\")"))]
     pub username: String,
     pub hr: HrSettings,
-    #[serde(default)]
     pub slideshow_length_sec: SlideshowLength,
+    pub zones: ZoneSettings,
+    /// Advertise our name and heart rate to other badges.
+    #[derivative(Default(value = "true"))]
+    pub visible: bool,
+    pub theme: Theme,
+    /// Let phones connect and change settings over BLE.
+    pub phone_setup: bool,
+    /// Local time's this far from UTC, phones with Local Time Information set it for us.
+    pub utc_offset_min: i16,
+    pub badge_layout: BadgeLayout,
 }
 
 const SETTINGS_PATH: &str = "/littlefs/settings";
 
+/// Postcard doesn't store field names, so saved settings start with this
+/// to tell which layout they're in. Files from before it existed don't.
+const SETTINGS_MAGIC: [u8; 3] = *b"MFS";
+/// Bump this and add what changed to `legacy` whenever `Settings` changes shape.
+const SETTINGS_VERSION: u8 = 7;
+
 impl Settings {
     pub fn littlefs_load() -> Result<Self> {
         if !fs::exists(SETTINGS_PATH)? {
@@ -34,18 +84,484 @@ impl Settings {
             Ok(default)
         } else {
             let bytes = fs::read(SETTINGS_PATH)?;
-            let res = postcard::from_bytes::<Settings>(&bytes);
-            let data = res.unwrap_or_default();
-            Ok(data)
+            match Self::from_stored(&bytes) {
+                Ok((settings, false)) => Ok(settings),
+                Ok((settings, true)) => {
+                    info!("Migrated settings to version {SETTINGS_VERSION}");
+                    settings.littlefs_save()?;
+                    Ok(settings)
+                }
+                Err(e) => {
+                    warn!("Couldn't load settings, using defaults: {e}");
+                    Ok(Self::default())
+                }
+            }
         }
     }
     pub fn littlefs_save(&self) -> Result<()> {
-        let file = fs::OpenOptions::new()
+        let mut file = fs::OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
             .open(SETTINGS_PATH)?;
+        file.write_all(&SETTINGS_MAGIC)?;
+        file.write_all(&[SETTINGS_VERSION])?;
         postcard::to_io(self, file)?;
         Ok(())
     }
+    /// Decodes saved settings, and whether they were in an older layout.
+    fn from_stored(bytes: &[u8]) -> postcard::Result<(Self, bool)> {
+        match bytes.strip_prefix(&SETTINGS_MAGIC) {
+            Some([SETTINGS_VERSION, rest @ ..]) => Ok((postcard::from_bytes(rest)?, false)),
+            Some([version, rest @ ..]) if *version < SETTINGS_VERSION => {
+                Ok((legacy::migrate_versioned(*version, rest)?, true))
+            }
+            // Newer firmware's settings, we can't know what they mean
+            Some(_) => Err(postcard::Error::DeserializeBadEncoding),
+            None => Ok((legacy::migrate(bytes)?, true)),
+        }
+    }
+}
+
+/// Older layouts, oldest last.
+mod legacy {
+    use serde_derive::Deserialize;
+
+    use super::{HrSettings, Settings};
+    use crate::{
+        app::{SlideshowLength, Theme},
+        heart_rate::{
+            ble::BleIdents, group::TrackedMonitor, source::HrSource, zones::ZoneSettings,
+        },
+    };
+
+    /// How every versioned layout starts, new fields have only ever gone on the end
+    /// of the heart rate settings or the end of everything. `Hr` and `Tail` are
+    /// whatever's been added since version 1, see `Added`.
+    #[derive(Deserialize)]
+    struct Layout<Hr, Tail> {
+        username: String,
+        hr: HrLayout<Hr>,
+        slideshow_length_sec: SlideshowLength,
+        zones: ZoneSettings,
+        visible: bool,
+        theme: Theme,
+        phone_setup: bool,
+        tail: Tail,
+    }
+
+    #[derive(Deserialize)]
+    struct HrLayout<Tail> {
+        saved: Option<BleIdents>,
+        filter_rr: bool,
+        rebroadcast: bool,
+        tracked: Vec<TrackedMonitor>,
+        name_fallback: bool,
+        tail: Tail,
+    }
+
+    /// A field some version added, and where it goes now. Postcard lays tuples
+    /// and newtypes out the same as the fields in them, so `(Older, Newer)`
+    /// reads as one after the other.
+    trait Added {
+        fn apply(self, settings: &mut Settings);
+    }
+
+    impl Added for () {
+        fn apply(self, _: &mut Settings) {}
+    }
+
+    impl<A: Added, B: Added> Added for (A, B) {
+        fn apply(self, settings: &mut Settings) {
+            self.0.apply(settings);
+            self.1.apply(settings);
+        }
+    }
+
+    /// Version 2
+    #[derive(Deserialize)]
+    struct Bond(bool);
+
+    impl Added for Bond {
+        fn apply(self, settings: &mut Settings) {
+            settings.hr.bond = self.0;
+        }
+    }
+
+    /// Version 3
+    #[derive(Deserialize)]
+    struct Source(HrSource);
+
+    impl Added for Source {
+        fn apply(self, settings: &mut Settings) {
+            settings.hr.source = self.0;
+        }
+    }
+
+    /// Version 4
+    #[derive(Deserialize)]
+    struct Record(bool);
+
+    impl Added for Record {
+        fn apply(self, settings: &mut Settings) {
+            settings.hr.record = self.0;
+        }
+    }
+
+    /// Version 5
+    #[derive(Deserialize)]
+    struct UtcOffset(i16);
+
+    impl Added for UtcOffset {
+        fn apply(self, settings: &mut Settings) {
+            settings.utc_offset_min = self.0;
+        }
+    }
+
+    /// Version 6
+    #[derive(Deserialize)]
+    struct LowBattery(u8);
+
+    impl Added for LowBattery {
+        fn apply(self, settings: &mut Settings) {
+            settings.hr.low_battery = self.0;
+        }
+    }
+
+    type HrV3 = (Bond, Source);
+    type HrV4 = (HrV3, Record);
+    type HrV6 = (HrV4, LowBattery);
+
+    impl<Hr: Added, Tail: Added> From<Layout<Hr, Tail>> for Settings {
+        fn from(old: Layout<Hr, Tail>) -> Self {
+            let mut settings = Settings {
+                username: old.username,
+                hr: HrSettings {
+                    saved: old.hr.saved,
+                    filter_rr: old.hr.filter_rr,
+                    rebroadcast: old.hr.rebroadcast,
+                    tracked: old.hr.tracked,
+                    name_fallback: old.hr.name_fallback,
+                    ..Default::default()
+                },
+                slideshow_length_sec: old.slideshow_length_sec,
+                zones: old.zones,
+                visible: old.visible,
+                theme: old.theme,
+                phone_setup: old.phone_setup,
+                ..Default::default()
+            };
+            old.hr.tail.apply(&mut settings);
+            old.tail.apply(&mut settings);
+            settings
+        }
+    }
+
+    /// When adding version 8, the current layout goes in here as 7.
+    pub fn migrate_versioned(version: u8, bytes: &[u8]) -> postcard::Result<Settings> {
+        match version {
+            6 => postcard::from_bytes::<Layout<HrV6, UtcOffset>>(bytes).map(Into::into),
+            5 => postcard::from_bytes::<Layout<HrV4, UtcOffset>>(bytes).map(Into::into),
+            4 => postcard::from_bytes::<Layout<HrV4, ()>>(bytes).map(Into::into),
+            3 => postcard::from_bytes::<Layout<HrV3, ()>>(bytes).map(Into::into),
+            2 => postcard::from_bytes::<Layout<Bond, ()>>(bytes).map(Into::into),
+            1 => postcard::from_bytes::<Layout<(), ()>>(bytes).map(Into::into),
+            _ => Err(postcard::Error::DeserializeBadEncoding),
+        }
+    }
+
+    /// Unversioned, no address type, so these'll match either.
+    #[derive(Deserialize)]
+    struct Ident {
+        mac: [u8; 6],
+        name: String,
+    }
+
+    impl From<Ident> for BleIdents {
+        fn from(ident: Ident) -> Self {
+            BleIdents {
+                mac: ident.mac,
+                name: ident.name,
+                addr_type: None,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct Tracked {
+        ident: Ident,
+        label: String,
+    }
+
+    /// Unversioned, right before the address type was saved.
+    #[derive(Deserialize)]
+    struct Unversioned {
+        username: String,
+        hr: UnversionedHr,
+        slideshow_length_sec: SlideshowLength,
+        zones: ZoneSettings,
+        visible: bool,
+        theme: Theme,
+        phone_setup: bool,
+    }
+
+    #[derive(Deserialize)]
+    struct UnversionedHr {
+        saved: Option<Ident>,
+        filter_rr: bool,
+        rebroadcast: bool,
+        tracked: Vec<Tracked>,
+    }
+
+    /// Unversioned, the very first layout, just a name and a monitor.
+    #[derive(Deserialize)]
+    struct Original {
+        username: String,
+        hr: OriginalHr,
+        slideshow_length_sec: SlideshowLength,
+    }
+
+    #[derive(Deserialize)]
+    struct OriginalHr {
+        saved: Option<Ident>,
+    }
+
+    /// Only takes an exact fit, so a layout in between isn't misread as a shorter one.
+    fn exactly<'a, T: serde::Deserialize<'a>>(bytes: &'a [u8]) -> postcard::Result<T> {
+        match postcard::take_from_bytes(bytes)? {
+            (value, []) => Ok(value),
+            _ => Err(postcard::Error::DeserializeBadEncoding),
+        }
+    }
+
+    /// From before settings were versioned.
+    pub fn migrate(bytes: &[u8]) -> postcard::Result<Settings> {
+        if let Ok(old) = exactly::<Unversioned>(bytes) {
+            return Ok(Settings {
+                username: old.username,
+                hr: HrSettings {
+                    saved: old.hr.saved.map(Into::into),
+                    filter_rr: old.hr.filter_rr,
+                    rebroadcast: old.hr.rebroadcast,
+                    tracked: old
+                        .hr
+                        .tracked
+                        .into_iter()
+                        .map(|tracked| TrackedMonitor {
+                            ident: tracked.ident.into(),
+                            label: tracked.label,
+                        })
+                        .collect(),
+                    ..Default::default()
+                },
+                slideshow_length_sec: old.slideshow_length_sec,
+                zones: old.zones,
+                visible: old.visible,
+                theme: old.theme,
+                phone_setup: old.phone_setup,
+                ..Default::default()
+            });
+        }
+        let old = exactly::<Original>(bytes)?;
+        Ok(Settings {
+            username: old.username,
+            hr: HrSettings {
+                saved: old.hr.saved.map(Into::into),
+                ..Default::default()
+            },
+            slideshow_length_sec: old.slideshow_length_sec,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Settings, SETTINGS_MAGIC, SETTINGS_VERSION};
+    use crate::{
+        app::{BadgeLayout, SlideshowLength, Theme},
+        heart_rate::ble::{BleAddrType, BleIdents},
+    };
+
+    fn stored(settings: &Settings) -> Vec<u8> {
+        let mut bytes = SETTINGS_MAGIC.to_vec();
+        bytes.push(SETTINGS_VERSION);
+        bytes.extend(postcard::to_allocvec(settings).unwrap());
+        bytes
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut settings = Settings {
+            username: String::from("Bingus"),
+            theme: Theme::Pink,
+            ..Default::default()
+        };
+        settings.hr.saved = Some(BleIdents {
+            mac: [1, 2, 3, 4, 5, 6],
+            name: String::from("Polar H10"),
+            addr_type: Some(BleAddrType::Random),
+        });
+        let (loaded, migrated) = Settings::from_stored(&stored(&settings)).unwrap();
+        assert!(!migrated);
+        assert_eq!("Bingus", loaded.username);
+        assert_eq!(Theme::Pink, loaded.theme);
+        assert_eq!(settings.hr.saved, loaded.hr.saved);
+    }
+
+    #[test]
+    fn settings_migrates_original() {
+        #[rustfmt::skip]
+        let original = [
+            6, b'B', b'i', b'n', b'g', b'u', b's',
+            // Saved monitor
+            1, 0xAA, 0xBB, 0xCC, 1, 2, 3,
+            3, b'H', b'1', b'0',
+            // Slideshow, 10s
+            2,
+        ];
+        let (loaded, migrated) = Settings::from_stored(&original).unwrap();
+        assert!(migrated);
+        assert_eq!("Bingus", loaded.username);
+        assert_eq!(SlideshowLength::TenSec, loaded.slideshow_length_sec);
+        let saved = loaded.hr.saved.unwrap();
+        assert_eq!([0xAA, 0xBB, 0xCC, 1, 2, 3], saved.mac);
+        assert_eq!("H10", saved.name);
+        assert_eq!(None, saved.addr_type);
+        // Anything it didn't have gets the defaults
+        assert!(loaded.visible);
+        assert!(loaded.hr.filter_rr);
+        assert!(!loaded.hr.name_fallback);
+
+        // Leftover bytes mean it's some other layout, better to start fresh than guess
+        let mut longer = original.to_vec();
+        longer.push(0);
+        assert!(Settings::from_stored(&longer).is_err());
+    }
+
+    /// Writes `settings` the way firmware saving `version` would have, leaving
+    /// out whatever that version didn't have yet. Postcard is just each field
+    /// one after another, so this is the current layout with gaps.
+    fn stored_as(version: u8, settings: &Settings) -> Vec<u8> {
+        fn field(bytes: &mut Vec<u8>, value: &impl serde::Serialize) {
+            bytes.extend(postcard::to_allocvec(value).unwrap());
+        }
+        let hr = &settings.hr;
+        let mut bytes = SETTINGS_MAGIC.to_vec();
+        bytes.push(version);
+        field(&mut bytes, &settings.username);
+        field(&mut bytes, &hr.saved);
+        field(&mut bytes, &hr.filter_rr);
+        field(&mut bytes, &hr.rebroadcast);
+        field(&mut bytes, &hr.tracked);
+        field(&mut bytes, &hr.name_fallback);
+        if version >= 2 {
+            field(&mut bytes, &hr.bond);
+        }
+        if version >= 3 {
+            field(&mut bytes, &hr.source);
+        }
+        if version >= 4 {
+            field(&mut bytes, &hr.record);
+        }
+        if version >= 6 {
+            field(&mut bytes, &hr.low_battery);
+        }
+        field(&mut bytes, &settings.slideshow_length_sec);
+        field(&mut bytes, &settings.zones);
+        field(&mut bytes, &settings.visible);
+        field(&mut bytes, &settings.theme);
+        field(&mut bytes, &settings.phone_setup);
+        if version >= 5 {
+            field(&mut bytes, &settings.utc_offset_min);
+        }
+        if version >= 7 {
+            field(&mut bytes, &settings.badge_layout);
+        }
+        bytes
+    }
+
+    #[test]
+    fn settings_migrates_versions() {
+        use crate::heart_rate::{group::TrackedMonitor, source::HrSource};
+
+        // Nothing left at its default, so a dropped or shifted field shows up
+        let saved = BleIdents {
+            mac: [1, 2, 3, 4, 5, 6],
+            name: String::from("Polar H10"),
+            addr_type: Some(BleAddrType::Public),
+        };
+        let mut settings = Settings {
+            username: String::from("Bingus"),
+            slideshow_length_sec: SlideshowLength::OneMin,
+            visible: false,
+            theme: Theme::Green,
+            phone_setup: true,
+            utc_offset_min: -300,
+            badge_layout: BadgeLayout::Monitor,
+            ..Default::default()
+        };
+        settings.hr.saved = Some(saved.clone());
+        settings.hr.filter_rr = false;
+        settings.hr.rebroadcast = true;
+        settings.hr.tracked = vec![TrackedMonitor {
+            ident: saved.clone(),
+            label: String::from("Me"),
+        }];
+        settings.hr.name_fallback = true;
+        settings.hr.bond = true;
+        settings.hr.source = HrSource::Simulator;
+        settings.hr.record = true;
+        settings.hr.low_battery = 25;
+
+        // What each version's added fields come back as, defaults for the ones it didn't have
+        #[rustfmt::skip]
+        let expected = [
+            // version, bond, source, record, low_battery, utc_offset_min, badge_layout
+            (1, false, HrSource::Ble, false, 15, 0, BadgeLayout::Classic),
+            (2, true, HrSource::Ble, false, 15, 0, BadgeLayout::Classic),
+            (3, true, HrSource::Simulator, false, 15, 0, BadgeLayout::Classic),
+            (4, true, HrSource::Simulator, true, 15, 0, BadgeLayout::Classic),
+            (5, true, HrSource::Simulator, true, 15, -300, BadgeLayout::Classic),
+            (6, true, HrSource::Simulator, true, 25, -300, BadgeLayout::Classic),
+            (7, true, HrSource::Simulator, true, 25, -300, BadgeLayout::Monitor),
+        ];
+        assert_eq!(SETTINGS_VERSION, expected.last().unwrap().0);
+        assert_eq!(stored(&settings), stored_as(SETTINGS_VERSION, &settings));
+
+        for (version, bond, source, record, low_battery, utc_offset_min, badge_layout) in expected {
+            let (loaded, migrated) = Settings::from_stored(&stored_as(version, &settings))
+                .unwrap_or_else(|e| panic!("version {version}: {e}"));
+            assert_eq!(version < SETTINGS_VERSION, migrated, "version {version}");
+            // Version 1 already had these
+            assert_eq!("Bingus", loaded.username, "version {version}");
+            assert_eq!(Some(&saved), loaded.hr.saved.as_ref(), "version {version}");
+            assert!(!loaded.hr.filter_rr, "version {version}");
+            assert!(loaded.hr.rebroadcast, "version {version}");
+            assert_eq!(settings.hr.tracked, loaded.hr.tracked, "version {version}");
+            assert!(loaded.hr.name_fallback, "version {version}");
+            assert_eq!(
+                SlideshowLength::OneMin,
+                loaded.slideshow_length_sec,
+                "version {version}"
+            );
+            assert!(!loaded.visible, "version {version}");
+            assert_eq!(Theme::Green, loaded.theme, "version {version}");
+            assert!(loaded.phone_setup, "version {version}");
+
+            assert_eq!(bond, loaded.hr.bond, "version {version}");
+            assert_eq!(source, loaded.hr.source, "version {version}");
+            assert_eq!(record, loaded.hr.record, "version {version}");
+            assert_eq!(low_battery, loaded.hr.low_battery, "version {version}");
+            assert_eq!(utc_offset_min, loaded.utc_offset_min, "version {version}");
+            assert_eq!(badge_layout, loaded.badge_layout, "version {version}");
+        }
+    }
+
+    #[test]
+    fn settings_rejects_newer_version() {
+        let mut bytes = stored(&Settings::default());
+        bytes[SETTINGS_MAGIC.len()] = SETTINGS_VERSION + 1;
+        assert!(Settings::from_stored(&bytes).is_err());
+    }
 }